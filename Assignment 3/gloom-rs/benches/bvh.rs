@@ -0,0 +1,112 @@
+extern crate nalgebra_glm as glm;
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use rand::{Rng, SeedableRng};
+use rand::rngs::StdRng;
+
+use gloom_rs::bvh::{Aabb, Bvh, Ray, SplitMethod};
+use gloom_rs::mesh::{Mesh, Terrain};
+
+// BVH construction and queries on the lunar terrain, against going through every triangle.
+// Run with `cargo bench --bench bvh`.
+
+const RAYS: usize = 100;
+const BOXES: usize = 100;
+const SPHERES: usize = 100;
+
+const TERRAIN_PATH: &str = "resources/lunarsurface.obj";
+const GENERATED_CELLS: usize = 256; // Per side of the stand-in grid, about as many triangles as the real terrain
+
+// The lunar terrain if it is there, or else a bumpy grid standing in for it
+fn terrain() -> Mesh {
+    if std::path::Path::new(TERRAIN_PATH).exists() {
+        return Terrain::load(TERRAIN_PATH);
+    }
+    println!("{} not found, using a generated terrain", TERRAIN_PATH);
+    let n = GENERATED_CELLS + 1;
+    let mut vertices = Vec::with_capacity(n * n * 3);
+    for z in 0..n {
+        for x in 0..n {
+            let (x, z) = (x as f32, z as f32);
+            vertices.extend_from_slice(&[x, 4.0 * (x * 0.11).sin() * (z * 0.07).cos() + (x * z * 0.013).sin(), z]);
+        }
+    }
+    let mut indices = Vec::with_capacity(GENERATED_CELLS * GENERATED_CELLS * 6);
+    for z in 0..GENERATED_CELLS {
+        for x in 0..GENERATED_CELLS {
+            let i = (z * n + x) as u32;
+            let n = n as u32;
+            indices.extend_from_slice(&[i, i + n, i + 1, i + 1, i + n, i + n + 1]);
+        }
+    }
+    Mesh {
+        colors: vec![1.0; vertices.len() / 3 * 4],
+        normals: vec![],
+        index_count: indices.len() as i32,
+        vertices,
+        indices,
+    }
+}
+
+fn construction(c: &mut Criterion) {
+    let terrain = terrain();
+    let mut group = c.benchmark_group("build");
+    group.sample_size(10);
+    group.bench_function("median", |b| b.iter(|| Bvh::new(black_box(&terrain), SplitMethod::Median)));
+    group.bench_function("sah", |b| b.iter(|| Bvh::new(black_box(&terrain), SplitMethod::Sah)));
+    group.finish();
+}
+
+fn queries(c: &mut Criterion) {
+    let terrain = terrain();
+    let median = Bvh::new(&terrain, SplitMethod::Median);
+    let sah = Bvh::new(&terrain, SplitMethod::Sah);
+
+    let bounds = sah.bounds();
+    let extent = bounds.max - bounds.min;
+    let mut rng = StdRng::seed_from_u64(27);
+    let mut random_point = || glm::vec3(
+        rng.gen_range(bounds.min.x..=bounds.max.x),
+        rng.gen_range(bounds.min.y..=bounds.max.y),
+        rng.gen_range(bounds.min.z..=bounds.max.z),
+    );
+
+    // Rays shot down at the terrain from above, with a bit of slant
+    let rays: Vec<Ray> = (0..RAYS).map(|_| {
+        let target = random_point();
+        let origin = target + glm::vec3(0.1 * extent.x, extent.y + 10.0, 0.05 * extent.z);
+        Ray::new(origin, glm::normalize(&(target - origin)))
+    }).collect();
+    let boxes: Vec<Aabb> = (0..BOXES).map(|_| {
+        let p = random_point();
+        let half = extent * 0.01;
+        Aabb::new(p - half, p + half)
+    }).collect();
+    let spheres: Vec<(glm::Vec3, f32)> = (0..SPHERES)
+        .map(|_| (random_point(), 0.01 * glm::length(&extent)))
+        .collect();
+
+    let mut group = c.benchmark_group("rays");
+    group.sample_size(10);
+    group.bench_function("linear", |b| b.iter(|| rays.iter().filter_map(|r| sah.intersect_ray_linear(r, f32::INFINITY)).count()));
+    group.bench_function("median", |b| b.iter(|| rays.iter().filter_map(|r| median.intersect_ray(r, f32::INFINITY)).count()));
+    group.bench_function("sah", |b| b.iter(|| rays.iter().filter_map(|r| sah.intersect_ray(r, f32::INFINITY)).count()));
+    group.finish();
+
+    let mut group = c.benchmark_group("boxes");
+    group.sample_size(10);
+    group.bench_function("linear", |b| b.iter(|| boxes.iter().map(|q| sah.query_aabb_linear(q).len()).sum::<usize>()));
+    group.bench_function("median", |b| b.iter(|| boxes.iter().map(|q| median.query_aabb(q).len()).sum::<usize>()));
+    group.bench_function("sah", |b| b.iter(|| boxes.iter().map(|q| sah.query_aabb(q).len()).sum::<usize>()));
+    group.finish();
+
+    let mut group = c.benchmark_group("spheres");
+    group.sample_size(10);
+    group.bench_function("linear", |b| b.iter(|| spheres.iter().map(|(p, r)| sah.query_sphere_linear(p, *r).len()).sum::<usize>()));
+    group.bench_function("median", |b| b.iter(|| spheres.iter().map(|(p, r)| median.query_sphere(p, *r).len()).sum::<usize>()));
+    group.bench_function("sah", |b| b.iter(|| spheres.iter().map(|(p, r)| sah.query_sphere(p, *r).len()).sum::<usize>()));
+    group.finish();
+}
+
+criterion_group!(benches, construction, queries);
+criterion_main!(benches);
@@ -0,0 +1,8 @@
+#version 430 core
+in vec4 vertexColor;
+out vec4 FragColor;
+
+void main()
+{
+    FragColor = vertexColor;
+}
@@ -0,0 +1,13 @@
+#version 430 core
+layout (location = 0) in vec3 aPos;
+layout (location = 1) in vec4 aColor;
+
+out vec4 vertexColor;
+
+#include "frame.glsl"
+
+void main()
+{
+    gl_Position = viewProjection * vec4(aPos, 1.0);
+    vertexColor = aColor;
+}
@@ -0,0 +1,30 @@
+// Per frame data, shared by all programs. Has to match FrameUniforms in uniform_buffer.rs
+#pragma once
+
+#define MAX_LIGHTS 16
+
+// Kinds of light, stored in Light.position.w
+#define DIRECTIONAL_LIGHT 0
+#define POINT_LIGHT       1
+#define SPOT_LIGHT        2
+
+struct Light {
+    vec4 position;  // xyz: position, w: kind of light
+    vec4 direction; // xyz: direction the light travels in, w: range
+    vec4 color;     // rgb: color, a: intensity
+    vec4 cone;      // x: cosine of the outer angle, y: of the inner angle
+};
+
+layout (std140, binding = 0) uniform FrameData {
+    mat4 view;
+    mat4 projection;
+    mat4 viewProjection;
+    vec4 cameraPosition;
+    float time;
+    int lightCount;
+    vec4 ambientLight;  // rgb: color
+    mat4 shadowViewProjection;
+    int shadowLight;    // Index of the light casting shadows, -1 if none
+    float shadowNormalOffset;
+    Light lights[MAX_LIGHTS];
+};
@@ -0,0 +1,77 @@
+// Lighting shared by the fragment shaders
+#pragma once
+
+#include "frame.glsl"
+
+// Has to match Material::apply in material.rs
+struct Material {
+    vec3 ambient;    // Fraction of the ambient light reflected
+    vec3 diffuse;
+    vec3 specular;
+    float shininess; // Larger values give smaller, sharper highlights
+};
+
+uniform Material uMaterial;
+
+// Depth seen from the shadow casting light, see shadow.rs
+layout (binding = 1) uniform sampler2DShadow uShadowMap;
+
+// Inverse square falloff, brought smoothly down to zero at the range
+float distanceAttenuation(float distance, float range)
+{
+    float window = clamp(1.0 - pow(distance / range, 4.0), 0.0, 1.0);
+    return window * window / max(distance * distance, 0.01);
+}
+
+// How much of the shadow casting light reaches the point, from 0 to 1.
+// Averages 3x3 filtered lookups (PCF) to soften the edges.
+float shadowFactor(vec3 position, vec3 normal)
+{
+    vec4 clip = shadowViewProjection * vec4(position + normal * shadowNormalOffset, 1.0);
+    vec3 coords = clip.xyz / clip.w * 0.5 + 0.5;
+    if (coords.z > 1.0) {
+        return 1.0; // Beyond the far plane of the shadow map
+    }
+    vec2 texel = 1.0 / vec2(textureSize(uShadowMap, 0));
+    float lit = 0.0;
+    for (int x = -1; x <= 1; x++) {
+        for (int y = -1; y <= 1; y++) {
+            lit += texture(uShadowMap, vec3(coords.xy + vec2(x, y) * texel, coords.z));
+        }
+    }
+    return lit / 9.0;
+}
+
+// Blinn-Phong lighting of a surface point by all lights in the frame.
+// `baseColor` tints the ambient and diffuse terms, e.g. with a vertex color.
+vec3 blinnPhong(vec3 baseColor, vec3 position, vec3 normal)
+{
+    vec3 toCamera = normalize(cameraPosition.xyz - position);
+    vec3 color = uMaterial.ambient * ambientLight.rgb * baseColor;
+    for (int i = 0; i < lightCount; i++) {
+        int kind = int(lights[i].position.w);
+        vec3 toLight = -normalize(lights[i].direction.xyz);
+        float attenuation = 1.0;
+        if (kind != DIRECTIONAL_LIGHT) {
+            vec3 offset = lights[i].position.xyz - position;
+            float distance = length(offset);
+            attenuation = distanceAttenuation(distance, lights[i].direction.w);
+            if (kind == SPOT_LIGHT) {
+                float cosAngle = dot(toLight, offset / distance); // Both point back at the light
+                attenuation *= smoothstep(lights[i].cone.x, lights[i].cone.y, cosAngle);
+            }
+            toLight = offset / distance;
+        }
+        if (i == shadowLight) {
+            attenuation *= shadowFactor(position, normal);
+        }
+        vec3 radiance = lights[i].color.rgb * lights[i].color.a * attenuation;
+
+        float diffuse = max(dot(normal, toLight), 0.0);
+        vec3 halfway = normalize(toLight + toCamera);
+        float specular = diffuse > 0.0 ? pow(max(dot(normal, halfway), 0.0), uMaterial.shininess) : 0.0;
+
+        color += radiance * (uMaterial.diffuse * baseColor * diffuse + uMaterial.specular * specular);
+    }
+    return color;
+}
@@ -0,0 +1,13 @@
+#version 430 core
+// Adds the blurred bright parts back onto the scene
+in vec2 uv;
+out vec4 FragColor;
+uniform sampler2D uInput;
+uniform sampler2D uBloom;
+uniform float uIntensity;
+
+void main()
+{
+    vec3 color = texture(uInput, uv).rgb + texture(uBloom, uv).rgb * uIntensity;
+    FragColor = vec4(color, 1.0);
+}
@@ -0,0 +1,17 @@
+#version 430 core
+// The parts of the HDR scene bright enough to bloom, drawn at a lower resolution
+in vec2 uv;
+out vec4 FragColor;
+uniform sampler2D uInput;
+uniform float uThreshold;  // Brightness where blooming starts
+uniform float uKnee;       // Width of the soft transition around the threshold
+
+void main()
+{
+    vec3 color = texture(uInput, uv).rgb;
+    float brightness = max(color.r, max(color.g, color.b));
+    float soft = clamp(brightness - uThreshold + uKnee, 0.0, 2.0 * uKnee);
+    soft = soft * soft / (4.0 * uKnee + 1e-4);
+    float contribution = max(soft, brightness - uThreshold) / max(brightness, 1e-4);
+    FragColor = vec4(color * contribution, 1.0);
+}
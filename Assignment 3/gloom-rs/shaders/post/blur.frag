@@ -0,0 +1,20 @@
+#version 430 core
+// One direction of a separable 9 tap Gaussian blur, using linear filtering to read two texels per tap
+in vec2 uv;
+out vec4 FragColor;
+uniform sampler2D uInput;
+uniform vec2 uDirection;   // (1, 0) or (0, 1)
+
+const float offsets[3] = float[](0.0, 1.3846153846, 3.2307692308);
+const float weights[3] = float[](0.2270270270, 0.3162162162, 0.0702702703);
+
+void main()
+{
+    vec2 step = uDirection / vec2(textureSize(uInput, 0));
+    vec3 color = texture(uInput, uv).rgb * weights[0];
+    for (int i = 1; i < 3; i++) {
+        color += texture(uInput, uv + step * offsets[i]).rgb * weights[i];
+        color += texture(uInput, uv - step * offsets[i]).rgb * weights[i];
+    }
+    FragColor = vec4(color, 1.0);
+}
@@ -0,0 +1,46 @@
+#version 430 core
+// Fast approximate antialiasing: blurs along edges found from the contrast in luma.
+// Works on the final, gamma corrected colors.
+in vec2 uv;
+out vec4 FragColor;
+uniform sampler2D uInput;
+uniform float uSpanMax;     // Longest blur along an edge, in pixels
+uniform float uReduceMin;   // Keep dark areas from being blurred too much
+uniform float uReduceMul;
+
+float luma(vec3 color)
+{
+    return dot(color, vec3(0.299, 0.587, 0.114));
+}
+
+void main()
+{
+    vec2 texel = 1.0 / vec2(textureSize(uInput, 0));
+    float lumaNW = luma(texture(uInput, uv + vec2(-1.0, -1.0) * texel).rgb);
+    float lumaNE = luma(texture(uInput, uv + vec2( 1.0, -1.0) * texel).rgb);
+    float lumaSW = luma(texture(uInput, uv + vec2(-1.0,  1.0) * texel).rgb);
+    float lumaSE = luma(texture(uInput, uv + vec2( 1.0,  1.0) * texel).rgb);
+    vec3 center = texture(uInput, uv).rgb;
+    float lumaM = luma(center);
+    float lumaMin = min(lumaM, min(min(lumaNW, lumaNE), min(lumaSW, lumaSE)));
+    float lumaMax = max(lumaM, max(max(lumaNW, lumaNE), max(lumaSW, lumaSE)));
+
+    // Perpendicular to the luma gradient, which is along the edge
+    vec2 direction = vec2(
+        -((lumaNW + lumaNE) - (lumaSW + lumaSE)),
+         ((lumaNW + lumaSW) - (lumaNE + lumaSE)));
+    float reduce = max((lumaNW + lumaNE + lumaSW + lumaSE) * 0.25 * uReduceMul, uReduceMin);
+    float scale = 1.0 / (min(abs(direction.x), abs(direction.y)) + reduce);
+    direction = clamp(direction * scale, vec2(-uSpanMax), vec2(uSpanMax)) * texel;
+
+    vec3 near = 0.5 * (
+        texture(uInput, uv + direction * (1.0 / 3.0 - 0.5)).rgb +
+        texture(uInput, uv + direction * (2.0 / 3.0 - 0.5)).rgb);
+    vec3 far = near * 0.5 + 0.25 * (
+        texture(uInput, uv - direction * 0.5).rgb +
+        texture(uInput, uv + direction * 0.5).rgb);
+
+    // The wider blur may pick up colors from beyond the edge, fall back to the narrower one then
+    float lumaFar = luma(far);
+    FragColor = vec4((lumaFar < lumaMin || lumaFar > lumaMax) ? near : far, 1.0);
+}
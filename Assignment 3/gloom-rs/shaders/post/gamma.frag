@@ -0,0 +1,12 @@
+#version 430 core
+// From linear colors to what the display expects
+in vec2 uv;
+out vec4 FragColor;
+uniform sampler2D uInput;
+uniform float uGamma;
+
+void main()
+{
+    vec3 color = max(texture(uInput, uv).rgb, vec3(0.0));
+    FragColor = vec4(pow(color, vec3(1.0 / uGamma)), 1.0);
+}
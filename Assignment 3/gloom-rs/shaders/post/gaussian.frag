@@ -0,0 +1,28 @@
+#version 430 core
+// One direction of a separable Gaussian blur of the luminance, reading whole texels so that it
+// matches image_filters::gaussian_blur exactly. Edges are extended outwards.
+in vec2 uv;
+out vec4 FragColor;
+uniform sampler2D uInput;
+uniform float uSigma;
+uniform vec2 uDirection;    // (1, 0) or (0, 1)
+
+#include "luminance.glsl"
+
+void main()
+{
+    ivec2 size = textureSize(uInput, 0);
+    ivec2 center = ivec2(gl_FragCoord.xy);
+    int radius = int(ceil(3.0 * uSigma));
+    ivec2 direction = ivec2(uDirection);
+
+    float sum = 0.0;
+    float weights = 0.0;
+    for (int i = -radius; i <= radius; i++) {
+        float weight = exp(-float(i * i) / (2.0 * uSigma * uSigma));
+        ivec2 texel = clamp(center + direction * i, ivec2(0), size - 1);
+        sum += luminance(texelFetch(uInput, texel, 0).rgb) * weight;
+        weights += weight;
+    }
+    FragColor = vec4(vec3(sum / weights), 1.0);
+}
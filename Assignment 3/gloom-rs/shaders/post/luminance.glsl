@@ -0,0 +1,7 @@
+// Has to match image_filters::luminance
+#pragma once
+
+float luminance(vec3 color)
+{
+    return dot(color, vec3(0.299, 0.587, 0.114));
+}
@@ -0,0 +1,14 @@
+// Shared by the Otsu threshold shaders, see image_filters.rs
+#pragma once
+
+#define OTSU_BINS 256
+
+layout (std430, binding = 0) buffer OtsuData {
+    uint bins[OTSU_BINS];   // Luminance histogram, bin i holding [i, i + 1) / OTSU_BINS
+    float threshold;        // Pixels at least this bright are foreground
+};
+
+int otsuBin(float luminance)
+{
+    return clamp(int(luminance * float(OTSU_BINS)), 0, OTSU_BINS - 1);
+}
@@ -0,0 +1,14 @@
+#version 430 core
+// White where the luminance reaches the threshold found by otsu_threshold.comp, black elsewhere
+in vec2 uv;
+out vec4 FragColor;
+uniform sampler2D uInput;
+
+#include "luminance.glsl"
+#include "otsu.glsl"
+
+void main()
+{
+    float value = luminance(texelFetch(uInput, ivec2(gl_FragCoord.xy), 0).rgb);
+    FragColor = vec4(vec3(value >= threshold ? 1.0 : 0.0), 1.0);
+}
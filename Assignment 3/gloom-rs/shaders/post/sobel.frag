@@ -0,0 +1,27 @@
+#version 430 core
+// Gradient magnitude of the luminance, matching image_filters::sobel. Edges are extended outwards.
+in vec2 uv;
+out vec4 FragColor;
+uniform sampler2D uInput;
+
+#include "luminance.glsl"
+
+float sampleAt(ivec2 texel, ivec2 size)
+{
+    return luminance(texelFetch(uInput, clamp(texel, ivec2(0), size - 1), 0).rgb);
+}
+
+void main()
+{
+    ivec2 size = textureSize(uInput, 0);
+    ivec2 p = ivec2(gl_FragCoord.xy);
+    float s[9];
+    for (int y = -1; y <= 1; y++) {
+        for (int x = -1; x <= 1; x++) {
+            s[(y + 1) * 3 + (x + 1)] = sampleAt(p + ivec2(x, y), size);
+        }
+    }
+    float gx = (s[2] + 2.0 * s[5] + s[8]) - (s[0] + 2.0 * s[3] + s[6]);
+    float gy = (s[6] + 2.0 * s[7] + s[8]) - (s[0] + 2.0 * s[1] + s[2]);
+    FragColor = vec4(vec3(sqrt(gx * gx + gy * gy)), 1.0);
+}
@@ -0,0 +1,18 @@
+#version 430 core
+// Maps the HDR scene into [0, 1], keeping the colors of bright areas from clipping
+in vec2 uv;
+out vec4 FragColor;
+uniform sampler2D uInput;
+uniform float uExposure;
+
+// Narkowicz' fit of the ACES filmic curve
+vec3 aces(vec3 x)
+{
+    return clamp((x * (2.51 * x + 0.03)) / (x * (2.43 * x + 0.59) + 0.14), 0.0, 1.0);
+}
+
+void main()
+{
+    vec3 color = texture(uInput, uv).rgb * uExposure;
+    FragColor = vec4(aces(color), 1.0);
+}
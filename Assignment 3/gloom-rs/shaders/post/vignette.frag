@@ -0,0 +1,16 @@
+#version 430 core
+// Darkens the corners of the frame
+in vec2 uv;
+out vec4 FragColor;
+uniform sampler2D uInput;
+uniform float uStrength;  // How dark the corners get, 0 to 1
+uniform float uRadius;    // Distance from the center where the darkening starts, 1 being a corner
+uniform float uSoftness;  // How gradually it darkens after that
+
+void main()
+{
+    vec3 color = texture(uInput, uv).rgb;
+    float distance = length(uv - 0.5) / length(vec2(0.5));
+    float darkening = smoothstep(uRadius, uRadius + uSoftness, distance) * uStrength;
+    FragColor = vec4(color * (1.0 - darkening), 1.0);
+}
@@ -0,0 +1,10 @@
+#version 430 core
+layout (location = 0) in vec3 aPos;
+layout (location = 3) in mat4 aModelMatrix; // Per instance, as in simple.vert
+
+#include "frame.glsl"
+
+void main()
+{
+    gl_Position = shadowViewProjection * aModelMatrix * vec4(aPos, 1.0);
+}
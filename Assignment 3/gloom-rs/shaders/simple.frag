@@ -1,18 +1,31 @@
 #version 430 core
 in vec4 vertexColor;
 in vec3 fragNormal;   // Normal from vertex shader
+in vec3 fragPosition; // In world space
 out vec4 FragColor;
 uniform float uAlpha;  
 
+// Variants, see ShaderFeatures:
+//   LIT            shaded by the frame's lights
+//   VERTEX_COLORS  colored by the mesh instead of white
+
+#include "frame.glsl"
+#include "lighting.glsl"
+
 
 void main()
 {
-   vec3 lightDirection = normalize(vec3(0.8, -0.5, 0.6));
+#ifdef VERTEX_COLORS
+    vec3 baseColor = vertexColor.rgb;
+#else
+    vec3 baseColor = vec3(1.0);
+#endif
 
-    float lightIntensity = max(0.0,dot(-lightDirection,fragNormal));
-    vec3 litColor = vec3(1,1,1)* lightIntensity;
+#ifdef LIT
+    vec3 litColor = blinnPhong(baseColor, fragPosition, normalize(fragNormal));
+#else
+    vec3 litColor = baseColor * uMaterial.diffuse;
+#endif
     
     FragColor = vec4(litColor, uAlpha);
 }
-
-
@@ -2,19 +2,23 @@
 layout (location = 0) in vec3 aPos;   
 layout (location = 1) in vec4 aColor; 
 layout (location = 2) in vec3 aNormal; 
+layout (location = 3) in mat4 aModelMatrix;  // Per instance, takes up locations 3 to 6
+layout (location = 7) in mat3 aNormalMatrix; // Per instance, locations 7 to 9
 
 out vec4 vertexColor; 
 out vec3 fragNormal;  
+out vec3 fragPosition; // In world space
 
-uniform mat4 uMVPMatrix;    // Model-View-Projection matrix for vertex positions
-uniform mat4 uModelMatrix;  // Model matrix only for normal transformations
+#include "frame.glsl"
 
 void main()
 {
-    gl_Position = uMVPMatrix * vec4(aPos, 1.0);
+    vec4 worldPosition = aModelMatrix * vec4(aPos, 1.0);
+    gl_Position = viewProjection * worldPosition;
+    fragPosition = worldPosition.xyz;
     vertexColor = aColor;
     
-    
-    mat3 normalMatrix = mat3(uModelMatrix);  // Extract 3x3 rotation/scale matrix
-    fragNormal = normalize(normalMatrix * aNormal);
+    // Keeps normals perpendicular to the surface under non-uniform scaling as well.
+    // Inverted once per instance on the CPU, rather than once per vertex here.
+    fragNormal = normalize(aNormalMatrix * aNormal);
 }
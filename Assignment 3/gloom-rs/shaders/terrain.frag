@@ -0,0 +1,12 @@
+#version 430 core
+in vec3 fragNormal;
+in vec3 fragPosition; // In world space
+out vec4 FragColor;
+uniform float uAlpha;
+
+#include "lighting.glsl"
+
+void main()
+{
+    FragColor = vec4(blinnPhong(vec3(1.0), fragPosition, normalize(fragNormal)), uAlpha);
+}
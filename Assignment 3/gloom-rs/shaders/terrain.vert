@@ -0,0 +1,9 @@
+#version 430 core
+layout (location = 0) in vec2 aPatchCorner; // (x, z) of a corner of a terrain patch
+
+out vec2 controlPosition;
+
+void main()
+{
+    controlPosition = aPatchCorner;
+}
@@ -0,0 +1,28 @@
+// Sampling the terrain heightmap, shared by the terrain shaders
+#pragma once
+
+uniform sampler2D uHeightmap;  // Covers uTerrainMin to uTerrainMin + uTerrainSize, in (x, z)
+uniform vec2 uTerrainMin;
+uniform vec2 uTerrainSize;
+
+// The first and last texels lie exactly on the edges of the terrain
+vec2 terrainUv(vec2 xz)
+{
+    vec2 texels = vec2(textureSize(uHeightmap, 0));
+    return (xz - uTerrainMin) / uTerrainSize * (texels - 1.0) / texels + 0.5 / texels;
+}
+
+float terrainHeight(vec2 xz)
+{
+    return textureLod(uHeightmap, terrainUv(xz), 0.0).r;
+}
+
+vec3 terrainNormal(vec2 xz)
+{
+    vec2 step = uTerrainSize / vec2(textureSize(uHeightmap, 0) - 1);
+    float left  = terrainHeight(xz - vec2(step.x, 0.0));
+    float right = terrainHeight(xz + vec2(step.x, 0.0));
+    float back  = terrainHeight(xz - vec2(0.0, step.y));
+    float front = terrainHeight(xz + vec2(0.0, step.y));
+    return normalize(vec3((left - right) / (2.0 * step.x), 1.0, (back - front) / (2.0 * step.y)));
+}
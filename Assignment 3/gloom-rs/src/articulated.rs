@@ -0,0 +1,202 @@
+extern crate nalgebra_glm as glm;
+
+use crate::mesh::Mesh;
+use crate::scene_graph::{Node, SceneNode};
+
+// Description of a model made of several moving parts, e.g. a vehicle.
+// Each part is one object in the OBJ file, rotating about its own pivot,
+// and optionally attached to another part so that it follows it around.
+
+pub struct PartDescription {
+    pub name   : String,         // What the part is called in code
+    pub object : String,         // What the object is called in the OBJ file
+    pub color  : [f32; 4],
+    pub pivot  : glm::Vec3,      // The point the part rotates and scales about
+    pub parent : Option<String>, // Name of the part this one is attached to, if any
+}
+
+pub struct ModelDescription {
+    pub name  : String,
+    pub parts : Vec<PartDescription>,
+}
+
+impl ModelDescription {
+    pub fn new(name: &str) -> Self {
+        ModelDescription {
+            name: name.to_string(),
+            parts: vec![],
+        }
+    }
+
+    pub fn part(mut self, name: &str, object: &str, color: [f32; 4], pivot: glm::Vec3, parent: Option<&str>) -> Self {
+        self.parts.push(PartDescription {
+            name: name.to_string(),
+            object: object.to_string(),
+            color,
+            pivot,
+            parent: parent.map(|p| p.to_string()),
+        });
+        self
+    }
+
+    pub fn helicopter() -> Self {
+        ModelDescription::new("helicopter")
+            .part("body",       "Body_body",             [0.3, 0.3, 0.3, 1.0], glm::zero(),                     None)
+            .part("door",       "Door_door",             [0.1, 0.1, 0.3, 1.0], glm::vec3(-1.0, 0.0, 0.0),       Some("body"))
+            .part("main_rotor", "Main_Rotor_main_rotor", [0.3, 0.1, 0.1, 1.0], glm::zero(),                     Some("body"))
+            .part("tail_rotor", "Tail_Rotor_tail_rotor", [0.1, 0.3, 0.1, 1.0], glm::vec3(0.35, 2.3, 10.4),      Some("body"))
+    }
+}
+
+// The loaded meshes, in the same order as the description
+
+pub struct ModelPart {
+    pub name   : String,
+    pub mesh   : Mesh,
+    pub pivot  : glm::Vec3,
+    pub parent : Option<usize>, // Always comes before this part
+}
+
+pub struct ArticulatedModel {
+    pub name  : String,
+    pub parts : Vec<ModelPart>,
+}
+
+impl ArticulatedModel {
+    pub fn load(path: &str, description: &ModelDescription) -> Self {
+        println!("Loading {} model...", description.name);
+        let before = std::time::Instant::now();
+        let (models, _materials)
+            = tobj::load_obj(path,
+                &tobj::LoadOptions{
+                    triangulate: true,
+                    single_index: true,
+                    ..Default::default()
+                }
+            ).unwrap_or_else(|e| panic!("Failed to load {} model: {}", description.name, e));
+        let after = std::time::Instant::now();
+        println!("Done in {:.3}ms!", after.duration_since(before).as_micros() as f32 / 1e3);
+
+        for model in &models {
+            println!("Loaded {} with {} points and {} triangles.", model.name, model.mesh.positions.len() / 3, model.mesh.indices.len() / 3);
+        }
+
+        let mut parts: Vec<ModelPart> = Vec::with_capacity(description.parts.len());
+        for part in &description.parts {
+            if parts.iter().any(|p| p.name == part.name) {
+                panic!("Part {} of the {} model is described twice!", part.name, description.name);
+            }
+            let parent = part.parent.as_ref().map(|parent| {
+                parts.iter().position(|p| &p.name == parent).unwrap_or_else(|| panic!(
+                    "Part {} of the {} model is attached to {}, which has to be described before it!",
+                    part.name, description.name, parent
+                ))
+            });
+            let model = models.iter().find(|m| m.name == part.object).unwrap_or_else(|| panic!(
+                "Incorrect model file! {} has no object called {}", path, part.object
+            ));
+
+            parts.push(ModelPart {
+                name: part.name.clone(),
+                mesh: Mesh::from(model.mesh.clone(), part.color).validated(&model.name),
+                pivot: part.pivot,
+                parent,
+            });
+        }
+
+        ArticulatedModel {
+            name: description.name.clone(),
+            parts,
+        }
+    }
+
+    pub fn part_index(&self, name: &str) -> Option<usize> {
+        self.parts.iter().position(|p| p.name == name)
+    }
+
+    pub fn part(&self, name: &str) -> Option<&ModelPart> {
+        self.parts.iter().find(|p| p.name == name)
+    }
+}
+
+// One copy of the model in the scene graph. The root is a group node that the parts
+// without a parent are attached to, and is what should be moved around.
+
+pub struct ModelInstance {
+    pub root  : Node,
+    pub parts : Vec<Node>, // In the same order as the model parts
+    names     : Vec<String>,
+}
+
+impl ModelInstance {
+    // `create_node` makes the drawable node of each part, e.g. from VAOs shared between instances
+    pub fn new<F>(model: &ArticulatedModel, mut create_node: F) -> Self
+        where F: FnMut(usize, &ModelPart) -> Node
+    {
+        let mut root = SceneNode::new();
+        let mut parts: Vec<Node> = Vec::with_capacity(model.parts.len());
+        for (i, part) in model.parts.iter().enumerate() {
+            let mut node = create_node(i, part);
+            node.reference_point = part.pivot;
+            match part.parent {
+                Some(parent) => parts[parent].add_child(&node),
+                None => root.add_child(&node),
+            }
+            parts.push(node);
+        }
+
+        ModelInstance {
+            root,
+            parts,
+            names: model.parts.iter().map(|p| p.name.clone()).collect(),
+        }
+    }
+
+    pub fn part_mut(&mut self, name: &str) -> Option<&mut SceneNode> {
+        let index = self.names.iter().position(|n| n == name)?;
+        Some(&mut self.parts[index])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // One triangle per object of the helicopter
+    fn helicopter_obj() -> String {
+        let path = std::env::temp_dir().join(format!("gloom_articulated_{}.obj", std::process::id()));
+        let mut obj = String::from("vn 0 0 1\n");
+        for (i, object) in ["Body_body", "Door_door", "Main_Rotor_main_rotor", "Tail_Rotor_tail_rotor"].iter().enumerate() {
+            obj += &format!("o {}\nv 0 0 {i}\nv 1 0 {i}\nv 0 1 {i}\nf {a}//1 {b}//1 {c}//1\n",
+                object, i = i, a = 3 * i + 1, b = 3 * i + 2, c = 3 * i + 3);
+        }
+        std::fs::write(&path, obj).unwrap();
+        path.to_string_lossy().to_string()
+    }
+
+    #[test]
+    fn parts_are_attached_to_their_parents_and_found_by_name() {
+        let path = helicopter_obj();
+        let model = ArticulatedModel::load(&path, &ModelDescription::helicopter());
+        std::fs::remove_file(&path).unwrap();
+
+        let parents: Vec<(&str, Option<usize>)> = model.parts.iter().map(|p| (p.name.as_str(), p.parent)).collect();
+        assert_eq!(parents, [("body", None), ("door", Some(0)), ("main_rotor", Some(0)), ("tail_rotor", Some(0))]);
+        assert_eq!(model.part_index("main_rotor"), Some(2));
+        assert_eq!(model.part("tail_rotor").unwrap().pivot, glm::vec3(0.35, 2.3, 10.4));
+        assert!(model.part("propeller").is_none());
+
+        // Plain group nodes, so no GL is needed
+        let mut instance = ModelInstance::new(&model, |_, _| SceneNode::new());
+        let body: *const SceneNode = &**instance.parts[0];
+        assert_eq!(instance.root.children, [body as *mut SceneNode]);
+        let attached: Vec<*mut SceneNode> = instance.parts[1..].iter().map(|n| &***n as *const SceneNode as *mut SceneNode).collect();
+        assert_eq!(instance.parts[0].children, attached);
+
+        let tail_rotor = instance.part_mut("tail_rotor").unwrap();
+        assert_eq!(tail_rotor.reference_point, glm::vec3(0.35, 2.3, 10.4));
+        tail_rotor.rotation.x = 1.0;
+        assert_eq!(instance.parts[3].rotation.x, 1.0);
+        assert!(instance.part_mut("propeller").is_none());
+    }
+}
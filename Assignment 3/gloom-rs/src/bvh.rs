@@ -0,0 +1,731 @@
+extern crate nalgebra_glm as glm;
+
+use crate::mesh::Mesh;
+
+// Axis aligned bounding box
+
+#[derive(Clone, Copy, Debug)]
+pub struct Aabb {
+    pub min : glm::Vec3,
+    pub max : glm::Vec3,
+}
+
+impl Aabb {
+    pub fn empty() -> Self {
+        Aabb {
+            min: glm::vec3(f32::INFINITY, f32::INFINITY, f32::INFINITY),
+            max: glm::vec3(f32::NEG_INFINITY, f32::NEG_INFINITY, f32::NEG_INFINITY),
+        }
+    }
+
+    pub fn new(min: glm::Vec3, max: glm::Vec3) -> Self {
+        Aabb { min, max }
+    }
+
+    pub fn grow(&mut self, p: &glm::Vec3) {
+        self.min = glm::min2(&self.min, p);
+        self.max = glm::max2(&self.max, p);
+    }
+
+    pub fn union(&self, other: &Aabb) -> Aabb {
+        Aabb {
+            min: glm::min2(&self.min, &other.min),
+            max: glm::max2(&self.max, &other.max),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.min.x > self.max.x || self.min.y > self.max.y || self.min.z > self.max.z
+    }
+
+    pub fn centroid(&self) -> glm::Vec3 {
+        (self.min + self.max) * 0.5
+    }
+
+    pub fn surface_area(&self) -> f32 {
+        if self.is_empty() {
+            return 0.0;
+        }
+        let d = self.max - self.min;
+        2.0 * (d.x * d.y + d.y * d.z + d.z * d.x)
+    }
+
+    pub fn overlaps(&self, other: &Aabb) -> bool {
+        self.min.x <= other.max.x && self.max.x >= other.min.x
+            && self.min.y <= other.max.y && self.max.y >= other.min.y
+            && self.min.z <= other.max.z && self.max.z >= other.min.z
+    }
+
+    pub fn distance_squared_to(&self, p: &glm::Vec3) -> f32 {
+        let closest = glm::clamp_vec(p, &self.min, &self.max);
+        glm::length2(&(closest - p))
+    }
+
+    // The box around all eight corners after transforming them, e.g. from model to world space
+    pub fn transformed(&self, m: &glm::Mat4) -> Aabb {
+        let mut result = Aabb::empty();
+        if self.is_empty() {
+            return result;
+        }
+        for corner in 0..8 {
+            let p = glm::vec3(
+                if corner & 1 == 0 { self.min.x } else { self.max.x },
+                if corner & 2 == 0 { self.min.y } else { self.max.y },
+                if corner & 4 == 0 { self.min.z } else { self.max.z },
+            );
+            result.grow(&(m * p.push(1.0)).xyz());
+        }
+        result
+    }
+
+    // Slab test, returns the entry distance along the ray if it is closer than t_max
+    fn intersect_ray(&self, ray: &Ray, inv_direction: &glm::Vec3, t_max: f32) -> Option<f32> {
+        let mut t_near = 0.0f32;
+        let mut t_far = t_max;
+        let axes = [
+            (self.min.x, self.max.x, ray.origin.x, ray.direction.x, inv_direction.x),
+            (self.min.y, self.max.y, ray.origin.y, ray.direction.y, inv_direction.y),
+            (self.min.z, self.max.z, ray.origin.z, ray.direction.z, inv_direction.z),
+        ];
+        for &(min, max, origin, direction, inv_direction) in &axes {
+            // Parallel to the slab, so either always inside it or never. Handled separately as
+            // 0 * infinity would give NaN for rays starting right on a face, e.g. straight down.
+            if direction == 0.0 {
+                if origin < min || origin > max {
+                    return None;
+                }
+                continue;
+            }
+            let t0 = (min - origin) * inv_direction;
+            let t1 = (max - origin) * inv_direction;
+            t_near = t_near.max(t0.min(t1));
+            t_far = t_far.min(t0.max(t1));
+        }
+        if t_near <= t_far {
+            Some(t_near)
+        } else {
+            None
+        }
+    }
+}
+
+// Ray queries
+
+#[derive(Clone, Copy, Debug)]
+pub struct Ray {
+    pub origin    : glm::Vec3,
+    pub direction : glm::Vec3,
+}
+
+impl Ray {
+    pub fn new(origin: glm::Vec3, direction: glm::Vec3) -> Self {
+        Ray { origin, direction }
+    }
+
+    pub fn at(&self, t: f32) -> glm::Vec3 {
+        self.origin + self.direction * t
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct RayHit {
+    pub triangle : usize, // Index of the triangle in the mesh, i.e. indices[3*triangle..3*triangle+3]
+    pub t        : f32,   // Distance along the ray, in multiples of its direction
+    pub u        : f32,   // Barycentric coordinates of the hit point
+    pub v        : f32,
+}
+
+// Bounding volume hierarchy
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SplitMethod {
+    Sah,    // Binned surface area heuristic, slower to build but faster to query
+    Median, // Split on the median centroid along the longest axis
+}
+
+// Leaves have count > 0 and `first` points into the triangle order,
+// internal nodes have count == 0 and children at `first` and `first + 1`.
+#[derive(Clone, Copy, Debug)]
+struct BvhNode {
+    bounds : Aabb,
+    first  : u32,
+    count  : u32,
+}
+
+pub struct Bvh {
+    positions : Vec<glm::Vec3>,
+    triangles : Vec<[u32; 3]>,
+    order     : Vec<u32>,     // Triangle indices, grouped so that each leaf owns a contiguous range
+    nodes     : Vec<BvhNode>,
+}
+
+const MAX_LEAF_SIZE: usize = 4;
+const SAH_BINS: usize = 12;
+const TRAVERSAL_COST: f32 = 1.0;
+const INTERSECTION_COST: f32 = 1.0;
+const EDGE_TOLERANCE: f32 = 1e-5; // Of the barycentric coordinates, so rays along shared edges can't slip through
+
+impl Bvh {
+    pub fn new(mesh: &Mesh, method: SplitMethod) -> Self {
+        let positions: Vec<glm::Vec3> = mesh.vertices
+            .chunks_exact(3)
+            .map(|p| glm::vec3(p[0], p[1], p[2]))
+            .collect();
+        let triangles: Vec<[u32; 3]> = mesh.indices
+            .chunks_exact(3)
+            .map(|t| [t[0], t[1], t[2]])
+            .collect();
+
+        // Triangles with out-of-range indices are left out of the hierarchy, and will never be hit
+        let valid = |t: &[u32; 3]| t.iter().all(|&i| (i as usize) < positions.len());
+        let order: Vec<u32> = (0..triangles.len() as u32).filter(|&t| valid(&triangles[t as usize])).collect();
+        let bounds: Vec<Aabb> = triangles.iter().map(|t| {
+            let mut b = Aabb::empty();
+            if valid(t) {
+                for &i in t {
+                    b.grow(&positions[i as usize]);
+                }
+            }
+            b
+        }).collect();
+        let centroids: Vec<glm::Vec3> = bounds.iter().map(|b| b.centroid()).collect();
+
+        let mut bvh = Bvh {
+            nodes: Vec::with_capacity(2 * order.len() / MAX_LEAF_SIZE + 1),
+            positions,
+            triangles,
+            order,
+        };
+        bvh.nodes.push(BvhNode { bounds: Aabb::empty(), first: 0, count: bvh.order.len() as u32 });
+        if !bvh.order.is_empty() {
+            bvh.subdivide(0, &bounds, &centroids, method);
+        }
+        bvh
+    }
+
+    pub fn bounds(&self) -> Aabb {
+        self.nodes[0].bounds
+    }
+
+    pub fn node_count(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn triangle_count(&self) -> usize {
+        self.triangles.len()
+    }
+
+    fn subdivide(&mut self, node_index: usize, bounds: &[Aabb], centroids: &[glm::Vec3], method: SplitMethod) {
+        let first = self.nodes[node_index].first as usize;
+        let count = self.nodes[node_index].count as usize;
+
+        let mut node_bounds = Aabb::empty();
+        let mut centroid_bounds = Aabb::empty();
+        for &t in &self.order[first..first + count] {
+            node_bounds = node_bounds.union(&bounds[t as usize]);
+            centroid_bounds.grow(&centroids[t as usize]);
+        }
+        self.nodes[node_index].bounds = node_bounds;
+
+        if count <= MAX_LEAF_SIZE {
+            return;
+        }
+
+        let extent = centroid_bounds.max - centroid_bounds.min;
+        let axis = if extent.x >= extent.y && extent.x >= extent.z {
+            0
+        } else if extent.y >= extent.z {
+            1
+        } else {
+            2
+        };
+        if extent[axis] <= 0.0 {
+            return; // All centroids coincide, no split will separate them
+        }
+
+        let split = match method {
+            SplitMethod::Median => {
+                let mid = count / 2;
+                self.order[first..first + count].select_nth_unstable_by(mid, |&a, &b| {
+                    centroids[a as usize][axis].total_cmp(&centroids[b as usize][axis])
+                });
+                mid
+            }
+            SplitMethod::Sah => {
+                match self.sah_split(first, count, axis, &centroid_bounds, &node_bounds, bounds, centroids) {
+                    Some(split) => split,
+                    None => return, // Splitting is more expensive than intersecting everything
+                }
+            }
+        };
+
+        let left = self.nodes.len();
+        self.nodes.push(BvhNode { bounds: Aabb::empty(), first: first as u32, count: split as u32 });
+        self.nodes.push(BvhNode { bounds: Aabb::empty(), first: (first + split) as u32, count: (count - split) as u32 });
+        self.nodes[node_index].first = left as u32;
+        self.nodes[node_index].count = 0;
+
+        self.subdivide(left, bounds, centroids, method);
+        self.subdivide(left + 1, bounds, centroids, method);
+    }
+
+    // Partitions the range by the cheapest bin boundary, returning the size of the left half
+    #[allow(clippy::too_many_arguments)]
+    fn sah_split(
+        &mut self,
+        first: usize,
+        count: usize,
+        axis: usize,
+        centroid_bounds: &Aabb,
+        node_bounds: &Aabb,
+        bounds: &[Aabb],
+        centroids: &[glm::Vec3],
+    ) -> Option<usize> {
+        let low = centroid_bounds.min[axis];
+        let scale = SAH_BINS as f32 / (centroid_bounds.max[axis] - low);
+        let bin_of = |t: u32| (((centroids[t as usize][axis] - low) * scale) as usize).min(SAH_BINS - 1);
+
+        let mut bin_bounds = [Aabb::empty(); SAH_BINS];
+        let mut bin_counts = [0usize; SAH_BINS];
+        for &t in &self.order[first..first + count] {
+            let b = bin_of(t);
+            bin_bounds[b] = bin_bounds[b].union(&bounds[t as usize]);
+            bin_counts[b] += 1;
+        }
+
+        // Sweep from both sides to get the cost of every boundary between bins
+        let mut right_area = [0.0f32; SAH_BINS];
+        let mut right_count = [0usize; SAH_BINS];
+        let mut acc = Aabb::empty();
+        let mut n = 0;
+        for b in (1..SAH_BINS).rev() {
+            acc = acc.union(&bin_bounds[b]);
+            n += bin_counts[b];
+            right_area[b] = acc.surface_area();
+            right_count[b] = n;
+        }
+
+        let mut best: Option<(usize, f32)> = None;
+        let mut acc = Aabb::empty();
+        let mut n = 0;
+        for b in 1..SAH_BINS {
+            acc = acc.union(&bin_bounds[b - 1]);
+            n += bin_counts[b - 1];
+            if n == 0 || right_count[b] == 0 {
+                continue;
+            }
+            let cost = acc.surface_area() * n as f32 + right_area[b] * right_count[b] as f32;
+            if best.map_or(true, |(_, c)| cost < c) {
+                best = Some((b, cost));
+            }
+        }
+
+        let (boundary, cost) = best?;
+        let split_cost = TRAVERSAL_COST + INTERSECTION_COST * cost / node_bounds.surface_area().max(f32::EPSILON);
+        if split_cost >= INTERSECTION_COST * count as f32 && count <= 4 * MAX_LEAF_SIZE {
+            return None;
+        }
+
+        let range = &mut self.order[first..first + count];
+        let mut split = 0;
+        for i in 0..range.len() {
+            if bin_of(range[i]) < boundary {
+                range.swap(i, split);
+                split += 1;
+            }
+        }
+        Some(split)
+    }
+
+    /// The closest triangle hit by the ray within t_max, if any.
+    pub fn intersect_ray(&self, ray: &Ray, t_max: f32) -> Option<RayHit> {
+        if self.order.is_empty() {
+            return None;
+        }
+        let inv_direction = glm::vec3(1.0 / ray.direction.x, 1.0 / ray.direction.y, 1.0 / ray.direction.z);
+
+        let mut closest: Option<RayHit> = None;
+        let mut t_limit = t_max;
+        // Nodes to visit along with where the ray enters them, which may be past a hit found since
+        let mut stack: Vec<(usize, f32)> = Vec::with_capacity(64);
+        if let Some(t) = self.nodes[0].bounds.intersect_ray(ray, &inv_direction, t_limit) {
+            stack.push((0, t));
+        }
+        while let Some((index, t_entry)) = stack.pop() {
+            if t_entry > t_limit {
+                continue;
+            }
+            let node = &self.nodes[index];
+            if node.count > 0 {
+                for &t in &self.order[node.first as usize..(node.first + node.count) as usize] {
+                    if let Some(hit) = self.intersect_triangle(t as usize, ray, t_limit) {
+                        t_limit = hit.t;
+                        closest = Some(hit);
+                    }
+                }
+                continue;
+            }
+
+            // Visit the nearer child first, so the far one is more likely to be culled
+            let left = node.first as usize;
+            let right = left + 1;
+            let t_left = self.nodes[left].bounds.intersect_ray(ray, &inv_direction, t_limit);
+            let t_right = self.nodes[right].bounds.intersect_ray(ray, &inv_direction, t_limit);
+            match (t_left, t_right) {
+                (Some(l), Some(r)) if l <= r => { stack.push((right, r)); stack.push((left, l)); }
+                (Some(l), Some(r)) => { stack.push((left, l)); stack.push((right, r)); }
+                (Some(l), None) => stack.push((left, l)),
+                (None, Some(r)) => stack.push((right, r)),
+                (None, None) => {}
+            }
+        }
+        closest
+    }
+
+    /// Triangles whose bounding boxes overlap the given box.
+    pub fn query_aabb(&self, query: &Aabb) -> Vec<usize> {
+        let mut result = vec![];
+        self.traverse(|bounds| bounds.overlaps(query), |t| {
+            if self.triangle_bounds(t).overlaps(query) {
+                result.push(t);
+            }
+        });
+        result
+    }
+
+    /// Triangles that touch the sphere.
+    pub fn query_sphere(&self, center: &glm::Vec3, radius: f32) -> Vec<usize> {
+        let radius2 = radius * radius;
+        let mut result = vec![];
+        self.traverse(|bounds| bounds.distance_squared_to(center) <= radius2, |t| {
+            if self.triangle_touches_sphere(t, center, radius2) {
+                result.push(t);
+            }
+        });
+        result
+    }
+
+    // The same queries going through every triangle, to check and benchmark the hierarchy against
+
+    pub fn intersect_ray_linear(&self, ray: &Ray, t_max: f32) -> Option<RayHit> {
+        let mut closest: Option<RayHit> = None;
+        for &t in &self.order {
+            let t_limit = closest.map_or(t_max, |hit| hit.t);
+            if let Some(hit) = self.intersect_triangle(t as usize, ray, t_limit) {
+                closest = Some(hit);
+            }
+        }
+        closest
+    }
+
+    pub fn query_aabb_linear(&self, query: &Aabb) -> Vec<usize> {
+        self.order.iter()
+            .map(|&t| t as usize)
+            .filter(|&t| self.triangle_bounds(t).overlaps(query))
+            .collect()
+    }
+
+    pub fn query_sphere_linear(&self, center: &glm::Vec3, radius: f32) -> Vec<usize> {
+        self.order.iter()
+            .map(|&t| t as usize)
+            .filter(|&t| self.triangle_touches_sphere(t, center, radius * radius))
+            .collect()
+    }
+
+    // The vertex indices of a triangle, as given in the mesh
+    pub fn triangle_indices(&self, t: usize) -> [u32; 3] {
+        self.triangles[t]
+    }
+
+    fn traverse<F, G>(&self, mut visit_node: F, mut visit_triangle: G)
+        where F: FnMut(&Aabb) -> bool, G: FnMut(usize)
+    {
+        if self.order.is_empty() {
+            return;
+        }
+        let mut stack = vec![0usize];
+        while let Some(index) = stack.pop() {
+            let node = &self.nodes[index];
+            if !visit_node(&node.bounds) {
+                continue;
+            }
+            if node.count > 0 {
+                for &t in &self.order[node.first as usize..(node.first + node.count) as usize] {
+                    visit_triangle(t as usize);
+                }
+            } else {
+                stack.push(node.first as usize);
+                stack.push(node.first as usize + 1);
+            }
+        }
+    }
+
+    pub fn triangle_positions(&self, t: usize) -> [glm::Vec3; 3] {
+        self.triangles[t].map(|i| self.positions[i as usize])
+    }
+
+    fn triangle_bounds(&self, t: usize) -> Aabb {
+        let mut bounds = Aabb::empty();
+        for p in &self.triangle_positions(t) {
+            bounds.grow(p);
+        }
+        bounds
+    }
+
+    fn triangle_touches_sphere(&self, t: usize, center: &glm::Vec3, radius2: f32) -> bool {
+        let [a, b, c] = self.triangle_positions(t);
+        glm::length2(&(closest_point_on_triangle(center, &a, &b, &c) - center)) <= radius2
+    }
+
+    fn intersect_triangle(&self, t: usize, ray: &Ray, t_max: f32) -> Option<RayHit> {
+        let [a, b, c] = self.triangle_positions(t);
+        intersect_triangle(ray, &a, &b, &c, t_max).map(|(dist, u, v)| RayHit { triangle: t, t: dist, u, v })
+    }
+}
+
+// Möller–Trumbore ray/triangle intersection, double sided
+fn intersect_triangle(ray: &Ray, a: &glm::Vec3, b: &glm::Vec3, c: &glm::Vec3, t_max: f32) -> Option<(f32, f32, f32)> {
+    let edge1 = b - a;
+    let edge2 = c - a;
+    let p = glm::cross(&ray.direction, &edge2);
+    let det = glm::dot(&edge1, &p);
+    if det.abs() < 1e-12 {
+        return None;
+    }
+    let inv_det = 1.0 / det;
+    let s = ray.origin - a;
+    let u = glm::dot(&s, &p) * inv_det;
+    if !(-EDGE_TOLERANCE..=1.0 + EDGE_TOLERANCE).contains(&u) {
+        return None;
+    }
+    let q = glm::cross(&s, &edge1);
+    let v = glm::dot(&ray.direction, &q) * inv_det;
+    if v < -EDGE_TOLERANCE || u + v > 1.0 + EDGE_TOLERANCE {
+        return None;
+    }
+    let t = glm::dot(&edge2, &q) * inv_det;
+    if t >= 0.0 && t < t_max {
+        Some((t, u, v))
+    } else {
+        None
+    }
+}
+
+// From Real-Time Collision Detection, section 5.1.5
+fn closest_point_on_triangle(p: &glm::Vec3, a: &glm::Vec3, b: &glm::Vec3, c: &glm::Vec3) -> glm::Vec3 {
+    let ab = b - a;
+    let ac = c - a;
+    let ap = p - a;
+    let d1 = glm::dot(&ab, &ap);
+    let d2 = glm::dot(&ac, &ap);
+    if d1 <= 0.0 && d2 <= 0.0 {
+        return *a;
+    }
+
+    let bp = p - b;
+    let d3 = glm::dot(&ab, &bp);
+    let d4 = glm::dot(&ac, &bp);
+    if d3 >= 0.0 && d4 <= d3 {
+        return *b;
+    }
+
+    let vc = d1 * d4 - d3 * d2;
+    if vc <= 0.0 && d1 >= 0.0 && d3 <= 0.0 {
+        return a + ab * (d1 / (d1 - d3));
+    }
+
+    let cp = p - c;
+    let d5 = glm::dot(&ab, &cp);
+    let d6 = glm::dot(&ac, &cp);
+    if d6 >= 0.0 && d5 <= d6 {
+        return *c;
+    }
+
+    let vb = d5 * d2 - d1 * d6;
+    if vb <= 0.0 && d2 >= 0.0 && d6 <= 0.0 {
+        return a + ac * (d2 / (d2 - d6));
+    }
+
+    let va = d3 * d6 - d5 * d4;
+    if va <= 0.0 && (d4 - d3) >= 0.0 && (d5 - d6) >= 0.0 {
+        return b + (c - b) * ((d4 - d3) / ((d4 - d3) + (d5 - d6)));
+    }
+
+    let denom = 1.0 / (va + vb + vc);
+    a + ab * (vb * denom) + ac * (vc * denom)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{Rng, SeedableRng};
+    use rand::rngs::StdRng;
+
+    const SIZE: f32 = 100.0;
+
+    // Small triangles scattered through a cube, some of them sharing vertices
+    fn random_mesh(rng: &mut StdRng, triangles: usize) -> Mesh {
+        let mut vertices = vec![];
+        let mut indices = vec![];
+        for _ in 0..triangles {
+            let center = random_point(rng);
+            if rng.gen_bool(0.2) && !indices.is_empty() {
+                // Share an edge with the previous triangle
+                let n = indices.len();
+                indices.extend_from_slice(&[indices[n - 2], indices[n - 1]]);
+            } else {
+                for _ in 0..2 {
+                    push_vertex(&mut vertices, &mut indices, center + random_offset(rng));
+                }
+            }
+            push_vertex(&mut vertices, &mut indices, center + random_offset(rng));
+        }
+        Mesh {
+            colors: vec![1.0; vertices.len() / 3 * 4],
+            normals: vec![],
+            index_count: indices.len() as i32,
+            vertices,
+            indices,
+        }
+    }
+
+    fn push_vertex(vertices: &mut Vec<f32>, indices: &mut Vec<u32>, p: glm::Vec3) {
+        indices.push((vertices.len() / 3) as u32);
+        vertices.extend_from_slice(&[p.x, p.y, p.z]);
+    }
+
+    fn random_point(rng: &mut StdRng) -> glm::Vec3 {
+        glm::vec3(rng.gen_range(0.0..SIZE), rng.gen_range(0.0..SIZE), rng.gen_range(0.0..SIZE))
+    }
+
+    fn random_offset(rng: &mut StdRng) -> glm::Vec3 {
+        glm::vec3(rng.gen_range(-4.0..4.0), rng.gen_range(-4.0..4.0), rng.gen_range(-4.0..4.0))
+    }
+
+    fn sorted(mut triangles: Vec<usize>) -> Vec<usize> {
+        triangles.sort_unstable();
+        triangles
+    }
+
+    fn hierarchies(mesh: &Mesh) -> Vec<Bvh> {
+        vec![Bvh::new(mesh, SplitMethod::Median), Bvh::new(mesh, SplitMethod::Sah)]
+    }
+
+    #[test]
+    fn rays_hit_the_same_as_brute_force() {
+        let mut rng = StdRng::seed_from_u64(27);
+        let mesh = random_mesh(&mut rng, 2000);
+        for bvh in hierarchies(&mesh) {
+            let mut mismatches = 0;
+            let mut hits = 0;
+            for _ in 0..1000 {
+                // From outside the cube through a point inside of it
+                let target = random_point(&mut rng);
+                let origin = target + glm::normalize(&random_offset(&mut rng)) * SIZE * 1.5;
+                let ray = Ray::new(origin, glm::normalize(&(target - origin)));
+                let expected = bvh.intersect_ray_linear(&ray, f32::INFINITY);
+                let mismatched = match (bvh.intersect_ray(&ray, f32::INFINITY), expected) {
+                    (Some(a), Some(b)) => (a.t - b.t).abs() > 1e-4 * b.t,
+                    (None, None) => false,
+                    _ => true,
+                };
+                mismatches += mismatched as usize;
+                hits += expected.is_some() as usize;
+            }
+            assert_eq!(mismatches, 0);
+            assert!(hits > 100, "only {} rays hit anything, the test isn't testing much", hits);
+        }
+    }
+
+    #[test]
+    fn ray_limit_is_respected() {
+        let mut rng = StdRng::seed_from_u64(28);
+        let mesh = random_mesh(&mut rng, 500);
+        for bvh in hierarchies(&mesh) {
+            for _ in 0..200 {
+                let origin = random_point(&mut rng);
+                let ray = Ray::new(origin, glm::normalize(&random_offset(&mut rng)));
+                let t_max = rng.gen_range(0.0..SIZE);
+                let hit = bvh.intersect_ray(&ray, t_max);
+                assert_eq!(hit.map(|h| h.t), bvh.intersect_ray_linear(&ray, t_max).map(|h| h.t));
+                if let Some(hit) = hit {
+                    assert!(hit.t < t_max);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn box_queries_find_the_same_as_brute_force() {
+        let mut rng = StdRng::seed_from_u64(29);
+        let mesh = random_mesh(&mut rng, 2000);
+        for bvh in hierarchies(&mesh) {
+            let mut mismatches = 0;
+            for _ in 0..300 {
+                let corner = random_point(&mut rng);
+                let size = glm::vec3(rng.gen_range(0.0..20.0), rng.gen_range(0.0..20.0), rng.gen_range(0.0..20.0));
+                let query = Aabb::new(corner, corner + size);
+                mismatches += (sorted(bvh.query_aabb(&query)) != sorted(bvh.query_aabb_linear(&query))) as usize;
+            }
+            assert_eq!(mismatches, 0);
+        }
+    }
+
+    #[test]
+    fn sphere_queries_find_the_same_as_brute_force() {
+        let mut rng = StdRng::seed_from_u64(30);
+        let mesh = random_mesh(&mut rng, 2000);
+        for bvh in hierarchies(&mesh) {
+            let mut mismatches = 0;
+            for _ in 0..300 {
+                let center = random_point(&mut rng);
+                let radius = rng.gen_range(0.0..15.0);
+                mismatches += (sorted(bvh.query_sphere(&center, radius)) != sorted(bvh.query_sphere_linear(&center, radius))) as usize;
+            }
+            assert_eq!(mismatches, 0);
+        }
+    }
+
+    #[test]
+    fn every_triangle_is_in_exactly_one_leaf() {
+        let mut rng = StdRng::seed_from_u64(31);
+        let mut mesh = random_mesh(&mut rng, 1000);
+        // One triangle pointing past the end of the vertices, which has to be left out
+        mesh.indices.extend_from_slice(&[0, 1, 1_000_000]);
+        for bvh in hierarchies(&mesh) {
+            let everything = Aabb::new(glm::vec3(-10.0, -10.0, -10.0), glm::vec3(SIZE + 10.0, SIZE + 10.0, SIZE + 10.0));
+            assert_eq!(sorted(bvh.query_aabb(&everything)), (0..1000).collect::<Vec<usize>>());
+            assert_eq!(bvh.triangle_count(), 1001);
+        }
+    }
+
+    #[test]
+    fn vertical_rays_along_shared_edges_hit() {
+        // Two triangles making up the unit square at y = 1, split along the diagonal
+        let mesh = Mesh {
+            vertices: vec![0.0, 1.0, 0.0, 1.0, 1.0, 0.0, 0.0, 1.0, 1.0, 1.0, 1.0, 1.0],
+            normals: vec![],
+            colors: vec![1.0; 16],
+            indices: vec![0, 2, 1, 1, 2, 3],
+            index_count: 6,
+        };
+        for bvh in hierarchies(&mesh) {
+            for &(x, z) in &[(0.0, 0.0), (0.5, 0.5), (1.0, 1.0), (0.0, 1.0), (0.25, 0.0), (1.0, 0.5)] {
+                let ray = Ray::new(glm::vec3(x, 5.0, z), glm::vec3(0.0, -1.0, 0.0));
+                let hit = bvh.intersect_ray(&ray, f32::INFINITY);
+                assert!(hit.is_some(), "missed at ({}, {})", x, z);
+                assert!((hit.unwrap().t - 4.0).abs() < 1e-5);
+            }
+        }
+    }
+
+    #[test]
+    fn empty_mesh_has_nothing() {
+        let mesh = Mesh { vertices: vec![], normals: vec![], colors: vec![], indices: vec![], index_count: 0 };
+        for bvh in hierarchies(&mesh) {
+            let ray = Ray::new(glm::vec3(0.0, 0.0, 0.0), glm::vec3(0.0, 0.0, 1.0));
+            assert!(bvh.intersect_ray(&ray, f32::INFINITY).is_none());
+            assert!(bvh.query_sphere(&glm::vec3(0.0, 0.0, 0.0), 1e6).is_empty());
+        }
+    }
+}
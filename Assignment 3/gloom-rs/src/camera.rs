@@ -40,6 +40,10 @@ impl Camera {
         self.aspect_ratio = width as f32 / height as f32;
     }
 
+    pub fn position(&self) -> glm::Vec3 {
+        glm::vec3(self.x, self.y, self.z)
+    }
+
     pub fn get_perspective_matrix(&self) -> glm::Mat4 {
         glm::perspective(self.aspect_ratio, self.fovy, self.near, self.far)
     }
@@ -77,6 +81,24 @@ impl Camera {
     }
 
 
+    // Corners of the part of the view frustum between the given distances, in world space
+    pub fn frustum_corners(&self, near: f32, far: f32) -> [glm::Vec3; 8] {
+        let projection = glm::perspective(self.aspect_ratio, self.fovy, near, far);
+        let inverse = glm::inverse(&(projection * self.get_view_matrix()));
+        let mut corners = [glm::Vec3::zeros(); 8];
+        for (i, corner) in corners.iter_mut().enumerate() {
+            let ndc = glm::vec4(
+                if i & 1 == 0 { -1.0 } else { 1.0 },
+                if i & 2 == 0 { -1.0 } else { 1.0 },
+                if i & 4 == 0 { -1.0 } else { 1.0 },
+                1.0,
+            );
+            let world = inverse * ndc;
+            *corner = world.xyz() / world.w;
+        }
+        corners
+    }
+
     pub fn get_view_projection_matrix(&self) -> glm::Mat4 {
         self.get_perspective_matrix() * self.get_view_matrix()
     }
@@ -0,0 +1,227 @@
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, SyncSender};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use image::RgbaImage;
+
+use crate::render_target::read_framebuffer_rgba;
+
+// Saving what is shown in the window, either a single screenshot or every frame of a recording.
+// Both read the back buffer, so they have to happen after drawing and before swapping buffers.
+
+pub const SCREENSHOT_DIRECTORY: &str = "screenshots";
+pub const RECORDING_DIRECTORY: &str = "recordings";
+pub const RECORDING_FRAME_RATE: f32 = 60.0;
+
+const RECORDING_QUEUE_LENGTH: usize = 8;  // Frames waiting to be written before drawing has to wait
+const SCREENSHOT_QUEUE_LENGTH: usize = 2; // The same for screenshots, which come one key press at a time
+
+// The back buffer of the window, top row first, at the size of the viewport
+pub unsafe fn read_window() -> RgbaImage {
+    let mut viewport = [0; 4];
+    gl::GetIntegerv(gl::VIEWPORT, viewport.as_mut_ptr());
+    read_framebuffer_rgba(0, gl::BACK, viewport[2], viewport[3])
+}
+
+// Encodes and saves images on a thread of its own, so that it slows down drawing as little as
+// possible. Drawing only has to wait if the queue is full.
+struct ImageWriter {
+    sender : Option<SyncSender<(PathBuf, RgbaImage)>>,
+    thread : Option<JoinHandle<()>>,
+}
+
+impl ImageWriter {
+    // `saved` is called on the writer thread after each image is saved
+    fn new(queue_length: usize, saved: fn(&Path)) -> Self {
+        let (sender, receiver) = mpsc::sync_channel::<(PathBuf, RgbaImage)>(queue_length);
+        let thread = thread::spawn(move || {
+            for (path, image) in receiver {
+                match image.save(&path) {
+                    Ok(()) => saved(&path),
+                    Err(e) => println!("Failed to save {}: {}", path.display(), e),
+                }
+            }
+        });
+        ImageWriter {
+            sender: Some(sender),
+            thread: Some(thread),
+        }
+    }
+
+    // Queues the image, returning false if the writer has stopped
+    fn write(&self, path: PathBuf, image: RgbaImage) -> bool {
+        match &self.sender {
+            Some(sender) => sender.send((path, image)).is_ok(),
+            None => false,
+        }
+    }
+
+    // Waits for the queued images to be saved
+    fn finish(&mut self) {
+        // The thread runs until the channel is closed
+        self.sender = None;
+        if let Some(thread) = self.thread.take() {
+            if thread.join().is_err() {
+                println!("The image writer panicked");
+            }
+        }
+    }
+}
+
+impl Drop for ImageWriter {
+    fn drop(&mut self) {
+        self.finish();
+    }
+}
+
+// Screenshots go into PNGs named after the time they were taken
+pub struct Screenshots {
+    writer : ImageWriter,
+}
+
+impl Screenshots {
+    pub fn new() -> Self {
+        Screenshots {
+            writer: ImageWriter::new(SCREENSHOT_QUEUE_LENGTH, |path| println!("Saved a screenshot to {}", path.display())),
+        }
+    }
+
+    /// Queues the back buffer to be saved, which is reported once it is done
+    pub unsafe fn take(&mut self) -> Result<(), String> {
+        std::fs::create_dir_all(SCREENSHOT_DIRECTORY)
+            .map_err(|e| format!("Failed to create {}: {}", SCREENSHOT_DIRECTORY, e))?;
+        let path = Path::new(SCREENSHOT_DIRECTORY).join(format!("screenshot_{}.png", timestamp()));
+        if !self.writer.write(path, read_window()) {
+            return Err("The screenshot writer has stopped".to_string());
+        }
+        Ok(())
+    }
+}
+
+impl Default for Screenshots {
+    fn default() -> Self {
+        Screenshots::new()
+    }
+}
+
+// Every frame drawn while recording goes into a numbered PNG in a directory of its own
+pub struct Recording {
+    directory : PathBuf,
+    timestep  : f32,         // Simulated seconds between frames
+    frames    : u32,         // Captured so far
+    writer    : ImageWriter,
+}
+
+impl Recording {
+    pub fn start(frame_rate: f32) -> Result<Self, String> {
+        let directory = Path::new(RECORDING_DIRECTORY).join(format!("recording_{}", timestamp()));
+        std::fs::create_dir_all(&directory)
+            .map_err(|e| format!("Failed to create {}: {}", directory.display(), e))?;
+
+        Ok(Recording {
+            directory,
+            timestep: 1.0 / frame_rate,
+            frames: 0,
+            writer: ImageWriter::new(RECORDING_QUEUE_LENGTH, |_| {}),
+        })
+    }
+
+    pub fn directory(&self) -> &Path {
+        &self.directory
+    }
+
+    // How far time should move on between two frames of the recording
+    pub fn timestep(&self) -> f32 {
+        self.timestep
+    }
+
+    pub fn frames(&self) -> u32 {
+        self.frames
+    }
+
+    // Queues the back buffer to be saved as the next frame
+    pub unsafe fn capture(&mut self) {
+        let path = self.directory.join(format!("frame_{:05}.png", self.frames));
+        if !self.writer.write(path, read_window()) {
+            println!("The recording writer has stopped, frame {} is lost", self.frames);
+        }
+        self.frames += 1;
+    }
+
+    /// Waits for the queued frames to be written, returning how many were captured
+    pub fn finish(mut self) -> u32 {
+        self.writer.finish();
+        self.frames
+    }
+}
+
+// The current UTC time as e.g. 2024-03-01_13-37-00-123, which sorts the same as the time does
+fn timestamp() -> String {
+    format_timestamp(SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default())
+}
+
+fn format_timestamp(since_epoch: Duration) -> String {
+    let seconds = since_epoch.as_secs() as i64;
+    let (days, time_of_day) = (seconds.div_euclid(86400), seconds.rem_euclid(86400));
+    let (year, month, day) = civil_from_days(days);
+    format!(
+        "{:04}-{:02}-{:02}_{:02}-{:02}-{:02}-{:03}",
+        year, month, day,
+        time_of_day / 3600, time_of_day % 3600 / 60, time_of_day % 60,
+        since_epoch.subsec_millis(),
+    )
+}
+
+// The Gregorian calendar date of a number of days since 1970-01-01, after Howard Hinnant
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let day_of_era = z.rem_euclid(146097);
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153; // Starting from March
+    let day = (day_of_year - (153 * shifted_month + 2) / 5 + 1) as u32;
+    let month = if shifted_month < 10 { shifted_month + 3 } else { shifted_month - 9 } as u32;
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn civil_from_days_counts_from_the_epoch() {
+        assert_eq!(civil_from_days(0), (1970, 1, 1));
+        assert_eq!(civil_from_days(11016), (2000, 2, 29));
+        assert_eq!(civil_from_days(19783), (2024, 3, 1));
+        assert_eq!(civil_from_days(-1), (1969, 12, 31));
+    }
+
+    #[test]
+    fn timestamps_are_utc_dates_and_times() {
+        assert_eq!(format_timestamp(Duration::ZERO), "1970-01-01_00-00-00-000");
+        let leap_day = Duration::from_secs(11016 * 86400 + 23 * 3600 + 59 * 60 + 59) + Duration::from_millis(999);
+        assert_eq!(format_timestamp(leap_day), "2000-02-29_23-59-59-999");
+        let seconds = 19783 * 86400 + 13 * 3600 + 37 * 60;
+        assert_eq!(format_timestamp(Duration::from_millis(seconds * 1000 + 123)), "2024-03-01_13-37-00-123");
+    }
+
+    #[test]
+    fn image_writer_saves_everything_queued_before_finishing() {
+        let directory = std::env::temp_dir().join(format!("gloom_image_writer_{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        let paths: Vec<PathBuf> = (0..5).map(|i| directory.join(format!("{}.png", i))).collect();
+        let mut writer = ImageWriter::new(1, |_| {});
+        for path in &paths {
+            assert!(writer.write(path.clone(), RgbaImage::new(4, 3)));
+        }
+        writer.finish();
+        for path in &paths {
+            assert_eq!(image::open(path).unwrap().to_rgba8().dimensions(), (4, 3));
+        }
+        assert!(!writer.write(directory.join("late.png"), RgbaImage::new(1, 1)));
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+}
@@ -0,0 +1,250 @@
+use std::time::Instant;
+
+// Simulated time, moved on once per frame. Everything that changes over time (camera movement,
+// animations, recordings) reads it from here rather than from the wall clock, so that a run
+// can be slowed down, paused, or made to step exactly the same way every time.
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum ClockMode {
+    RealTime,                     // As much time as passed on the wall clock
+    FixedStep { step: f32 },      // The same number of seconds every frame, however long it took
+    Paused,                       // No time at all
+    SlowMotion { scale: f32 },    // The wall clock time scaled, e.g. 0.25 for four times slower
+    Scripted { times: Vec<f32> }, // The time of each frame, staying at the last one when they run out
+}
+
+impl ClockMode {
+    /// Parses "real", "paused", "fixed:<seconds>", "slow:<scale>" or "script:<path>".
+    /// A script is a file of times in seconds, one frame each, separated by whitespace.
+    pub fn parse(spec: &str) -> Result<ClockMode, String> {
+        let (name, argument) = match spec.find(':') {
+            Some(i) => (&spec[..i], Some(&spec[i + 1..])),
+            None => (spec, None),
+        };
+        let number = |what: &str| -> Result<f32, String> {
+            let value = argument.ok_or_else(|| format!("The {} clock needs a {}, as in {}:<{}>", name, what, name, what))?;
+            match value.parse::<f32>() {
+                Ok(value) if value > 0.0 && value.is_finite() => Ok(value),
+                _ => Err(format!("The {} of the {} clock has to be a positive number, not {:?}", what, name, value)),
+            }
+        };
+        match name {
+            "real" | "paused" if argument.is_some() => Err(format!("The {} clock takes no argument, found {:?}", name, spec)),
+            "real" => Ok(ClockMode::RealTime),
+            "paused" => Ok(ClockMode::Paused),
+            "fixed" => Ok(ClockMode::FixedStep { step: number("step")? }),
+            "slow" => Ok(ClockMode::SlowMotion { scale: number("scale")? }),
+            "script" => match argument {
+                Some(path) => ClockMode::load_script(path),
+                None => Err("The script clock needs a file, as in script:<path>".to_string()),
+            },
+            _ => Err(format!("Unknown clock {:?}, expected real, paused, fixed:<step>, slow:<scale> or script:<path>", spec)),
+        }
+    }
+
+    pub fn load_script(path: &str) -> Result<ClockMode, String> {
+        let source = std::fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read the time script {}: {}", path, e))?;
+        let times = source.split_whitespace()
+            .map(|word| word.parse::<f32>().map_err(|_| format!("{:?} in {} is not a time", word, path)))
+            .collect::<Result<Vec<f32>, String>>()?;
+        if times.is_empty() {
+            return Err(format!("The time script {} is empty", path));
+        }
+        Ok(ClockMode::Scripted { times })
+    }
+
+    // Whether the time depends on how fast frames are drawn, making runs differ
+    pub fn follows_wall_clock(&self) -> bool {
+        matches!(self, ClockMode::RealTime | ClockMode::SlowMotion { .. })
+    }
+}
+
+pub struct Clock {
+    mode          : ClockMode,
+    previous_mode : Option<ClockMode>, // To go back to, see toggle_mode
+    time          : f64,               // Seconds since the start, in double precision so long runs stay exact
+    delta         : f32,               // Seconds between the latest two ticks
+    frame         : u64,               // Ticks since the start
+    mode_start    : f64,               // Time when the mode was set
+    mode_frames   : u64,               // Ticks since the mode was set
+    previous_tick : Instant,
+}
+
+impl Clock {
+    pub fn new(mode: ClockMode) -> Self {
+        Clock {
+            mode,
+            previous_mode: None,
+            time: 0.0,
+            delta: 0.0,
+            frame: 0,
+            mode_start: 0.0,
+            mode_frames: 0,
+            previous_tick: Instant::now(),
+        }
+    }
+
+    pub fn mode(&self) -> &ClockMode {
+        &self.mode
+    }
+
+    // Time goes on from where it is in the new mode, starting with the next tick.
+    // A script starts over from its first time.
+    pub fn set_mode(&mut self, mode: ClockMode) {
+        self.mode = mode;
+        self.mode_start = self.time;
+        self.mode_frames = 0;
+    }
+
+    // Switches to the mode, or back to the one before it if the clock is already in it,
+    // e.g. to pause and resume
+    pub fn toggle_mode(&mut self, mode: ClockMode) {
+        if self.mode == mode {
+            let previous = self.previous_mode.take().unwrap_or(ClockMode::RealTime);
+            self.set_mode(previous);
+        } else {
+            self.previous_mode = Some(self.mode.clone());
+            self.set_mode(mode);
+        }
+    }
+
+    // Makes the clock leave `mode` for `replacement`, whether it is in it or would go back to it
+    // from a toggled mode. Ends a temporary mode without undoing e.g. a pause made meanwhile.
+    pub fn replace_mode(&mut self, mode: &ClockMode, replacement: ClockMode) {
+        if self.previous_mode.as_ref() == Some(mode) {
+            self.previous_mode = Some(replacement.clone());
+        }
+        if &self.mode == mode {
+            self.set_mode(replacement);
+        }
+    }
+
+    /// Moves time on by one frame, returning the seconds that passed.
+    /// Those are never negative, a script going back in time counts as no time passing.
+    pub fn tick(&mut self) -> f32 {
+        let now = Instant::now();
+        let wall_delta = now.duration_since(self.previous_tick).as_secs_f64();
+        self.previous_tick = now;
+
+        self.mode_frames += 1;
+        let time = match &self.mode {
+            ClockMode::RealTime => self.time + wall_delta,
+            // From the number of steps rather than adding them up, so no rounding errors build up
+            ClockMode::FixedStep { step } => self.mode_start + self.mode_frames as f64 * *step as f64,
+            ClockMode::Paused => self.time,
+            ClockMode::SlowMotion { scale } => self.time + wall_delta * *scale as f64,
+            ClockMode::Scripted { times } => {
+                let index = (self.mode_frames as usize - 1).min(times.len() - 1);
+                times[index] as f64
+            },
+        };
+        self.delta = (time - self.time).max(0.0) as f32;
+        self.time = time;
+        self.frame += 1;
+        self.delta
+    }
+
+    // Seconds since the start, as of the latest tick
+    pub fn time(&self) -> f32 {
+        self.time as f32
+    }
+
+    pub fn delta(&self) -> f32 {
+        self.delta
+    }
+
+    pub fn frame(&self) -> u64 {
+        self.frame
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn script(name: &str, contents: &str) -> String {
+        let path = std::env::temp_dir().join(format!("gloom_clock_{}_{}", std::process::id(), name));
+        std::fs::write(&path, contents).unwrap();
+        path.to_string_lossy().to_string()
+    }
+
+    #[test]
+    fn parse_reads_every_mode() {
+        assert_eq!(ClockMode::parse("real"), Ok(ClockMode::RealTime));
+        assert_eq!(ClockMode::parse("paused"), Ok(ClockMode::Paused));
+        assert_eq!(ClockMode::parse("fixed:0.02"), Ok(ClockMode::FixedStep { step: 0.02 }));
+        assert_eq!(ClockMode::parse("slow:0.25"), Ok(ClockMode::SlowMotion { scale: 0.25 }));
+        let path = script("times", "0.0 0.5\n1.25\n");
+        assert_eq!(ClockMode::parse(&format!("script:{}", path)), Ok(ClockMode::Scripted { times: vec![0.0, 0.5, 1.25] }));
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn parse_rejects_bad_modes() {
+        for spec in ["fixed:-1", "fixed:0", "fixed:inf", "fixed:abc", "fixed", "slow", "slow:", "script", "", "fast", "real:1", "paused:2"] {
+            assert!(ClockMode::parse(spec).is_err(), "{:?} was accepted", spec);
+        }
+        assert!(ClockMode::parse("script:/no/such/clock/script").is_err());
+        let empty = script("empty", " \n");
+        assert!(ClockMode::parse(&format!("script:{}", empty)).is_err());
+        let words = script("words", "0.0 soon");
+        assert!(ClockMode::parse(&format!("script:{}", words)).is_err());
+        std::fs::remove_file(empty).unwrap();
+        std::fs::remove_file(words).unwrap();
+    }
+
+    #[test]
+    fn fixed_steps_add_up_exactly() {
+        let step = 0.1;
+        let mut clock = Clock::new(ClockMode::FixedStep { step });
+        for n in 1..=10_000u64 {
+            clock.tick();
+            assert_eq!(clock.time(), (n as f64 * step as f64) as f32);
+            assert_eq!(clock.frame(), n);
+        }
+        assert!((clock.delta() - step).abs() < 1e-3);
+    }
+
+    #[test]
+    fn scripts_never_go_back_in_time_and_stop_at_the_end() {
+        let mut clock = Clock::new(ClockMode::Scripted { times: vec![0.0, 0.5, 0.25, 1.0] });
+        let ticks: Vec<(f32, f32)> = (0..6).map(|_| (clock.tick(), clock.time())).collect();
+        assert_eq!(ticks, vec![(0.0, 0.0), (0.5, 0.5), (0.0, 0.25), (0.75, 1.0), (0.0, 1.0), (0.0, 1.0)]);
+    }
+
+    #[test]
+    fn toggling_pauses_and_resumes() {
+        let mut clock = Clock::new(ClockMode::FixedStep { step: 0.25 });
+        clock.tick();
+        clock.toggle_mode(ClockMode::Paused);
+        assert_eq!(clock.mode(), &ClockMode::Paused);
+        assert_eq!(clock.tick(), 0.0);
+        assert_eq!(clock.time(), 0.25);
+
+        clock.toggle_mode(ClockMode::Paused);
+        assert_eq!(clock.mode(), &ClockMode::FixedStep { step: 0.25 });
+        assert_eq!(clock.tick(), 0.25);
+        assert_eq!(clock.time(), 0.5);
+
+        // With nothing to go back to, it goes on in real time
+        let mut clock = Clock::new(ClockMode::Paused);
+        clock.toggle_mode(ClockMode::Paused);
+        assert_eq!(clock.mode(), &ClockMode::RealTime);
+    }
+
+    #[test]
+    fn replace_mode_keeps_a_pause() {
+        let recording = ClockMode::FixedStep { step: 1.0 / 60.0 };
+        let mut clock = Clock::new(recording.clone());
+        clock.replace_mode(&recording, ClockMode::RealTime);
+        assert_eq!(clock.mode(), &ClockMode::RealTime);
+
+        let mut clock = Clock::new(recording.clone());
+        clock.toggle_mode(ClockMode::Paused);
+        clock.replace_mode(&recording, ClockMode::RealTime);
+        assert_eq!(clock.mode(), &ClockMode::Paused);
+        clock.toggle_mode(ClockMode::Paused);
+        assert_eq!(clock.mode(), &ClockMode::RealTime);
+    }
+}
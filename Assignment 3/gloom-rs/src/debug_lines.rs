@@ -0,0 +1,85 @@
+extern crate nalgebra_glm as glm;
+
+use crate::gl_objects::{StreamBuffer, VertexArray};
+use crate::shader::ShaderError;
+use crate::shader_reload::{self, ReloadingShader};
+use crate::vertex_layout::{VertexAttribute, VertexLayout};
+
+// Lines drawn on top of the scene for a single frame, e.g. to show normals or bounding boxes.
+// They are streamed to the GPU every frame and forgotten once drawn.
+
+const MAX_DEBUG_LINES: usize = 16 * 1024;
+const FLOATS_PER_VERTEX: usize = 7; // Position and RGBA color
+
+pub struct DebugLines {
+    vertices : Vec<f32>,
+    stream   : StreamBuffer,
+    vao      : VertexArray,
+    layout   : VertexLayout,
+    shader   : ReloadingShader,
+}
+
+impl DebugLines {
+    pub unsafe fn new() -> Self {
+        let layout = VertexLayout::new().interleaved(vec![
+            VertexAttribute::float("aPos",   0, 3),
+            VertexAttribute::float("aColor", 1, 4),
+        ]);
+        let stream = StreamBuffer::new(
+            gl::ARRAY_BUFFER,
+            (MAX_DEBUG_LINES * 2 * FLOATS_PER_VERTEX * std::mem::size_of::<f32>()) as isize,
+        );
+        let shader = ReloadingShader::new(&["shaders/debug_lines.vert", "shaders/debug_lines.frag"], shader_reload::no_check)
+            .unwrap_or_else(|e| panic!("{}", e));
+
+        DebugLines {
+            vertices: Vec::with_capacity(MAX_DEBUG_LINES * 2 * FLOATS_PER_VERTEX),
+            stream,
+            vao: VertexArray::new(),
+            layout,
+            shader,
+        }
+    }
+
+    pub fn line(&mut self, from: &glm::Vec3, to: &glm::Vec3, color: [f32; 4]) {
+        if self.vertices.len() >= MAX_DEBUG_LINES * 2 * FLOATS_PER_VERTEX {
+            return;
+        }
+        for p in [from, to] {
+            self.vertices.extend_from_slice(&[p.x, p.y, p.z]);
+            self.vertices.extend_from_slice(&color);
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.vertices.is_empty()
+    }
+
+    // Rebuilds the shader if its files changed, see ReloadingShader::poll
+    pub unsafe fn reload_shader(&mut self) -> Option<(Result<(), ShaderError>, String)> {
+        let result = self.shader.poll()?;
+        Some((result, self.shader.paths().join(", ")))
+    }
+
+    /// Draws all lines added since the last call, then forgets them.
+    /// The camera is read from the per-frame uniform buffer.
+    pub unsafe fn draw(&mut self) {
+        if self.vertices.is_empty() {
+            self.stream.finish_frame();
+            return;
+        }
+        if let Some(offset) = self.stream.push(&self.vertices) {
+            self.vao.bind();
+            self.stream.buffer().bind();
+            self.layout.apply_at(0, offset);
+
+            self.shader.activate();
+            gl::DrawArrays(gl::LINES, 0, (self.vertices.len() / FLOATS_PER_VERTEX) as i32);
+
+            gl::BindBuffer(gl::ARRAY_BUFFER, 0);
+            gl::BindVertexArray(0);
+        }
+        self.stream.finish_frame();
+        self.vertices.clear();
+    }
+}
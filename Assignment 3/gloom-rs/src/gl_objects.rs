@@ -0,0 +1,534 @@
+use std::os::raw::c_void;
+
+use crate::graphics::{byte_size_of_array, pointer_to_array};
+use crate::vertex_layout::{as_bytes, Pod};
+
+// Owned OpenGL objects, which are deleted again when dropped.
+// They have to be dropped on the thread holding the OpenGL context, like all other GL calls.
+// Data going in or out of buffers has to be Pod, as the GPU sees every byte of it.
+
+// Buffer
+
+pub struct Buffer {
+    id     : u32,
+    target : gl::types::GLenum,
+    size   : isize, // In bytes
+}
+
+impl Buffer {
+    pub unsafe fn new(target: gl::types::GLenum) -> Self {
+        let mut id = 0;
+        gl::GenBuffers(1, &mut id);
+        Buffer { id, target, size: 0 }
+    }
+
+    // Creates a buffer and fills it with the given data, leaving it bound
+    pub unsafe fn with_data<T: Pod>(target: gl::types::GLenum, data: &[T], usage: gl::types::GLenum) -> Self {
+        let mut buffer = Buffer::new(target);
+        buffer.bind();
+        buffer.upload(data, usage);
+        buffer
+    }
+
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+
+    pub fn target(&self) -> gl::types::GLenum {
+        self.target
+    }
+
+    pub fn size(&self) -> isize {
+        self.size
+    }
+
+    pub unsafe fn bind(&self) {
+        gl::BindBuffer(self.target, self.id);
+    }
+
+    // Binds the buffer to an indexed binding point, like the `binding` of a shader storage
+    // or uniform block. Only for SHADER_STORAGE_BUFFER, UNIFORM_BUFFER and the like.
+    pub unsafe fn bind_base(&self, index: u32) {
+        gl::BindBufferBase(self.target, index, self.id);
+    }
+
+    // Same as above, for `size` bytes starting `offset` bytes in
+    pub unsafe fn bind_range(&self, index: u32, offset: isize, size: isize) {
+        gl::BindBufferRange(self.target, index, self.id, offset, size);
+    }
+
+    // Reads the whole buffer back, e.g. after a compute shader wrote to it. Binds the buffer.
+    pub unsafe fn read<T: Pod + Default>(&self) -> Vec<T> {
+        let mut data = vec![T::default(); self.size as usize / std::mem::size_of::<T>()];
+        self.bind();
+        gl::GetBufferSubData(self.target, 0, byte_size_of_array(&data), data.as_mut_ptr() as *mut c_void);
+        data
+    }
+
+    // Replaces the whole contents of the buffer. Make sure it is bound first.
+    pub unsafe fn upload<T: Pod>(&mut self, data: &[T], usage: gl::types::GLenum) {
+        let bytes = as_bytes(data);
+        let pointer = if bytes.is_empty() { std::ptr::null() } else { bytes.as_ptr() as *const c_void };
+        self.size = byte_size_of_array(bytes);
+        gl::BufferData(self.target, self.size, pointer, usage);
+    }
+
+    // Overwrites part of the buffer, starting `offset` bytes in. Make sure it is bound first.
+    pub unsafe fn update<T: Pod>(&mut self, offset: isize, data: &[T]) {
+        let bytes = as_bytes(data);
+        let size = byte_size_of_array(bytes);
+        if offset < 0 || offset + size > self.size {
+            panic!("Buffer update of {} bytes at {} is outside of the {} byte buffer", size, offset, self.size);
+        }
+        if size > 0 {
+            gl::BufferSubData(self.target, offset, size, bytes.as_ptr() as *const c_void);
+        }
+    }
+
+    // Replaces the whole contents, possibly with a different size. Fresh storage is allocated
+    // first, letting the driver throw away the old one once draws still reading from it are
+    // done rather than waiting for them. Make sure it is bound first.
+    pub unsafe fn orphan_and_upload<T: Pod>(&mut self, data: &[T], usage: gl::types::GLenum) {
+        let bytes = as_bytes(data);
+        self.size = byte_size_of_array(bytes);
+        gl::BufferData(self.target, self.size, std::ptr::null(), usage);
+        if self.size > 0 {
+            gl::BufferSubData(self.target, 0, self.size, bytes.as_ptr() as *const c_void);
+        }
+    }
+}
+
+impl Drop for Buffer {
+    fn drop(&mut self) {
+        unsafe { gl::DeleteBuffers(1, &self.id) };
+    }
+}
+
+// Ring buffer for data that is rewritten every frame, like debug lines or instance data.
+// The buffer is split into one region per frame in flight, and each frame writes into the next
+// region, waiting for the GPU to finish with it first. Where available, the buffer is mapped once
+// and written to directly, otherwise it falls back to orphaning and BufferSubData.
+
+const STREAM_FRAMES: usize = 3;
+
+pub struct StreamBuffer {
+    buffer   : Buffer,
+    capacity : isize,                  // Bytes per frame
+    frame    : usize,                  // The region being written to
+    cursor   : isize,                  // Bytes written to the region this frame
+    mapping  : *mut u8,                // Null if not persistently mapped
+    fences   : [gl::types::GLsync; STREAM_FRAMES],
+}
+
+impl StreamBuffer {
+    pub unsafe fn new(target: gl::types::GLenum, capacity: isize) -> Self {
+        let mut buffer = Buffer::new(target);
+        buffer.bind();
+        buffer.size = capacity * STREAM_FRAMES as isize;
+
+        let mut mapping = std::ptr::null_mut();
+        if gl::BufferStorage::is_loaded() && gl::MapBufferRange::is_loaded() {
+            let flags = gl::MAP_WRITE_BIT | gl::MAP_PERSISTENT_BIT | gl::MAP_COHERENT_BIT;
+            gl::BufferStorage(target, buffer.size, std::ptr::null(), flags);
+            mapping = gl::MapBufferRange(target, 0, buffer.size, flags) as *mut u8;
+        }
+        if mapping.is_null() {
+            gl::BufferData(target, buffer.size, std::ptr::null(), gl::STREAM_DRAW);
+        }
+
+        StreamBuffer {
+            buffer,
+            capacity,
+            frame: 0,
+            cursor: 0,
+            mapping,
+            fences: [std::ptr::null(); STREAM_FRAMES],
+        }
+    }
+
+    pub fn buffer(&self) -> &Buffer {
+        &self.buffer
+    }
+
+    pub fn capacity(&self) -> isize {
+        self.capacity
+    }
+
+    pub fn is_persistently_mapped(&self) -> bool {
+        !self.mapping.is_null()
+    }
+
+    /// Appends the data to this frame's region, returning its byte offset into the buffer,
+    /// or None if the region is full. Binds the buffer.
+    pub unsafe fn push<T: Pod>(&mut self, data: &[T]) -> Option<isize> {
+        let bytes = as_bytes(data);
+        let size = byte_size_of_array(bytes);
+        // Keep every push aligned, so any type can be read back from the offset
+        let start = (self.cursor + 15) & !15;
+        if start + size > self.capacity {
+            return None;
+        }
+
+        if self.cursor == 0 {
+            self.wait_for_region();
+        }
+        let offset = self.frame as isize * self.capacity + start;
+        self.buffer.bind();
+        if size > 0 {
+            if self.mapping.is_null() {
+                if offset == 0 {
+                    gl::BufferData(self.buffer.target, self.buffer.size, std::ptr::null(), gl::STREAM_DRAW);
+                }
+                gl::BufferSubData(self.buffer.target, offset, size, bytes.as_ptr() as *const c_void);
+            } else {
+                std::ptr::copy_nonoverlapping(bytes.as_ptr(), self.mapping.offset(offset), bytes.len());
+            }
+        }
+        self.cursor = start + size;
+        Some(offset)
+    }
+
+    /// Call once all draws reading this frame's data have been issued.
+    pub unsafe fn finish_frame(&mut self) {
+        if self.cursor > 0 && !self.mapping.is_null() {
+            self.fences[self.frame] = gl::FenceSync(gl::SYNC_GPU_COMMANDS_COMPLETE, 0);
+        }
+        self.frame = (self.frame + 1) % STREAM_FRAMES;
+        self.cursor = 0;
+    }
+
+    unsafe fn wait_for_region(&mut self) {
+        let fence = std::mem::replace(&mut self.fences[self.frame], std::ptr::null());
+        if fence.is_null() {
+            return;
+        }
+        loop {
+            let status = gl::ClientWaitSync(fence, gl::SYNC_FLUSH_COMMANDS_BIT, 1_000_000);
+            if status == gl::ALREADY_SIGNALED || status == gl::CONDITION_SATISFIED || status == gl::WAIT_FAILED {
+                break;
+            }
+        }
+        gl::DeleteSync(fence);
+    }
+}
+
+impl Drop for StreamBuffer {
+    fn drop(&mut self) {
+        unsafe {
+            for fence in self.fences {
+                if !fence.is_null() {
+                    gl::DeleteSync(fence);
+                }
+            }
+            if !self.mapping.is_null() {
+                self.buffer.bind();
+                gl::UnmapBuffer(self.buffer.target);
+            }
+        }
+    }
+}
+
+// Vertex array, owning the buffers its attributes are read from
+
+pub struct VertexArray {
+    id           : u32,
+    buffers      : Vec<Buffer>,
+    index_buffer : Option<Buffer>,
+    index_count  : i32,
+}
+
+impl VertexArray {
+    pub unsafe fn new() -> Self {
+        let mut id = 0;
+        gl::GenVertexArrays(1, &mut id);
+        VertexArray {
+            id,
+            buffers: vec![],
+            index_buffer: None,
+            index_count: 0,
+        }
+    }
+
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+
+    pub fn index_count(&self) -> i32 {
+        self.index_count
+    }
+
+    // The vertex buffers, in the order they were added
+    pub fn buffers(&self) -> &[Buffer] {
+        &self.buffers
+    }
+
+    pub fn buffers_mut(&mut self) -> &mut [Buffer] {
+        &mut self.buffers
+    }
+
+    pub fn index_buffer(&self) -> Option<&Buffer> {
+        self.index_buffer.as_ref()
+    }
+
+    pub fn index_buffer_mut(&mut self) -> Option<&mut Buffer> {
+        self.index_buffer.as_mut()
+    }
+
+    pub fn set_index_count(&mut self, index_count: i32) {
+        self.index_count = index_count;
+    }
+
+    pub unsafe fn bind(&self) {
+        gl::BindVertexArray(self.id);
+    }
+
+    // Hands a vertex buffer over to the VAO. Attribute pointers are set up by the caller.
+    pub fn add_buffer(&mut self, buffer: Buffer) -> &Buffer {
+        self.buffers.push(buffer);
+        self.buffers.last().unwrap()
+    }
+
+    // Hands the index buffer over to the VAO. It has to be bound while the VAO is bound.
+    pub fn set_index_buffer(&mut self, buffer: Buffer, index_count: i32) {
+        self.index_buffer = Some(buffer);
+        self.index_count = index_count;
+    }
+}
+
+impl Drop for VertexArray {
+    fn drop(&mut self) {
+        unsafe { gl::DeleteVertexArrays(1, &self.id) };
+    }
+}
+
+// Texture
+
+pub struct Texture {
+    id     : u32,
+    target : gl::types::GLenum,
+    width  : i32,
+    height : i32,
+}
+
+impl Texture {
+    pub unsafe fn new(target: gl::types::GLenum) -> Self {
+        let mut id = 0;
+        gl::GenTextures(1, &mut id);
+        Texture { id, target, width: 0, height: 0 }
+    }
+
+    // Creates a 2D texture filled with the given pixels, row by row from the bottom.
+    // `format` and `ty` describe the data, `internal_format` how it is stored on the GPU.
+    pub unsafe fn with_data_2d<T>(
+        width: i32,
+        height: i32,
+        internal_format: gl::types::GLenum,
+        format: gl::types::GLenum,
+        ty: gl::types::GLenum,
+        data: &[T],
+    ) -> Self {
+        let mut texture = Texture::new(gl::TEXTURE_2D);
+        texture.upload_2d(width, height, internal_format, format, ty, pointer_to_array(data));
+        texture.set_filter(gl::LINEAR, gl::LINEAR);
+        texture.set_wrap(gl::CLAMP_TO_EDGE);
+        texture
+    }
+
+    // Same as above, with undefined contents, e.g. for rendering into
+    pub unsafe fn empty_2d(
+        width: i32,
+        height: i32,
+        internal_format: gl::types::GLenum,
+        format: gl::types::GLenum,
+        ty: gl::types::GLenum,
+    ) -> Self {
+        let mut texture = Texture::new(gl::TEXTURE_2D);
+        texture.upload_2d(width, height, internal_format, format, ty, std::ptr::null());
+        texture.set_filter(gl::LINEAR, gl::LINEAR);
+        texture.set_wrap(gl::CLAMP_TO_EDGE);
+        texture
+    }
+
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+
+    pub fn target(&self) -> gl::types::GLenum {
+        self.target
+    }
+
+    pub fn width(&self) -> i32 {
+        self.width
+    }
+
+    pub fn height(&self) -> i32 {
+        self.height
+    }
+
+    pub unsafe fn bind(&self) {
+        gl::BindTexture(self.target, self.id);
+    }
+
+    // Binds the texture to the given texture unit, leaving that unit active
+    pub unsafe fn bind_to_unit(&self, unit: u32) {
+        gl::ActiveTexture(gl::TEXTURE0 + unit);
+        self.bind();
+    }
+
+    // (Re)allocates the storage of a 2D texture, filling it from `pixels` unless it is null. Binds the texture.
+    pub unsafe fn upload_2d(
+        &mut self,
+        width: i32,
+        height: i32,
+        internal_format: gl::types::GLenum,
+        format: gl::types::GLenum,
+        ty: gl::types::GLenum,
+        pixels: *const c_void,
+    ) {
+        self.bind();
+        gl::PixelStorei(gl::UNPACK_ALIGNMENT, 1);
+        gl::TexImage2D(self.target, 0, internal_format as i32, width, height, 0, format, ty, pixels);
+        gl::PixelStorei(gl::UNPACK_ALIGNMENT, 4);
+        self.width = width;
+        self.height = height;
+    }
+
+    // Storage that can't be reallocated afterwards, with undefined contents, e.g. for rendering into.
+    // Any sized internal format works, without having to name a matching pixel format.
+    pub unsafe fn storage_2d(width: i32, height: i32, internal_format: gl::types::GLenum, levels: i32) -> Self {
+        let mut texture = Texture::new(gl::TEXTURE_2D);
+        texture.bind();
+        gl::TexStorage2D(gl::TEXTURE_2D, levels, internal_format, width, height);
+        texture.width = width;
+        texture.height = height;
+        texture.set_filter(gl::LINEAR, gl::LINEAR);
+        texture.set_wrap(gl::CLAMP_TO_EDGE);
+        texture
+    }
+
+    // Binds the texture
+    pub unsafe fn set_filter(&self, min: gl::types::GLenum, mag: gl::types::GLenum) {
+        self.bind();
+        gl::TexParameteri(self.target, gl::TEXTURE_MIN_FILTER, min as i32);
+        gl::TexParameteri(self.target, gl::TEXTURE_MAG_FILTER, mag as i32);
+    }
+
+    // Binds the texture
+    pub unsafe fn set_wrap(&self, wrap: gl::types::GLenum) {
+        self.bind();
+        gl::TexParameteri(self.target, gl::TEXTURE_WRAP_S, wrap as i32);
+        gl::TexParameteri(self.target, gl::TEXTURE_WRAP_T, wrap as i32);
+    }
+
+    // Binds the texture
+    pub unsafe fn generate_mipmaps(&self) {
+        self.bind();
+        gl::GenerateMipmap(self.target);
+    }
+}
+
+impl Drop for Texture {
+    fn drop(&mut self) {
+        unsafe { gl::DeleteTextures(1, &self.id) };
+    }
+}
+
+// Renderbuffer, for framebuffer attachments that are never sampled, e.g. multisampled ones
+
+pub struct Renderbuffer {
+    id : u32,
+}
+
+impl Renderbuffer {
+    // `samples` 0 for a regular renderbuffer
+    pub unsafe fn new(width: i32, height: i32, internal_format: gl::types::GLenum, samples: i32) -> Self {
+        let mut id = 0;
+        gl::GenRenderbuffers(1, &mut id);
+        gl::BindRenderbuffer(gl::RENDERBUFFER, id);
+        gl::RenderbufferStorageMultisample(gl::RENDERBUFFER, samples, internal_format, width, height);
+        gl::BindRenderbuffer(gl::RENDERBUFFER, 0);
+        Renderbuffer { id }
+    }
+
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+}
+
+impl Drop for Renderbuffer {
+    fn drop(&mut self) {
+        unsafe { gl::DeleteRenderbuffers(1, &self.id) };
+    }
+}
+
+// Framebuffer. Doesn't own its attachments, which have to outlive it.
+
+pub struct Framebuffer {
+    id : u32,
+}
+
+impl Framebuffer {
+    pub unsafe fn new() -> Self {
+        let mut id = 0;
+        gl::GenFramebuffers(1, &mut id);
+        Framebuffer { id }
+    }
+
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+
+    pub unsafe fn bind(&self) {
+        gl::BindFramebuffer(gl::FRAMEBUFFER, self.id);
+    }
+}
+
+impl Drop for Framebuffer {
+    fn drop(&mut self) {
+        unsafe { gl::DeleteFramebuffers(1, &self.id) };
+    }
+}
+
+// Shader stage, compiled on its own and then linked into a program
+
+pub struct ShaderObject {
+    id : u32,
+}
+
+impl ShaderObject {
+    pub unsafe fn new(shader_type: gl::types::GLenum) -> Self {
+        ShaderObject { id: gl::CreateShader(shader_type) }
+    }
+
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+}
+
+impl Drop for ShaderObject {
+    fn drop(&mut self) {
+        unsafe { gl::DeleteShader(self.id) };
+    }
+}
+
+// Shader program
+
+pub struct Program {
+    id : u32,
+}
+
+impl Program {
+    pub unsafe fn new() -> Self {
+        Program { id: gl::CreateProgram() }
+    }
+
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+}
+
+impl Drop for Program {
+    fn drop(&mut self) {
+        unsafe { gl::DeleteProgram(self.id) };
+    }
+}
@@ -1,9 +1,13 @@
-use std::{mem, os::raw::c_void};
+use std::{mem, ops::Range, os::raw::c_void};
+
+use crate::gl_objects::VertexArray;
+use crate::mesh::Mesh;
+use crate::vertex_layout::{as_bytes, create_vao_with_layout};
 
 // Get the size of an arbitrary array of numbers measured in bytes
 // Example usage:  byte_size_of_array(my_array)
 pub fn byte_size_of_array<T>(val: &[T]) -> isize {
-    std::mem::size_of_val(&val[..]) as isize
+    std::mem::size_of_val(val) as isize
 }
 
 // Get the OpenGL-compatible pointer to an arbitrary array of numbers
@@ -25,93 +29,117 @@ pub fn offset<T>(n: u32) -> *const c_void {
 }
 
 // TASK 1 a)
-pub unsafe fn create_vao(vertices: &Vec<f32>, indices: &Vec<u32>, colors: &Vec<f32>, normals: &Vec<f32>) -> u32 {
-    // defining variables
-    let mut vao = 0;
-    let mut vbo = 0;
-    let mut ibo = 0;
-    let mut cbo = 0;
-    let mut nbo = 0;
-    // vertex array objects
-    gl::GenVertexArrays(1, &mut vao);
-    gl::BindVertexArray(vao);
-
-    // vertex buffer objects
-    gl::GenBuffers(1, &mut vbo);
-    gl::BindBuffer(gl::ARRAY_BUFFER, vbo);
-
-    gl::BufferData(
-        gl::ARRAY_BUFFER,
-        byte_size_of_array(vertices),
-        pointer_to_array(vertices),
-        gl::STATIC_DRAW,
-    );
-
-    gl::VertexAttribPointer(
-        0,
-        3,
-        gl::FLOAT,
-        gl::FALSE,
-        0,
-        offset::<f32>(0),
-    );
-    gl::EnableVertexAttribArray(0);
-
-    // colour buffer
-    gl::GenBuffers(1, &mut cbo);
-    gl::BindBuffer(gl::ARRAY_BUFFER, cbo);
-    gl::BufferData(
-        gl::ARRAY_BUFFER,
-        byte_size_of_array(colors),
-        pointer_to_array(colors),
-        gl::STATIC_DRAW,
-    );
-    gl::VertexAttribPointer(
-        1,
-        3,
-        gl::FLOAT,
-        gl::FALSE,
-        0,
-        offset::<f32>(0),
-    );
-    gl::EnableVertexAttribArray(1);
-
-    // normal buffer
-    gl::GenBuffers(1, &mut nbo);
-    gl::BindBuffer(gl::ARRAY_BUFFER, nbo);
-    gl::BufferData(
-        gl::ARRAY_BUFFER,
-        byte_size_of_array(normals),
-        pointer_to_array(normals),
-        gl::STATIC_DRAW,
-    );
-    gl::VertexAttribPointer(
-        2,
-        3,
-        gl::FLOAT,
-        gl::FALSE,
-        0,
-        offset::<f32>(0),
-    );
-    gl::EnableVertexAttribArray(2);
-
-    
-    //index buffer
-    gl::GenBuffers(1, &mut ibo);
-    gl::BindBuffer(gl::ELEMENT_ARRAY_BUFFER, ibo);
-
-    gl::BufferData(
-        gl::ELEMENT_ARRAY_BUFFER,
-        byte_size_of_array(indices),
-        pointer_to_array(indices),
+// The returned VAO owns its buffers, and deletes all of them when dropped
+pub unsafe fn create_vao(vertices: &[f32], indices: &[u32], colors: &[f32], normals: &[f32]) -> VertexArray {
+    create_vao_with_layout(
+        &Mesh::vertex_layout(),
+        &[as_bytes(vertices), as_bytes(colors), as_bytes(normals)],
+        indices,
         gl::STATIC_DRAW,
-    );
+    ).unwrap_or_else(|e| panic!("Failed to create VAO: {}", e))
+}
+
+// A mesh kept on both the CPU and the GPU, so that it can be edited and re-uploaded.
+// Edits go through the *_mut methods, which remember which vertices changed,
+// and are uploaded by the next call to sync().
+pub struct GpuMesh {
+    pub mesh      : Mesh,
+    pub vao       : VertexArray,
+    dirty         : [Option<Range<usize>>; 3], // Changed vertices in the position, color and normal buffers
+    dirty_indices : bool,
+}
+
+// Components per vertex in each of the mesh buffers
+const GPU_MESH_COMPONENTS: [usize; 3] = [3, 4, 3];
+
+impl GpuMesh {
+    pub unsafe fn new(mesh: Mesh) -> Self {
+        let vao = create_vao_with_layout(
+            &Mesh::vertex_layout(),
+            &[as_bytes(&mesh.vertices), as_bytes(&mesh.colors), as_bytes(&mesh.normals)],
+            &mesh.indices,
+            gl::DYNAMIC_DRAW,
+        ).unwrap_or_else(|e| panic!("Failed to create VAO: {}", e));
+        GpuMesh {
+            mesh,
+            vao,
+            dirty: [None, None, None],
+            dirty_indices: false,
+        }
+    }
+
+    fn mark_dirty(&mut self, buffer: usize, vertices: &Range<usize>) {
+        self.dirty[buffer] = Some(match self.dirty[buffer].take() {
+            Some(d) => d.start.min(vertices.start)..d.end.max(vertices.end),
+            None => vertices.clone(),
+        });
+    }
+
+    pub fn positions_mut(&mut self, vertices: Range<usize>) -> &mut [f32] {
+        self.mark_dirty(0, &vertices);
+        &mut self.mesh.vertices[vertices.start * 3..vertices.end * 3]
+    }
+
+    pub fn colors_mut(&mut self, vertices: Range<usize>) -> &mut [f32] {
+        self.mark_dirty(1, &vertices);
+        &mut self.mesh.colors[vertices.start * 4..vertices.end * 4]
+    }
+
+    pub fn normals_mut(&mut self, vertices: Range<usize>) -> &mut [f32] {
+        self.mark_dirty(2, &vertices);
+        &mut self.mesh.normals[vertices.start * 3..vertices.end * 3]
+    }
+
+    // The whole index buffer is uploaded again after this
+    pub fn indices_mut(&mut self) -> &mut Vec<u32> {
+        self.dirty_indices = true;
+        &mut self.mesh.indices
+    }
 
-    // Unbind the VAO and VBO to avoid accidental modification elsewhere
-    gl::BindBuffer(gl::ARRAY_BUFFER, 0);
-    gl::BindVertexArray(0);
+    // Swaps in a completely different mesh, possibly of another size
+    pub fn replace(&mut self, mesh: Mesh) {
+        let vertex_count = mesh.vertices.len() / 3;
+        self.mesh = mesh;
+        for buffer in 0..3 {
+            self.mark_dirty(buffer, &(0..vertex_count));
+        }
+        self.dirty_indices = true;
+    }
 
-    vao
+    /// Uploads everything that changed since the last sync.
+    pub unsafe fn sync(&mut self) {
+        if self.dirty.iter().all(|d| d.is_none()) && !self.dirty_indices {
+            return;
+        }
+        self.vao.bind();
+        let mesh = &self.mesh;
+        let data: [&[f32]; 3] = [&mesh.vertices, &mesh.colors, &mesh.normals];
+        for (i, buffer) in self.vao.buffers_mut().iter_mut().enumerate() {
+            let range = match self.dirty[i].take() {
+                Some(r) => r,
+                None => continue,
+            };
+            let n = GPU_MESH_COMPONENTS[i];
+            buffer.bind();
+            if byte_size_of_array(data[i]) != buffer.size() {
+                buffer.orphan_and_upload(data[i], gl::DYNAMIC_DRAW);
+            } else {
+                buffer.update(byte_size_of_array(&data[i][..range.start * n]), &data[i][range.start * n..range.end * n]);
+            }
+        }
+        if self.dirty_indices {
+            let index_count = mesh.indices.len() as i32;
+            if let Some(buffer) = self.vao.index_buffer_mut() {
+                buffer.bind();
+                buffer.orphan_and_upload(&mesh.indices, gl::DYNAMIC_DRAW);
+            }
+            self.vao.set_index_count(index_count);
+            self.mesh.index_count = index_count;
+            self.dirty_indices = false;
+        }
+        gl::BindBuffer(gl::ARRAY_BUFFER, 0);
+        gl::BindVertexArray(0);
+    }
 }
 
 pub unsafe fn setup_opengl() {
@@ -0,0 +1,194 @@
+extern crate nalgebra_glm as glm;
+
+use crate::bvh::{Bvh, Ray, SplitMethod};
+use crate::mesh::Mesh;
+
+/// The result of querying the terrain at a point in the XZ plane.
+#[derive(Clone, Copy, Debug)]
+pub struct TerrainSample {
+    pub height : f32,
+    pub normal : glm::Vec3,
+}
+
+/// Height and normal lookups on a terrain mesh.
+///
+/// A query shoots a ray straight down through a BVH over the triangles of the mesh, so it only
+/// has to look at the handful of triangles near the point instead of the whole mesh.
+/// The terrain is assumed to be placed in the world without any transformation.
+pub struct HeightField {
+    bvh     : Bvh,
+    normals : Vec<glm::Vec3>, // One per vertex, or none to use the normals of the triangles
+}
+
+impl HeightField {
+    pub fn new(mesh: &Mesh) -> Self {
+        // Vertex normals are only usable if there is exactly one per position
+        let normals: Vec<glm::Vec3> = if mesh.normals.len() == mesh.vertices.len() {
+            mesh.normals
+                .chunks_exact(3)
+                .map(|n| glm::vec3(n[0], n[1], n[2]))
+                .collect()
+        } else {
+            vec![]
+        };
+
+        HeightField {
+            bvh: Bvh::new(mesh, SplitMethod::Sah),
+            normals,
+        }
+    }
+
+    /// Interpolated height and surface normal at (x, z), or None outside of the terrain.
+    /// Where several surfaces overlap, the highest one is returned.
+    pub fn sample(&self, x: f32, z: f32) -> Option<TerrainSample> {
+        let bounds = self.bvh.bounds();
+        if bounds.is_empty() || x < bounds.min.x || z < bounds.min.z || x > bounds.max.x || z > bounds.max.z {
+            return None;
+        }
+
+        // The first surface hit from above is the highest one
+        let ray = Ray::new(glm::vec3(x, bounds.max.y + 1.0, z), glm::vec3(0.0, -1.0, 0.0));
+        let hit = self.bvh.intersect_ray(&ray, f32::INFINITY)?;
+        let [a, b, c] = self.bvh.triangle_positions(hit.triangle);
+        let weights = glm::vec3(1.0 - hit.u - hit.v, hit.u, hit.v);
+        let height = weights.x * a.y + weights.y * b.y + weights.z * c.y;
+
+        let mut normal = if self.normals.is_empty() {
+            glm::cross(&(b - a), &(c - a))
+        } else {
+            let [ia, ib, ic] = self.bvh.triangle_indices(hit.triangle).map(|i| i as usize);
+            self.normals[ia] * weights.x + self.normals[ib] * weights.y + self.normals[ic] * weights.z
+        };
+        if normal.y < 0.0 {
+            normal = -normal;
+        }
+        let normal = if glm::length(&normal) > f32::EPSILON {
+            glm::normalize(&normal)
+        } else {
+            glm::vec3(0.0, 1.0, 0.0)
+        };
+
+        Some(TerrainSample { height, normal })
+    }
+
+    pub fn height_at(&self, x: f32, z: f32) -> Option<f32> {
+        self.sample(x, z).map(|s| s.height)
+    }
+
+    pub fn normal_at(&self, x: f32, z: f32) -> Option<glm::Vec3> {
+        self.sample(x, z).map(|s| s.normal)
+    }
+
+    /// Lower and upper corners of the terrain in the XZ plane, as (x, z).
+    pub fn bounds(&self) -> (glm::Vec2, glm::Vec2) {
+        let bounds = self.bvh.bounds();
+        if bounds.is_empty() {
+            return (glm::zero(), glm::zero());
+        }
+        (glm::vec2(bounds.min.x, bounds.min.z), glm::vec2(bounds.max.x, bounds.max.z))
+    }
+
+    /// Heights on a regular grid of `width` by `height` points spanning the bounds, row-major in z.
+    /// Points outside of the terrain get the lowest height found.
+    pub fn rasterize(&self, width: usize, height: usize) -> Vec<f32> {
+        let (min, max) = self.bounds();
+        let step = glm::vec2(
+            (max.x - min.x) / (width.max(2) - 1) as f32,
+            (max.y - min.y) / (height.max(2) - 1) as f32,
+        );
+        let heights: Vec<Option<f32>> = (0..width * height)
+            .map(|i| self.height_at(min.x + (i % width) as f32 * step.x, min.y + (i / width) as f32 * step.y))
+            .collect();
+        let lowest = heights.iter().flatten().fold(f32::INFINITY, |a, &b| a.min(b));
+        let lowest = if lowest.is_finite() { lowest } else { 0.0 };
+        heights.iter().map(|h| h.unwrap_or(lowest)).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A 2x2 cell grid over [0, 2] x [0, 2], each cell split along its diagonal
+    fn grid(height: impl Fn(f32, f32) -> f32, normals: Vec<f32>) -> Mesh {
+        let mut vertices = vec![];
+        for z in 0..3 {
+            for x in 0..3 {
+                let (x, z) = (x as f32, z as f32);
+                vertices.extend_from_slice(&[x, height(x, z), z]);
+            }
+        }
+        let mut indices = vec![];
+        for z in 0..2 {
+            for x in 0..2 {
+                let i = z * 3 + x;
+                indices.extend_from_slice(&[i, i + 3, i + 1, i + 1, i + 3, i + 4]);
+            }
+        }
+        Mesh {
+            colors: vec![1.0; vertices.len() / 3 * 4],
+            index_count: indices.len() as i32,
+            vertices,
+            normals,
+            indices,
+        }
+    }
+
+    fn assert_close(actual: f32, expected: f32) {
+        assert!((actual - expected).abs() < 1e-5, "expected {}, got {}", expected, actual);
+    }
+
+    #[test]
+    fn plane_is_interpolated_exactly() {
+        let field = HeightField::new(&grid(|x, z| 0.5 * x + 0.25 * z, vec![]));
+        for &(x, z) in &[(0.5, 1.5), (0.0, 0.0), (2.0, 2.0), (1.3, 0.2), (1.0, 1.0)] {
+            assert_close(field.height_at(x, z).unwrap(), 0.5 * x + 0.25 * z);
+        }
+    }
+
+    #[test]
+    fn plane_normal_comes_from_the_triangles() {
+        let field = HeightField::new(&grid(|x, z| 0.5 * x + 0.25 * z, vec![]));
+        let normal = field.normal_at(0.7, 1.2).unwrap();
+        let expected = glm::normalize(&glm::vec3(-0.5, 1.0, -0.25));
+        for axis in 0..3 {
+            assert_close(normal[axis], expected[axis]);
+        }
+    }
+
+    #[test]
+    fn vertex_normals_are_interpolated() {
+        let normals = [0.0, 1.0, 0.0].repeat(9);
+        let field = HeightField::new(&grid(|x, z| x * z, normals));
+        let normal = field.normal_at(1.5, 0.5).unwrap();
+        assert_close(normal.y, 1.0);
+    }
+
+    #[test]
+    fn peak_is_interpolated_along_edges() {
+        let field = HeightField::new(&grid(|x, z| if x == 1.0 && z == 1.0 { 1.0 } else { 0.0 }, vec![]));
+        assert_close(field.height_at(1.0, 1.0).unwrap(), 1.0);
+        assert_close(field.height_at(1.0, 0.5).unwrap(), 0.5);
+        assert_close(field.height_at(0.5, 1.0).unwrap(), 0.5);
+        assert_close(field.height_at(2.0, 0.0).unwrap(), 0.0);
+    }
+
+    #[test]
+    fn outside_is_none() {
+        let field = HeightField::new(&grid(|_, _| 3.0, vec![]));
+        assert!(field.sample(-0.1, 1.0).is_none());
+        assert!(field.sample(1.0, 2.1).is_none());
+        assert!(field.height_at(2.5, 2.5).is_none());
+        let (min, max) = field.bounds();
+        assert_close(min.x, 0.0);
+        assert_close(max.y, 2.0);
+    }
+
+    #[test]
+    fn mesh_without_triangles_has_no_samples() {
+        let mut mesh = grid(|_, _| 0.0, vec![]);
+        mesh.indices.clear();
+        mesh.index_count = 0;
+        assert!(HeightField::new(&mesh).sample(1.0, 1.0).is_none());
+    }
+}
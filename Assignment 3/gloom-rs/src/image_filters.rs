@@ -0,0 +1,389 @@
+extern crate nalgebra_glm as glm;
+
+use image::{ImageBuffer, Luma, Rgba, RgbaImage};
+use crate::gl_objects::{Buffer, Texture};
+use crate::post_processing::{self, FULLSCREEN_VERTEX_SHADER};
+use crate::render_target::{RenderTarget, RenderTargetDescription};
+use crate::shader::{self, ShaderError};
+use crate::shader_reload::{self, ReloadingShader};
+
+// Gaussian smoothing, Sobel edge detection and Otsu thresholding, both as GPU passes over
+// rendered frames and as CPU functions over images. All of them work on the luminance and
+// extend the image edges outwards, so both versions agree up to rounding.
+
+pub type GrayImageF32 = ImageBuffer<Luma<f32>, Vec<f32>>;
+
+pub const OTSU_BINS: usize = 256;  // Has to match otsu.glsl
+const OTSU_BINDING: u32 = 0;       // The binding of the OtsuData block in otsu.glsl
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FilterKind {
+    Gaussian,
+    Sobel,
+    Otsu,
+}
+
+impl FilterKind {
+    pub const ALL: [FilterKind; 3] = [FilterKind::Gaussian, FilterKind::Sobel, FilterKind::Otsu];
+}
+
+// CPU versions
+
+// Has to match luminance.glsl
+pub fn luminance(image: &RgbaImage) -> GrayImageF32 {
+    GrayImageF32::from_fn(image.width(), image.height(), |x, y| {
+        let Rgba([r, g, b, _]) = *image.get_pixel(x, y);
+        Luma([0.299 * (r as f32 / 255.0) + 0.587 * (g as f32 / 255.0) + 0.114 * (b as f32 / 255.0)])
+    })
+}
+
+// Pixel values outside the image are those of the nearest edge pixel
+fn clamped(image: &GrayImageF32, x: i64, y: i64) -> f32 {
+    let x = x.clamp(0, image.width() as i64 - 1) as u32;
+    let y = y.clamp(0, image.height() as i64 - 1) as u32;
+    image.get_pixel(x, y)[0]
+}
+
+/// Blurs with a Gaussian of the given standard deviation in pixels, cut off at 3 sigma.
+pub fn gaussian_blur(image: &GrayImageF32, sigma: f32) -> GrayImageF32 {
+    let radius = (3.0 * sigma).ceil() as i64;
+    let kernel: Vec<f32> = (-radius..=radius)
+        .map(|i| (-((i * i) as f32) / (2.0 * sigma * sigma)).exp())
+        .collect();
+    let total: f32 = kernel.iter().sum();
+
+    // Separably, one direction after the other
+    let blur = |image: &GrayImageF32, (dx, dy): (i64, i64)| {
+        GrayImageF32::from_fn(image.width(), image.height(), |x, y| {
+            let sum: f32 = (-radius..=radius)
+                .zip(&kernel)
+                .map(|(i, weight)| clamped(image, x as i64 + dx * i, y as i64 + dy * i) * weight)
+                .sum();
+            Luma([sum / total])
+        })
+    };
+    blur(&blur(image, (1, 0)), (0, 1))
+}
+
+/// Magnitude of the gradient found by the 3x3 Sobel operators.
+pub fn sobel(image: &GrayImageF32) -> GrayImageF32 {
+    GrayImageF32::from_fn(image.width(), image.height(), |x, y| {
+        let s = |dx: i64, dy: i64| clamped(image, x as i64 + dx, y as i64 + dy);
+        let gx = (s(1, -1) + 2.0 * s(1, 0) + s(1, 1)) - (s(-1, -1) + 2.0 * s(-1, 0) + s(-1, 1));
+        let gy = (s(-1, 1) + 2.0 * s(0, 1) + s(1, 1)) - (s(-1, -1) + 2.0 * s(0, -1) + s(1, -1));
+        Luma([(gx * gx + gy * gy).sqrt()])
+    })
+}
+
+pub fn otsu_bin(value: f32) -> usize {
+    ((value * OTSU_BINS as f32) as i64).clamp(0, OTSU_BINS as i64 - 1) as usize
+}
+
+/// The threshold separating the pixels into two classes with the largest variance between them.
+/// Pixels at least this bright are foreground.
+pub fn otsu_threshold(image: &GrayImageF32) -> f32 {
+    let mut bins = [0u64; OTSU_BINS];
+    for pixel in image.pixels() {
+        bins[otsu_bin(pixel[0])] += 1;
+    }
+
+    let total: f64 = bins.iter().map(|&n| n as f64).sum();
+    let sum: f64 = bins.iter().enumerate().map(|(i, &n)| i as f64 * n as f64).sum();
+    let mut background_weight = 0.0;
+    let mut background_sum = 0.0;
+    let mut best = (-1.0, 0);
+    for (t, &n) in bins.iter().enumerate() {
+        background_weight += n as f64;
+        background_sum += t as f64 * n as f64;
+        let foreground_weight = total - background_weight;
+        if background_weight == 0.0 {
+            continue;
+        }
+        if foreground_weight == 0.0 {
+            break;
+        }
+        let difference = background_sum / background_weight - (sum - background_sum) / foreground_weight;
+        let variance = background_weight * foreground_weight * difference * difference;
+        if variance > best.0 {
+            best = (variance, t);
+        }
+    }
+    (best.1 + 1) as f32 / OTSU_BINS as f32
+}
+
+// 1 where the image reaches the threshold, 0 elsewhere
+pub fn threshold(image: &GrayImageF32, threshold: f32) -> GrayImageF32 {
+    GrayImageF32::from_fn(image.width(), image.height(), |x, y| {
+        Luma([if image.get_pixel(x, y)[0] >= threshold { 1.0 } else { 0.0 }])
+    })
+}
+
+// GPU versions
+
+pub struct GpuFilters {
+    pub sigma      : f32,              // Of the Gaussian blur, in pixels
+    gaussian       : ReloadingShader,
+    sobel          : ReloadingShader,
+    otsu_histogram : ReloadingShader,
+    otsu_threshold : ReloadingShader,
+    otsu_apply     : ReloadingShader,
+    otsu_data      : Buffer,           // See OtsuData in otsu.glsl
+    scratch        : RenderTarget,     // Between the two directions of the blur, gray in all channels
+}
+
+impl GpuFilters {
+    pub unsafe fn new() -> Result<Self, ShaderError> {
+        let pass = |fragment| ReloadingShader::new(&[FULLSCREEN_VERTEX_SHADER, fragment], shader_reload::no_check);
+        let compute = |path| ReloadingShader::new(&[path], shader_reload::no_check);
+        let otsu_data = Buffer::with_data(gl::SHADER_STORAGE_BUFFER, &[0u32; OTSU_BINS + 1], gl::DYNAMIC_DRAW);
+        gl::BindBuffer(gl::SHADER_STORAGE_BUFFER, 0);
+        let scratch = RenderTarget::new(&RenderTargetDescription::new().color(gl::RGBA32F), 1, 1)
+            .unwrap_or_else(|e| panic!("{}", e));
+        Ok(GpuFilters {
+            sigma: 2.0,
+            gaussian: pass("shaders/post/gaussian.frag")?,
+            sobel: pass("shaders/post/sobel.frag")?,
+            otsu_histogram: compute("shaders/post/otsu_histogram.comp")?,
+            otsu_threshold: compute("shaders/post/otsu_threshold.comp")?,
+            otsu_apply: pass("shaders/post/otsu_apply.frag")?,
+            otsu_data,
+            scratch,
+        })
+    }
+
+    /// Draws the filtered luminance of `input` into all of `output`, which has to be the same size.
+    /// A VAO has to be bound, and depth testing and blending switched off.
+    pub unsafe fn apply(&mut self, kind: FilterKind, input: &Texture, output: &RenderTarget) {
+        match kind {
+            FilterKind::Gaussian => {
+                if let Err(e) = self.scratch.resize(input.width(), input.height()) {
+                    panic!("Failed to resize the blur target: {}", e);
+                }
+                self.gaussian.set_f32("uSigma", self.sigma);
+                self.scratch.bind();
+                self.gaussian.set_vec2("uDirection", &glm::vec2(1.0, 0.0));
+                post_processing::draw_fullscreen(&self.gaussian, &[("uInput", input)]);
+                output.bind();
+                self.gaussian.set_vec2("uDirection", &glm::vec2(0.0, 1.0));
+                post_processing::draw_fullscreen(&self.gaussian, &[("uInput", self.scratch.color(0))]);
+            },
+            FilterKind::Sobel => {
+                output.bind();
+                post_processing::draw_fullscreen(&self.sobel, &[("uInput", input)]);
+            },
+            FilterKind::Otsu => {
+                // Histogram, then the threshold from it, then the thresholding itself
+                self.otsu_data.bind();
+                gl::ClearBufferData(gl::SHADER_STORAGE_BUFFER, gl::R32UI, gl::RED_INTEGER, gl::UNSIGNED_INT, std::ptr::null());
+                gl::BindBuffer(gl::SHADER_STORAGE_BUFFER, 0);
+                self.otsu_data.bind_base(OTSU_BINDING);
+                self.otsu_histogram.set_texture("uInput", 0, gl::TEXTURE_2D, input.id());
+                self.otsu_histogram.dispatch_invocations([input.width() as u32, input.height() as u32, 1]);
+                shader::memory_barrier(gl::SHADER_STORAGE_BARRIER_BIT);
+                self.otsu_threshold.dispatch([1, 1, 1]);
+                shader::memory_barrier(gl::SHADER_STORAGE_BARRIER_BIT);
+                output.bind();
+                post_processing::draw_fullscreen(&self.otsu_apply, &[("uInput", input)]);
+            },
+        }
+    }
+
+    // The threshold found by the latest Otsu pass
+    pub unsafe fn otsu_threshold(&self) -> f32 {
+        let data: Vec<u32> = self.otsu_data.read();
+        gl::BindBuffer(gl::SHADER_STORAGE_BUFFER, 0);
+        f32::from_bits(data[OTSU_BINS])
+    }
+
+    /// Rebuilds the shaders whose files were edited, see ReloadingShader::poll
+    pub unsafe fn reload_shaders(&mut self) -> Vec<(Result<(), ShaderError>, String)> {
+        let shaders = vec![
+            &mut self.gaussian, &mut self.sobel,
+            &mut self.otsu_histogram, &mut self.otsu_threshold, &mut self.otsu_apply,
+        ];
+        shaders.into_iter()
+            .filter_map(|shader| shader.poll().map(|result| (result, shader.paths().join(", "))))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{Rng, SeedableRng};
+
+    use crate::gl_objects::VertexArray;
+
+    fn gray(width: u32, height: u32, f: impl Fn(u32, u32) -> f32) -> GrayImageF32 {
+        GrayImageF32::from_fn(width, height, |x, y| Luma([f(x, y)]))
+    }
+
+    fn constant() -> GrayImageF32 {
+        gray(16, 8, |_, _| 0.4)
+    }
+
+    // Dark on the left half, bright on the right
+    fn step_edge() -> GrayImageF32 {
+        gray(16, 8, |x, _| if x < 8 { 0.0 } else { 1.0 })
+    }
+
+    // A dark and a bright cluster of values, three times as many dark ones
+    fn bimodal() -> GrayImageF32 {
+        gray(16, 16, |x, y| {
+            let spread = ((x * 7 + y * 3) % 11) as f32 / 10.0 * 0.1;
+            if (x + y) % 4 == 0 { 0.65 + spread } else { 0.15 + spread }
+        })
+    }
+
+    #[test]
+    fn gaussian_blur_keeps_a_constant_image() {
+        for pixel in gaussian_blur(&constant(), 2.0).pixels() {
+            assert!((pixel[0] - 0.4).abs() < 1e-6);
+        }
+    }
+
+    #[test]
+    fn gaussian_blur_smooths_a_step_edge_symmetrically() {
+        let blurred = gaussian_blur(&step_edge(), 1.0);
+        for y in 0..blurred.height() {
+            let row: Vec<f32> = (0..blurred.width()).map(|x| blurred.get_pixel(x, y)[0]).collect();
+            assert!(row.windows(2).all(|w| w[0] <= w[1]));
+            // Out of reach of the 3 sigma kernel, the sides stay as they were
+            assert!(row[..5].iter().all(|&v| v == 0.0));
+            assert!(row[11..].iter().all(|&v| (v - 1.0).abs() < 1e-6));
+            for i in 0..8 {
+                assert!((row[7 - i] + row[8 + i] - 1.0).abs() < 1e-6);
+            }
+            assert!(row[7] > 0.2 && row[8] < 0.8);
+        }
+    }
+
+    #[test]
+    fn sobel_finds_no_gradient_in_a_constant_image() {
+        assert!(sobel(&constant()).pixels().all(|pixel| pixel[0] == 0.0));
+    }
+
+    #[test]
+    fn sobel_finds_the_step_edge() {
+        let edges = sobel(&step_edge());
+        for (x, _, pixel) in edges.enumerate_pixels() {
+            let expected = if x == 7 || x == 8 { 4.0 } else { 0.0 };
+            assert_eq!(pixel[0], expected, "at x = {}", x);
+        }
+    }
+
+    #[test]
+    fn otsu_keeps_a_constant_image_in_one_class() {
+        let image = constant();
+        let classes = threshold(&image, otsu_threshold(&image));
+        assert!(classes.pixels().all(|pixel| pixel[0] == classes.get_pixel(0, 0)[0]));
+    }
+
+    #[test]
+    fn otsu_separates_a_step_edge() {
+        let image = step_edge();
+        assert_eq!(threshold(&image, otsu_threshold(&image)), image);
+    }
+
+    #[test]
+    fn otsu_threshold_falls_between_the_modes() {
+        let image = bimodal();
+        let t = otsu_threshold(&image);
+        assert!(t > 0.25 && t <= 0.65, "threshold {}", t);
+        let classes = threshold(&image, t);
+        for (x, y, pixel) in classes.enumerate_pixels() {
+            let bright = (x + y) % 4 == 0;
+            assert_eq!(pixel[0], if bright { 1.0 } else { 0.0 });
+        }
+    }
+
+    #[test]
+    fn threshold_counts_the_threshold_as_foreground() {
+        let image = gray(3, 1, |x, _| x as f32 * 0.25);
+        let classes = threshold(&image, 0.25);
+        assert_eq!(classes.into_raw(), vec![0.0, 1.0, 1.0]);
+    }
+
+    // Smooth gradients with noise and some sharp edged disks, giving every filter something to do
+    fn test_image(width: u32, height: u32, seed: u64) -> RgbaImage {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(seed);
+        let disks: Vec<(f32, f32, f32, [u8; 3])> = (0..12)
+            .map(|_| (
+                rng.gen_range(0.0..width as f32),
+                rng.gen_range(0.0..height as f32),
+                rng.gen_range(5.0..40.0),
+                [rng.gen(), rng.gen(), rng.gen()],
+            ))
+            .collect();
+        RgbaImage::from_fn(width, height, |x, y| {
+            let (fx, fy) = (x as f32, y as f32);
+            let mut color = [
+                (255.0 * fx / width as f32) as u8,
+                (255.0 * fy / height as f32) as u8,
+                96u8.saturating_add(rng.gen_range(0..32)),
+            ];
+            for &(cx, cy, radius, disk_color) in &disks {
+                if (fx - cx).powi(2) + (fy - cy).powi(2) < radius * radius {
+                    color = disk_color;
+                }
+            }
+            Rgba([color[0], color[1], color[2], 255])
+        })
+    }
+
+    // Runs every filter on the GPU and the CPU over the same image, and compares the results
+    #[test]
+    #[ignore = "needs a GPU"]
+    fn gpu_filters_match_cpu() {
+        const WIDTH: u32 = 333; // Not a multiple of the work group size, on purpose
+        const HEIGHT: u32 = 250;
+        const SIGMA: f32 = 2.5;
+        // The GPU may round differently, and evaluate exp() less precisely
+        const TOLERANCE: f32 = 1e-3;
+        // Pixels right at a bin boundary may end up in the neighbouring bin
+        const OTSU_MISMATCH_TOLERANCE: f32 = 1e-3;
+
+        let image = test_image(WIDTH, HEIGHT, 1234);
+        let gray = luminance(&image);
+
+        unsafe {
+            crate::test_context::make_current();
+            // Textures hold the bottom row first
+            let flipped = image::imageops::flip_vertical(&image);
+            let input = Texture::with_data_2d(WIDTH as i32, HEIGHT as i32, gl::RGBA8, gl::RGBA, gl::UNSIGNED_BYTE, flipped.as_raw());
+            let output = RenderTarget::new(&RenderTargetDescription::new().color(gl::R32F), WIDTH as i32, HEIGHT as i32)
+                .unwrap_or_else(|e| panic!("{}", e));
+            let mut filters = GpuFilters::new().unwrap_or_else(|e| panic!("{}", e));
+            filters.sigma = SIGMA;
+            let vao = VertexArray::new();
+            vao.bind();
+            gl::Disable(gl::DEPTH_TEST);
+            gl::Disable(gl::BLEND);
+
+            for kind in FilterKind::ALL.iter().copied() {
+                filters.apply(kind, &input, &output);
+                let actual = output.read_red_f32();
+                let expected = match kind {
+                    FilterKind::Gaussian => gaussian_blur(&gray, SIGMA),
+                    FilterKind::Sobel => sobel(&gray),
+                    FilterKind::Otsu => threshold(&gray, otsu_threshold(&gray)),
+                };
+
+                let differences: Vec<f32> = actual.pixels().zip(expected.pixels()).map(|(a, e)| (a[0] - e[0]).abs()).collect();
+                if kind == FilterKind::Otsu {
+                    let (gpu, cpu) = (filters.otsu_threshold(), otsu_threshold(&gray));
+                    assert!((gpu - cpu).abs() <= 1.0 / OTSU_BINS as f32, "threshold {} on the GPU, {} on the CPU", gpu, cpu);
+                    let mismatched = differences.iter().filter(|&&d| d > 0.5).count() as f32 / differences.len() as f32;
+                    assert!(mismatched <= OTSU_MISMATCH_TOLERANCE, "{}% of the pixels differ", mismatched * 100.0);
+                } else {
+                    let (worst, i) = differences.iter().enumerate().fold((0.0f32, 0), |w, (i, &d)| if d > w.0 { (d, i) } else { w });
+                    assert!(
+                        worst <= TOLERANCE,
+                        "{:?}: largest difference {:e} at pixel {}, {}", kind, worst, i as u32 % WIDTH, i as u32 / WIDTH,
+                    );
+                }
+            }
+            gl::BindVertexArray(0);
+        }
+    }
+}
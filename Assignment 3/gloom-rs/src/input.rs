@@ -9,6 +9,10 @@ use crate::camera::Camera;
 pub struct InputHandler {
     /// Legacy field for testing - can be removed in final version
     pub arbitrary_number: f32,
+    /// Keys held down during the previous frame, to tell when a key goes down
+    previous_keys: Vec<VirtualKeyCode>,
+    /// Keys that went down this frame
+    pressed_keys: Vec<VirtualKeyCode>,
 }
 
 impl InputHandler {
@@ -16,8 +20,23 @@ impl InputHandler {
     pub fn new() -> Self {
         InputHandler {
             arbitrary_number: 0.0,
+            previous_keys: Vec::new(),
+            pressed_keys: Vec::new(),
         }
     }
+}
+
+impl Default for InputHandler {
+    fn default() -> Self {
+        InputHandler::new()
+    }
+}
+
+impl InputHandler {
+    /// Whether the key went down this frame, for toggles that should only flip once per press
+    pub fn key_pressed(&self, key: VirtualKeyCode) -> bool {
+        self.pressed_keys.contains(&key)
+    }
 
 
     /// All movements are scaled by delta_time to ensure consistent speed regardless of framerate.
@@ -27,6 +46,9 @@ impl InputHandler {
         let move_speed = 50.0;        // Translation speed (world units per second)
         let rotation_speed = 3.0;    // Rotation speed (radians per second)
 
+        self.pressed_keys = keys.iter().filter(|k| !self.previous_keys.contains(k)).copied().collect();
+        self.previous_keys = keys.to_vec();
+
         // Process each currently pressed key
         for key in keys.iter() {
             match key {
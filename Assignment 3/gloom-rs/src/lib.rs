@@ -0,0 +1,49 @@
+// Uncomment these following global attributes to silence most warnings of "low" interest:
+#![allow(dead_code)]
+#![allow(non_snake_case)]
+#![allow(unreachable_code)]
+#![allow(unused_mut)]
+#![allow(unused_unsafe)]
+#![allow(unused_variables)]
+// The unsafe functions are all unsafe for the same reason: they call OpenGL, which needs a
+// current context on the calling thread
+#![allow(clippy::missing_safety_doc)]
+
+// Everything the viewer in main.rs is built from, as a library so that benchmarks can use it too
+
+extern crate nalgebra_glm as glm;
+
+pub mod shader;
+pub mod shader_reload;
+pub mod shader_preprocessor;
+pub mod shader_library;
+pub mod material;
+pub mod light;
+pub mod util;
+pub mod graphics;
+pub mod gl_objects;
+pub mod vertex_layout;
+pub mod debug_lines;
+pub mod uniform_buffer;
+pub mod scene;
+pub mod camera;
+pub mod renderer;
+pub mod input;
+pub mod mesh;
+pub mod mesh_validation;
+pub mod articulated;
+pub mod heightfield;
+pub mod terrain_tessellation;
+pub mod shadow;
+pub mod render_target;
+pub mod post_processing;
+pub mod image_filters;
+pub mod capture;
+pub mod clock;
+pub mod bvh;
+pub mod particles;
+pub mod scene_graph;
+pub mod toolbox;
+
+#[cfg(test)]
+mod test_context;
@@ -0,0 +1,132 @@
+extern crate nalgebra_glm as glm;
+
+use crate::uniform_buffer::LightUniform;
+
+// Light sources attached to scene nodes. A light sits at the origin of its node and shines
+// along `direction`, both in the node's space, so it moves and turns along with the node.
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LightKind {
+    Directional,                // Infinitely far away, like the sun
+    Point { range: f32 },       // Shining in all directions, fading out towards the range
+    Spot {
+        range       : f32,
+        inner_angle : f32,      // Half angle of the fully lit cone, in radians
+        outer_angle : f32,      // Half angle beyond which nothing is lit
+    },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Light {
+    pub kind      : LightKind,
+    pub direction : glm::Vec3,  // Unused by point lights
+    pub color     : glm::Vec3,
+    pub intensity : f32,        // For point and spot lights, as seen from one unit away
+}
+
+impl Light {
+    pub fn directional(direction: glm::Vec3, color: glm::Vec3, intensity: f32) -> Self {
+        Light { kind: LightKind::Directional, direction, color, intensity }
+    }
+
+    pub fn point(color: glm::Vec3, intensity: f32, range: f32) -> Self {
+        Light { kind: LightKind::Point { range }, direction: glm::vec3(0.0, -1.0, 0.0), color, intensity }
+    }
+
+    pub fn spot(direction: glm::Vec3, color: glm::Vec3, intensity: f32, range: f32, inner_angle: f32, outer_angle: f32) -> Self {
+        Light { kind: LightKind::Spot { range, inner_angle, outer_angle }, direction, color, intensity }
+    }
+
+    /// The light as the shaders see it, placed by the world transform of its node.
+    pub fn in_world(&self, world: &glm::Mat4) -> LightUniform {
+        let position = world * glm::vec4(0.0, 0.0, 0.0, 1.0);
+        let direction = glm::normalize(&(world * self.direction.push(0.0)).xyz());
+        let (kind, range, cone) = match self.kind {
+            LightKind::Directional => (LightUniform::DIRECTIONAL, 0.0, [0.0; 2]),
+            LightKind::Point { range } => (LightUniform::POINT, range, [0.0; 2]),
+            LightKind::Spot { range, inner_angle, outer_angle } => {
+                (LightUniform::SPOT, range, [outer_angle.cos(), inner_angle.min(outer_angle).cos()])
+            },
+        };
+        LightUniform {
+            position: [position.x, position.y, position.z, kind],
+            direction: [direction.x, direction.y, direction.z, range],
+            color: [self.color.x, self.color.y, self.color.z, self.intensity],
+            cone: [cone[0], cone[1], 0.0, 0.0],
+        }
+    }
+}
+
+/// Orders lights by how much they are likely to matter around the camera: directional lights
+/// first, then the others by how far the camera is outside their range.
+pub fn sort_by_relevance(lights: &mut [LightUniform], camera_position: &glm::Vec3) {
+    let key = |light: &LightUniform| {
+        if light.position[3] == LightUniform::DIRECTIONAL {
+            return f32::NEG_INFINITY;
+        }
+        let position = glm::vec3(light.position[0], light.position[1], light.position[2]);
+        glm::distance(&position, camera_position) - light.direction[3]
+    };
+    lights.sort_by(|a, b| key(a).total_cmp(&key(b)));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::uniform_buffer::{FrameUniforms, MAX_LIGHTS};
+
+    fn assert_close(a: f32, b: f32) {
+        assert!((a - b).abs() < 1e-5, "{} != {}", a, b);
+    }
+
+    #[test]
+    fn spot_lights_turn_with_their_parent() {
+        let spot = Light::spot(glm::vec3(0.0, 0.0, -1.0), glm::vec3(1.0, 1.0, 1.0), 2.0, 30.0, 0.2, 0.4);
+        // Scaled too, which must not change the length of the direction
+        let world = glm::translation(&glm::vec3(1.0, 2.0, 3.0))
+            * glm::rotation(std::f32::consts::FRAC_PI_2, &glm::vec3(0.0, 1.0, 0.0))
+            * glm::scaling(&glm::vec3(2.0, 2.0, 2.0));
+        let light = spot.in_world(&world);
+
+        assert_eq!(light.position, [1.0, 2.0, 3.0, LightUniform::SPOT]);
+        // Looking down -z, turned a quarter to the left, it looks down -x
+        assert_close(light.direction[0], -1.0);
+        assert_close(light.direction[1], 0.0);
+        assert_close(light.direction[2], 0.0);
+        assert_eq!(light.direction[3], 30.0);
+        assert_close(light.cone[0], 0.4f32.cos());
+        assert_close(light.cone[1], 0.2f32.cos());
+    }
+
+    #[test]
+    fn the_most_relevant_lights_are_kept() {
+        // Point lights 10 units apart along x, told apart by their intensity
+        let mut lights: Vec<LightUniform> = (0..MAX_LIGHTS + 4)
+            .rev()
+            .map(|i| {
+                let world = glm::translation(&glm::vec3(i as f32 * 10.0, 0.0, 0.0));
+                Light::point(glm::vec3(1.0, 1.0, 1.0), i as f32, 8.0).in_world(&world)
+            })
+            .collect();
+        // Far away, but reaching almost all the way to the camera
+        let far_reaching = Light::point(glm::vec3(1.0, 1.0, 1.0), 100.0, 497.0);
+        lights.push(far_reaching.in_world(&glm::translation(&glm::vec3(500.0, 0.0, 0.0))));
+        let sun = Light::directional(glm::vec3(0.0, -1.0, 0.0), glm::vec3(1.0, 1.0, 1.0), 1.0);
+        lights.push(sun.in_world(&glm::Mat4::identity()));
+
+        sort_by_relevance(&mut lights, &glm::vec3(0.0, 0.0, 0.0));
+        let order: Vec<f32> = lights.iter().map(|light| light.color[3]).collect();
+        assert_eq!(lights[0].position[3], LightUniform::DIRECTIONAL);
+        assert_eq!(&order[1..5], &[0.0, 1.0, 100.0, 2.0]);
+        assert_eq!(&order[5..], &(3..MAX_LIGHTS + 4).map(|i| i as f32).collect::<Vec<_>>()[..]);
+
+        let mut frame = FrameUniforms::new(&glm::Mat4::identity(), &glm::Mat4::identity(), &glm::vec3(0.0, 0.0, 0.0), 0.0);
+        let added = lights.iter().take_while(|light| frame.add_light(light)).count();
+        assert_eq!(added, MAX_LIGHTS);
+        assert_eq!(frame.light_count as usize, MAX_LIGHTS);
+        // The sun, the far reaching light and the closest point lights, the farthest ones are cut
+        let kept: Vec<f32> = frame.lights.iter().map(|light| light.color[3]).collect();
+        assert_eq!(&kept[..], &order[..MAX_LIGHTS]);
+        assert_eq!(kept[MAX_LIGHTS - 1], (MAX_LIGHTS - 3) as f32);
+    }
+}
@@ -7,20 +7,11 @@
 #![allow(unused_variables)]
 
 extern crate nalgebra_glm as glm;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{Arc, Mutex, RwLock};
 use std::thread;
 //use std::ptr;
 
-mod shader;
-mod util;
-mod graphics;
-mod scene;
-mod camera;
-mod renderer;
-mod input;
-mod mesh;
-mod scene_graph;
-mod toolbox;
 use glutin::event::{
     DeviceEvent,
     ElementState::{Pressed, Released},
@@ -30,17 +21,46 @@ use glutin::event::{
 };
 use glutin::event_loop::ControlFlow;
 
-use camera::Camera;
-use scene::Scene;
-use renderer::Renderer;
-use input::InputHandler;
-use scene_graph::SceneNode;
+use gloom_rs::{capture, graphics, util};
+use gloom_rs::camera::Camera;
+use gloom_rs::scene::Scene;
+use gloom_rs::renderer::Renderer;
+use gloom_rs::input::InputHandler;
+use gloom_rs::clock::{Clock, ClockMode};
 
 // initial window size
 const INITIAL_SCREEN_W: u32 = 800;
 const INITIAL_SCREEN_H: u32 = 600;
 
+// keep the camera at least this far above the terrain
+const CAMERA_GROUND_CLEARANCE: f32 = 2.0;
+
+// how much slower time goes in slow motion
+const SLOW_MOTION_SCALE: f32 = 0.25;
+
+// Waits for the frames of the recording to be saved, and says where they went
+fn finish_recording(recording: capture::Recording) {
+    let directory = recording.directory().to_path_buf();
+    let frames = recording.finish();
+    println!("Recorded {} frames into {}", frames, directory.display());
+}
+
 fn main() {
+    // How time moves on, e.g. `--clock fixed:0.02` for runs that go the same way every time
+    let mut clock_args = std::env::args().skip_while(|arg| arg != "--clock");
+    let clock_mode = match (clock_args.next(), clock_args.next()) {
+        (None, _) => ClockMode::RealTime,
+        (Some(_), spec) => {
+            let mode = spec
+                .ok_or_else(|| "--clock needs a clock, as in --clock fixed:0.02".to_string())
+                .and_then(|spec| ClockMode::parse(&spec));
+            mode.unwrap_or_else(|e| {
+                eprintln!("{}", e);
+                std::process::exit(2);
+            })
+        },
+    };
+
     // Set up the necessary objects to deal with windows and event handling
     let el = glutin::event_loop::EventLoop::new();
     let wb = glutin::window::WindowBuilder::new()
@@ -68,6 +88,11 @@ fn main() {
     // Make a reference of this tuple to send to the render thread
     let window_size = Arc::clone(&arc_window_size);
 
+    // Set when the window should close. The render thread then finishes saving what it captured
+    // and stops, and only after that does the program exit.
+    let arc_quit_requested = Arc::new(AtomicBool::new(false));
+    let quit_requested = Arc::clone(&arc_quit_requested);
+
     // Spawn a separate thread for rendering, so event handling doesn't block rendering
     let render_thread = thread::spawn(move || {
         // Acquire the OpenGL Context and load the function pointers.
@@ -104,25 +129,29 @@ fn main() {
         let mut renderer = unsafe { Renderer::new(&scene) };
         let mut input_handler = InputHandler::new();
         
+        // Every frame is saved while recording. If the clock follows the wall clock, it is switched
+        // to fixed steps for the recording, so that it plays back smoothly, and back afterwards.
+        let mut recording: Option<capture::Recording> = None;
+        let mut clock_before_recording: Option<ClockMode> = None;
+        let mut screenshots = capture::Screenshots::new();
+
         // The main rendering loop
-        let first_frame_time = std::time::Instant::now();
-        let mut previous_frame_time = first_frame_time;
-        loop {
-            // Compute time passed since the previous frame and since the start of the program
-            let now = std::time::Instant::now();
-            let elapsed = now.duration_since(first_frame_time).as_secs_f32();
-            let delta_time = now.duration_since(previous_frame_time).as_secs_f32();
-            previous_frame_time = now;
+        let mut clock = Clock::new(clock_mode);
+        while !quit_requested.load(Ordering::Relaxed) {
+            // Move the simulated time on, giving the time passed since the previous frame and since the start
+            let delta_time = clock.tick();
+            let elapsed = clock.time();
 
             // Handle resize events
             if let Ok(mut new_size) = window_size.lock() {
                 if new_size.2 {
                     context.resize(glutin::dpi::PhysicalSize::new(new_size.0, new_size.1));
                     camera.update_aspect_ratio(new_size.0, new_size.1);
-                    (*new_size).2 = false;
+                    new_size.2 = false;
                     println!("Window was resized to {}x{}", new_size.0, new_size.1);
                     unsafe {
                         gl::Viewport(0, 0, new_size.0 as i32, new_size.1 as i32);
+                        renderer.resize(new_size.0, new_size.1);
                     }
                 }
             }
@@ -133,6 +162,91 @@ fn main() {
                 
             }
 
+            // T switches between the terrain mesh and the tessellated heightmap
+            if input_handler.key_pressed(VirtualKeyCode::T) {
+                renderer.use_tessellated_terrain = !renderer.use_tessellated_terrain;
+                println!("Tessellated terrain: {}", renderer.use_tessellated_terrain);
+            }
+
+            // Y switches the sun's shadows on and off
+            if input_handler.key_pressed(VirtualKeyCode::Y) {
+                renderer.shadows_enabled = !renderer.shadows_enabled;
+                println!("Shadows: {}", renderer.shadows_enabled);
+            }
+
+            // G shows the terrain height and normal found below each helicopter
+            if input_handler.key_pressed(VirtualKeyCode::G) {
+                renderer.show_terrain_probes = !renderer.show_terrain_probes;
+                println!("Terrain probes: {}", renderer.show_terrain_probes);
+            }
+
+            // V tints the terrain by level of detail, red for the most detailed, then green and blue
+            if input_handler.key_pressed(VirtualKeyCode::V) {
+                unsafe { renderer.set_show_terrain_lods(!renderer.show_terrain_lods()) };
+                println!("Terrain detail levels: {}", renderer.show_terrain_lods());
+            }
+
+            // 1 to 5 switch the post-processing effects on and off, in the order they are applied
+            let effect_keys = [Key1, Key2, Key3, Key4, Key5];
+            for (i, &key) in effect_keys.iter().enumerate() {
+                if input_handler.key_pressed(key) {
+                    if let Some((name, enabled)) = renderer.post_processing.toggle(i) {
+                        println!("Post-processing {}: {}", name, enabled);
+                    }
+                }
+            }
+
+            // 6 goes through the image filters, shown in grayscale on top of everything else
+            if input_handler.key_pressed(Key6) {
+                match renderer.post_processing.cycle_filter() {
+                    Some(kind) => println!("Image filter: {:?}", kind),
+                    None => println!("Image filter: off"),
+                }
+            }
+
+            // K pauses and resumes time, L switches slow motion on and off
+            if input_handler.key_pressed(K) {
+                clock.toggle_mode(ClockMode::Paused);
+                println!("Clock: {:?}", clock.mode());
+            }
+            if input_handler.key_pressed(L) {
+                clock.toggle_mode(ClockMode::SlowMotion { scale: SLOW_MOTION_SCALE });
+                println!("Clock: {:?}", clock.mode());
+            }
+
+            // P saves a screenshot of this frame once it is drawn
+            let take_screenshot = input_handler.key_pressed(P);
+
+            // R starts recording every frame from now on, or stops the recording
+            if input_handler.key_pressed(R) {
+                match recording.take() {
+                    Some(finished) => {
+                        let recording_mode = ClockMode::FixedStep { step: finished.timestep() };
+                        finish_recording(finished);
+                        // If paused meanwhile, it stays paused and resumes in the mode from before
+                        if let Some(mode) = clock_before_recording.take() {
+                            clock.replace_mode(&recording_mode, mode);
+                        }
+                    },
+                    None => match capture::Recording::start(capture::RECORDING_FRAME_RATE) {
+                        Ok(started) => {
+                            println!("Recording into {}", started.directory().display());
+                            if clock.mode().follows_wall_clock() {
+                                clock_before_recording = Some(clock.mode().clone());
+                                clock.set_mode(ClockMode::FixedStep { step: started.timestep() });
+                            }
+                            recording = Some(started);
+                        },
+                        Err(e) => println!("{}", e),
+                    },
+                }
+            }
+
+            // Don't let the camera sink into the ground
+            if let Some(ground) = renderer.terrain.height_at(camera.x, camera.z) {
+                camera.y = camera.y.max(ground + CAMERA_GROUND_CLEARANCE);
+            }
+
 
             // Handle mouse movement. delta contains the x and y movement of the mouse since last frame in pixels
             if let Ok(mut delta) = mouse_delta.lock() {
@@ -142,37 +256,59 @@ fn main() {
                 *delta = (0.0, 0.0); // reset when done
             }
 
-            // Render the scene with animation time
+            // Render the scene with animation time, picking up any edited shaders first
             unsafe {
-                //renderer.update_animations(elapsed);
-                renderer.render(&camera);
+                renderer.reload_shaders();
+                renderer.update_animations(elapsed);
+                renderer.render(&camera, elapsed);
+            }
+
+            // Read back what was drawn before it is swapped away
+            unsafe {
+                if take_screenshot {
+                    if let Err(e) = screenshots.take() {
+                        println!("{}", e);
+                    }
+                }
+                if let Some(recording) = &mut recording {
+                    recording.capture();
+                }
             }
 
             // Display the new color buffer on the display
             context.swap_buffers().unwrap(); // we use "double buffering" to avoid artifacts
         }
+
+        // Don't lose the frames and screenshots still waiting to be saved
+        if let Some(finished) = recording.take() {
+            finish_recording(finished);
+        }
+        drop(screenshots);
     });
 
 
-    // Keep track of the health of the rendering thread
-    let render_thread_healthy = Arc::new(RwLock::new(true));
-    let render_thread_watchdog = Arc::clone(&render_thread_healthy);
+    // Keep track of whether the rendering thread is still running
+    let render_thread_running = Arc::new(RwLock::new(true));
+    let render_thread_watchdog = Arc::clone(&render_thread_running);
+    let event_loop_proxy = el.create_proxy();
     thread::spawn(move || {
-        if !render_thread.join().is_ok() {
-            if let Ok(mut health) = render_thread_watchdog.write() {
-                println!("Render thread panicked!");
-                *health = false;
-            }
+        if render_thread.join().is_err() {
+            println!("Render thread panicked!");
         }
+        if let Ok(mut running) = render_thread_watchdog.write() {
+            *running = false;
+        }
+        // Wake the event loop up, so that it sees it
+        let _ = event_loop_proxy.send_event(());
     });
 
     // Start the event loop -- This is where window events are initially handled
     el.run(move |event, _, control_flow| {
         *control_flow = ControlFlow::Wait;
 
-        // Terminate program if render thread panics
-        if let Ok(health) = render_thread_healthy.read() {
-            if *health == false {
+        // Terminate program once the render thread has stopped, after quitting or panicking
+        if let Ok(running) = render_thread_running.read() {
+            if !*running {
                 *control_flow = ControlFlow::Exit;
             }
         }
@@ -194,7 +330,7 @@ fn main() {
                 event: WindowEvent::CloseRequested,
                 ..
             } => {
-                *control_flow = ControlFlow::Exit;
+                arc_quit_requested.store(true, Ordering::Relaxed);
             }
 
 
@@ -231,10 +367,10 @@ fn main() {
                 // Handle Escape and Q keys separately
                 match keycode {
                     Escape => {
-                        *control_flow = ControlFlow::Exit;
+                        arc_quit_requested.store(true, Ordering::Relaxed);
                     }
                     Q => {
-                        *control_flow = ControlFlow::Exit;
+                        arc_quit_requested.store(true, Ordering::Relaxed);
                     }
                     _ => {}
                 }
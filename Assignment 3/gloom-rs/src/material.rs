@@ -0,0 +1,45 @@
+extern crate nalgebra_glm as glm;
+
+use crate::shader::Shader;
+use crate::shader_library::ShaderFeatures;
+
+// How a drawable scene node looks. Selects the variant of the scene shader it is drawn with,
+// and fills in the uMaterial uniforms of lighting.glsl.
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Material {
+    pub features  : ShaderFeatures,
+    pub ambient   : glm::Vec3,   // Fraction of the ambient light reflected
+    pub diffuse   : glm::Vec3,   // Multiplied with the vertex color, if used
+    pub specular  : glm::Vec3,
+    pub shininess : f32,         // Larger values give smaller, sharper highlights
+}
+
+impl Material {
+    pub fn new(features: ShaderFeatures) -> Self {
+        Material {
+            features,
+            ambient: glm::vec3(1.0, 1.0, 1.0),
+            diffuse: glm::vec3(1.0, 1.0, 1.0),
+            specular: glm::vec3(0.1, 0.1, 0.1),
+            shininess: 16.0,
+        }
+    }
+
+    // Sets the uniforms of a shader built with this material's features
+    pub unsafe fn apply(&self, shader: &Shader) {
+        shader.set_vec3("uMaterial.diffuse", &self.diffuse);
+        if self.features.contains(ShaderFeatures::LIT) {
+            shader.set_vec3("uMaterial.ambient", &self.ambient);
+            shader.set_vec3("uMaterial.specular", &self.specular);
+            shader.set_f32("uMaterial.shininess", self.shininess);
+        }
+    }
+}
+
+impl Default for Material {
+    // Plain white, lit by the scene lights
+    fn default() -> Self {
+        Material::new(ShaderFeatures::LIT)
+    }
+}
@@ -1,4 +1,7 @@
-use tobj;
+extern crate nalgebra_glm as glm;
+
+use crate::bvh::Aabb;
+use crate::vertex_layout::{VertexAttribute, VertexLayout};
 
 // internal helper
 fn generate_color_vec(color: [f32; 4], num: usize) -> Vec<f32> {
@@ -7,6 +10,7 @@ fn generate_color_vec(color: [f32; 4], num: usize) -> Vec<f32> {
 
 // Mesh
 
+#[derive(Clone)]
 pub struct Mesh {
     pub vertices    : Vec<f32>,
     pub normals     : Vec<f32>,
@@ -27,10 +31,472 @@ impl Mesh {
             index_count,
         }
     }
+
+    // Positions, RGBA colors and normals, each in their own buffer
+    pub fn vertex_layout() -> VertexLayout {
+        VertexLayout::new()
+            .separate(VertexAttribute::float("aPos",    0, 3))
+            .separate(VertexAttribute::float("aColor",  1, 4))
+            .separate(VertexAttribute::float("aNormal", 2, 3))
+    }
+}
+
+// Level of detail
+
+// Symmetric 4x4 error quadric, storing the upper triangle:
+// [a2, ab, ac, ad, b2, bc, bd, c2, cd, d2]
+#[derive(Clone, Copy, Default)]
+struct Quadric([f64; 10]);
+
+impl Quadric {
+    fn from_plane(a: f64, b: f64, c: f64, d: f64, weight: f64) -> Self {
+        Quadric([
+            a * a, a * b, a * c, a * d,
+                   b * b, b * c, b * d,
+                          c * c, c * d,
+                                 d * d,
+        ].map(|q| q * weight))
+    }
+
+    fn add(&self, other: &Quadric) -> Quadric {
+        let mut sum = *self;
+        for (s, o) in sum.0.iter_mut().zip(other.0.iter()) {
+            *s += o;
+        }
+        sum
+    }
+
+    fn error(&self, p: [f64; 3]) -> f64 {
+        let q = &self.0;
+        let (x, y, z) = (p[0], p[1], p[2]);
+        q[0] * x * x + 2.0 * q[1] * x * y + 2.0 * q[2] * x * z + 2.0 * q[3] * x
+            + q[4] * y * y + 2.0 * q[5] * y * z + 2.0 * q[6] * y
+            + q[7] * z * z + 2.0 * q[8] * z
+            + q[9]
+    }
+}
+
+// A candidate edge collapse in the priority queue. It is stale if either vertex
+// has been touched since it was pushed.
+struct Collapse {
+    cost    : f64,
+    keep    : u32,
+    remove  : u32,
+    stamps  : (u32, u32),
+    t       : f32, // Where along the edge to put the merged vertex
+}
+
+impl PartialEq for Collapse {
+    fn eq(&self, other: &Self) -> bool {
+        self.cost == other.cost
+    }
+}
+impl Eq for Collapse {}
+impl PartialOrd for Collapse {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+impl Ord for Collapse {
+    // Reversed, so the BinaryHeap pops the cheapest collapse first
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        other.cost.total_cmp(&self.cost)
+    }
+}
+
+fn sub3(a: [f64; 3], b: [f64; 3]) -> [f64; 3] {
+    [a[0] - b[0], a[1] - b[1], a[2] - b[2]]
+}
+
+fn cross3(a: [f64; 3], b: [f64; 3]) -> [f64; 3] {
+    [a[1] * b[2] - a[2] * b[1], a[2] * b[0] - a[0] * b[2], a[0] * b[1] - a[1] * b[0]]
+}
+
+fn dot3(a: [f64; 3], b: [f64; 3]) -> f64 {
+    a[0] * b[0] + a[1] * b[1] + a[2] * b[2]
+}
+
+fn lerp3(a: [f64; 3], b: [f64; 3], t: f64) -> [f64; 3] {
+    [a[0] + (b[0] - a[0]) * t, a[1] + (b[1] - a[1]) * t, a[2] + (b[2] - a[2]) * t]
+}
+
+// What simplifying may do to the open edges of a mesh
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Boundary {
+    Weighted, // Keep the outline as far as possible, but allow removing vertices along it
+    Locked,   // Never move or remove a vertex on the outline, so that separately simplified
+              // pieces of one mesh still fit together without cracks
+}
+
+impl Mesh {
+    /// Simplifies the mesh down to roughly `target_triangles` triangles by repeatedly collapsing
+    /// the edge with the smallest quadric error (Garland & Heckbert). Open boundaries, like the
+    /// edges of the terrain, are kept according to `boundary`. Collapses that would fold the
+    /// surface into a non-manifold shape are skipped, so it may stop above the target.
+    pub fn simplify(&self, target_triangles: usize, boundary: Boundary) -> Mesh {
+        use std::collections::BinaryHeap;
+
+        let vertex_count = self.vertices.len() / 3;
+        let has_normals = self.normals.len() == self.vertices.len();
+        let has_colors = self.colors.len() == vertex_count * 4;
+
+        let mut positions: Vec<[f64; 3]> = self.vertices
+            .chunks_exact(3)
+            .map(|p| [p[0] as f64, p[1] as f64, p[2] as f64])
+            .collect();
+        let mut normals = self.normals.clone();
+        let mut colors = self.colors.clone();
+        let mut triangles: Vec<[u32; 3]> = self.indices
+            .chunks_exact(3)
+            .map(|t| [t[0], t[1], t[2]])
+            .filter(|t| t.iter().all(|&i| (i as usize) < vertex_count))
+            .collect();
+
+        let mut live_triangles = triangles.len();
+        if live_triangles <= target_triangles {
+            return self.clone();
+        }
+
+        let mut triangle_alive = vec![true; triangles.len()];
+        let mut vertex_alive = vec![true; vertex_count];
+        let mut stamps = vec![0u32; vertex_count];
+        let mut vertex_triangles: Vec<Vec<u32>> = vec![vec![]; vertex_count];
+        for (t, tri) in triangles.iter().enumerate() {
+            for &v in tri {
+                vertex_triangles[v as usize].push(t as u32);
+            }
+        }
+
+        let face_normal = |positions: &[[f64; 3]], tri: &[u32; 3]| {
+            let [a, b, c] = tri.map(|i| positions[i as usize]);
+            cross3(sub3(b, a), sub3(c, a))
+        };
+
+        // Accumulate the planes of the surrounding faces, weighted by area
+        let mut quadrics = vec![Quadric::default(); vertex_count];
+        let mut edge_faces: std::collections::HashMap<(u32, u32), u32> = Default::default();
+        for tri in &triangles {
+            let n = face_normal(&positions, tri);
+            let length = dot3(n, n).sqrt();
+            if length <= 0.0 {
+                continue;
+            }
+            let n = [n[0] / length, n[1] / length, n[2] / length];
+            let d = -dot3(n, positions[tri[0] as usize]);
+            let q = Quadric::from_plane(n[0], n[1], n[2], d, length * 0.5);
+            for &v in tri {
+                quadrics[v as usize] = quadrics[v as usize].add(&q);
+            }
+            for e in 0..3 {
+                let (a, b) = (tri[e], tri[(e + 1) % 3]);
+                *edge_faces.entry((a.min(b), a.max(b))).or_insert(0) += 1;
+            }
+        }
+
+        // Edges with a single face get a plane through the edge, perpendicular to the face
+        const BOUNDARY_WEIGHT: f64 = 1000.0;
+        let mut on_boundary = vec![false; vertex_count];
+        for tri in &triangles {
+            let n = face_normal(&positions, tri);
+            for e in 0..3 {
+                let (a, b) = (tri[e], tri[(e + 1) % 3]);
+                if edge_faces.get(&(a.min(b), a.max(b))) != Some(&1) {
+                    continue;
+                }
+                on_boundary[a as usize] = true;
+                on_boundary[b as usize] = true;
+                let edge = sub3(positions[b as usize], positions[a as usize]);
+                let p = cross3(edge, n);
+                let length = dot3(p, p).sqrt();
+                if length <= 0.0 {
+                    continue;
+                }
+                let p = [p[0] / length, p[1] / length, p[2] / length];
+                let d = -dot3(p, positions[a as usize]);
+                let q = Quadric::from_plane(p[0], p[1], p[2], d, BOUNDARY_WEIGHT * dot3(edge, edge));
+                quadrics[a as usize] = quadrics[a as usize].add(&q);
+                quadrics[b as usize] = quadrics[b as usize].add(&q);
+            }
+        }
+
+        // None if the edge may not be collapsed at all
+        let evaluate = |positions: &[[f64; 3]], quadrics: &[Quadric], stamps: &[u32], on_boundary: &[bool], a: u32, b: u32| {
+            let placements: &[f64] = match (boundary, on_boundary[a as usize], on_boundary[b as usize]) {
+                (Boundary::Locked, true, true) => return None,
+                (Boundary::Locked, true, false) => &[0.0],
+                (Boundary::Locked, false, true) => &[1.0],
+                _ => &[0.0, 0.5, 1.0],
+            };
+            let q = quadrics[a as usize].add(&quadrics[b as usize]);
+            let (pa, pb) = (positions[a as usize], positions[b as usize]);
+            let (cost, t) = placements
+                .iter()
+                .map(|&t| (q.error(lerp3(pa, pb, t)), t))
+                .min_by(|x, y| x.0.total_cmp(&y.0))
+                .unwrap();
+            Some(Collapse { cost, keep: a, remove: b, stamps: (stamps[a as usize], stamps[b as usize]), t: t as f32 })
+        };
+
+        let mut heap = BinaryHeap::with_capacity(edge_faces.len());
+        for &(a, b) in edge_faces.keys() {
+            heap.extend(evaluate(&positions, &quadrics, &stamps, &on_boundary, a, b));
+        }
+
+        while live_triangles > target_triangles {
+            let collapse = match heap.pop() {
+                Some(c) => c,
+                None => break,
+            };
+            let (keep, remove) = (collapse.keep as usize, collapse.remove as usize);
+            if !vertex_alive[keep] || !vertex_alive[remove]
+                || (stamps[keep], stamps[remove]) != collapse.stamps
+            {
+                continue;
+            }
+
+            // Link condition (Dey et al.): the only vertices next to both ends may be the third
+            // corners of the faces on the edge, and no two faces on either side may share their
+            // far edge. Otherwise the collapse pinches the surface, e.g. folds a tetrahedron into
+            // two faces back to back. On the outline, joining two ends across the inside would
+            // pinch it too.
+            let edge_triangles: Vec<[u32; 3]> = vertex_triangles[keep]
+                .iter()
+                .filter(|&&t| triangle_alive[t as usize])
+                .map(|&t| triangles[t as usize])
+                .filter(|tri| tri.contains(&(remove as u32)))
+                .collect();
+            let mut opposite: Vec<u32> = edge_triangles
+                .iter()
+                .flat_map(|tri| tri.iter().copied())
+                .filter(|&v| v as usize != keep && v as usize != remove)
+                .collect();
+            opposite.sort_unstable();
+            opposite.dedup();
+            let link = |v: usize, other: usize| {
+                let mut vertices = vec![];
+                let mut edges = vec![];
+                for &t in &vertex_triangles[v] {
+                    let tri = triangles[t as usize];
+                    if !triangle_alive[t as usize] || tri.contains(&(other as u32)) {
+                        continue;
+                    }
+                    let rest: Vec<u32> = tri.iter().copied().filter(|&i| i as usize != v).collect();
+                    vertices.extend_from_slice(&rest);
+                    edges.push((rest[0].min(rest[1]), rest[0].max(rest[1])));
+                }
+                vertices.sort_unstable();
+                vertices.dedup();
+                edges.sort_unstable();
+                (vertices, edges)
+            };
+            let (keep_vertices, keep_edges) = link(keep, remove);
+            let (remove_vertices, remove_edges) = link(remove, keep);
+            let shared_vertices: Vec<u32> = keep_vertices
+                .iter()
+                .copied()
+                .filter(|v| remove_vertices.binary_search(v).is_ok() && opposite.binary_search(v).is_err())
+                .collect();
+            let shared_edge = keep_edges.iter().any(|e| remove_edges.binary_search(e).is_ok());
+            let pinches_outline = on_boundary[keep] && on_boundary[remove] && edge_triangles.len() != 1;
+            if edge_triangles.is_empty() || edge_triangles.len() > 2 || !shared_vertices.is_empty() || shared_edge || pinches_outline {
+                continue;
+            }
+
+            // Reject the collapse if any of the remaining faces around it would flip over
+            let target = lerp3(positions[keep], positions[remove], collapse.t as f64);
+            let flips = [keep, remove].iter().any(|&v| {
+                vertex_triangles[v].iter().any(|&t| {
+                    let tri = triangles[t as usize];
+                    if !triangle_alive[t as usize] || tri.contains(&(keep as u32)) && tri.contains(&(remove as u32)) {
+                        return false;
+                    }
+                    let before = face_normal(&positions, &tri);
+                    let moved: Vec<[f64; 3]> = tri.iter()
+                        .map(|&i| if i as usize == v { target } else { positions[i as usize] })
+                        .collect();
+                    let after = cross3(sub3(moved[1], moved[0]), sub3(moved[2], moved[0]));
+                    dot3(before, after) <= 0.0
+                })
+            });
+            if flips {
+                continue;
+            }
+
+            // Merge `remove` into `keep`
+            let t = collapse.t;
+            positions[keep] = target;
+            if has_normals {
+                for k in 0..3 {
+                    normals[keep * 3 + k] += (normals[remove * 3 + k] - normals[keep * 3 + k]) * t;
+                }
+            }
+            if has_colors {
+                for k in 0..4 {
+                    colors[keep * 4 + k] += (colors[remove * 4 + k] - colors[keep * 4 + k]) * t;
+                }
+            }
+            quadrics[keep] = quadrics[keep].add(&quadrics[remove]);
+            on_boundary[keep] |= on_boundary[remove];
+            vertex_alive[remove] = false;
+            stamps[keep] += 1;
+
+            let moved = std::mem::take(&mut vertex_triangles[remove]);
+            for t in moved {
+                let tri = &mut triangles[t as usize];
+                if !triangle_alive[t as usize] {
+                    continue;
+                }
+                if tri.contains(&(keep as u32)) {
+                    triangle_alive[t as usize] = false;
+                    live_triangles -= 1;
+                    continue;
+                }
+                for v in tri.iter_mut() {
+                    if *v as usize == remove {
+                        *v = keep as u32;
+                    }
+                }
+                vertex_triangles[keep].push(t);
+            }
+            vertex_triangles[keep].retain(|&t| triangle_alive[t as usize]);
+
+            // Re-evaluate every edge around the merged vertex
+            let mut neighbours: Vec<u32> = vertex_triangles[keep]
+                .iter()
+                .flat_map(|&t| triangles[t as usize])
+                .filter(|&v| v as usize != keep)
+                .collect();
+            neighbours.sort_unstable();
+            neighbours.dedup();
+            for n in neighbours {
+                heap.extend(evaluate(&positions, &quadrics, &stamps, &on_boundary, keep as u32, n));
+            }
+        }
+
+        // Compact the surviving vertices and triangles
+        let mut remap = vec![u32::MAX; vertex_count];
+        let mut mesh = Mesh {
+            vertices: vec![],
+            normals: vec![],
+            colors: vec![],
+            indices: Vec::with_capacity(live_triangles * 3),
+            index_count: 0,
+        };
+        for (t, tri) in triangles.iter().enumerate() {
+            if !triangle_alive[t] {
+                continue;
+            }
+            for &v in tri {
+                let v = v as usize;
+                if remap[v] == u32::MAX {
+                    remap[v] = (mesh.vertices.len() / 3) as u32;
+                    mesh.vertices.extend(positions[v].iter().map(|&p| p as f32));
+                    if has_normals {
+                        let n = &normals[v * 3..v * 3 + 3];
+                        let length = (n[0] * n[0] + n[1] * n[1] + n[2] * n[2]).sqrt().max(f32::EPSILON);
+                        mesh.normals.extend(n.iter().map(|c| c / length));
+                    }
+                    if has_colors {
+                        mesh.colors.extend_from_slice(&colors[v * 4..v * 4 + 4]);
+                    }
+                }
+                mesh.indices.push(remap[v]);
+            }
+        }
+        mesh.index_count = mesh.indices.len() as i32;
+        mesh
+    }
+
+    /// Builds a chain of progressively simpler meshes, each with `ratio` times the triangles of
+    /// the previous one. The first entry is the mesh itself.
+    pub fn generate_lods(&self, levels: usize, ratio: f32, boundary: Boundary) -> Vec<Mesh> {
+        let mut lods = vec![self.clone()];
+        for _ in 1..levels {
+            let previous = lods.last().unwrap();
+            let target = (previous.indices.len() as f32 / 3.0 * ratio) as usize;
+            lods.push(previous.simplify(target.max(1), boundary));
+        }
+        lods
+    }
+
+    /// The box around all vertices, empty if there are none.
+    pub fn bounds(&self) -> Aabb {
+        let mut bounds = Aabb::empty();
+        for p in self.vertices.chunks_exact(3) {
+            bounds.grow(&glm::vec3(p[0], p[1], p[2]));
+        }
+        bounds
+    }
+
+    /// Cuts the mesh into a grid of `columns` by `rows` pieces over its extent in the XZ plane,
+    /// each triangle going to the piece its center falls in. Vertices on the cuts are copied
+    /// into every piece using them. Pieces without triangles are left out.
+    pub fn split_into_chunks(&self, columns: usize, rows: usize) -> Vec<Mesh> {
+        let bounds = self.bounds();
+        if bounds.is_empty() {
+            return vec![];
+        }
+        let size = bounds.max - bounds.min;
+        let cell = |value: f32, min: f32, extent: f32, count: usize| {
+            if extent <= 0.0 {
+                return 0;
+            }
+            (((value - min) / extent * count as f32) as usize).min(count - 1)
+        };
+
+        let vertex_count = self.vertices.len() / 3;
+        let has_normals = self.normals.len() == self.vertices.len();
+        let has_colors = self.colors.len() == vertex_count * 4;
+        let mut chunks: Vec<(Mesh, Vec<u32>)> = (0..columns * rows)
+            .map(|_| {
+                let mesh = Mesh { vertices: vec![], normals: vec![], colors: vec![], indices: vec![], index_count: 0 };
+                (mesh, vec![u32::MAX; vertex_count])
+            })
+            .collect();
+
+        for tri in self.indices.chunks_exact(3) {
+            if tri.iter().any(|&i| i as usize >= vertex_count) {
+                continue;
+            }
+            let center = tri.iter().fold(glm::vec3(0.0, 0.0, 0.0), |sum, &i| {
+                let i = i as usize;
+                sum + glm::vec3(self.vertices[i * 3], self.vertices[i * 3 + 1], self.vertices[i * 3 + 2]) / 3.0
+            });
+            let column = cell(center.x, bounds.min.x, size.x, columns);
+            let row = cell(center.z, bounds.min.z, size.z, rows);
+            let (chunk, remap) = &mut chunks[row * columns + column];
+            for &v in tri {
+                let v = v as usize;
+                if remap[v] == u32::MAX {
+                    remap[v] = (chunk.vertices.len() / 3) as u32;
+                    chunk.vertices.extend_from_slice(&self.vertices[v * 3..v * 3 + 3]);
+                    if has_normals {
+                        chunk.normals.extend_from_slice(&self.normals[v * 3..v * 3 + 3]);
+                    }
+                    if has_colors {
+                        chunk.colors.extend_from_slice(&self.colors[v * 4..v * 4 + 4]);
+                    }
+                }
+                chunk.indices.push(remap[v]);
+            }
+        }
+
+        chunks
+            .into_iter()
+            .map(|(mut chunk, _)| {
+                chunk.index_count = chunk.indices.len() as i32;
+                chunk
+            })
+            .filter(|chunk| chunk.index_count > 0)
+            .collect()
+    }
 }
 
 // Lunar terrain
 
+// The vertex color of the whole terrain
+pub const TERRAIN_COLOR: [f32; 4] = [1.0, 1.0, 1.0, 1.0];
+
 pub struct Terrain;
 impl Terrain {
     pub fn load(path: &str) -> Mesh {
@@ -47,7 +513,7 @@ impl Terrain {
         let after = std::time::Instant::now();
         println!("Done in {:.3}ms.", after.duration_since(before).as_micros() as f32 / 1e3);
 
-        if models.len() > 1 || models.len() == 0 {
+        if models.len() > 1 || models.is_empty() {
             panic!("Please use a model with a single mesh!")
             // You could try merging the vertices and indices
             // of the separate meshes into a single mesh.
@@ -61,64 +527,160 @@ impl Terrain {
             terrain.mesh.indices.len() / 3,
         );
 
-        Mesh::from(terrain.mesh, [1.0, 1.0, 1.0, 1.0])
+        Mesh::from(terrain.mesh, TERRAIN_COLOR).validated(&terrain.name)
     }
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-// Helicopter
+    fn mesh(vertices: Vec<f32>, indices: Vec<u32>) -> Mesh {
+        let count = vertices.len() / 3;
+        Mesh {
+            normals: vertices.chunks_exact(3).flat_map(|_| [0.0, 1.0, 0.0]).collect(),
+            colors: generate_color_vec([1.0; 4], count),
+            index_count: indices.len() as i32,
+            vertices,
+            indices,
+        }
+    }
 
-pub struct Helicopter {
-    pub body       : Mesh,
-    pub door       : Mesh,
-    pub main_rotor : Mesh,
-    pub tail_rotor : Mesh,
-}
+    // A bumpy square of n by n cells, two triangles each
+    fn grid(n: u32) -> Mesh {
+        let mut vertices = vec![];
+        for z in 0..=n {
+            for x in 0..=n {
+                let (x, z) = (x as f32, z as f32);
+                vertices.extend_from_slice(&[x, (x * 0.7).sin() * (z * 0.4).cos(), z]);
+            }
+        }
+        let mut indices = vec![];
+        for z in 0..n {
+            for x in 0..n {
+                let i = z * (n + 1) + x;
+                indices.extend_from_slice(&[i, i + n + 1, i + 1, i + 1, i + n + 1, i + n + 2]);
+            }
+        }
+        mesh(vertices, indices)
+    }
 
-// You can use square brackets to access the components of the helicopter, if you want to use loops!
-use std::ops::Index;
-impl Index<usize> for Helicopter {
-    type Output = Mesh;
-    fn index<'a>(&'a self, i: usize) -> &'a Mesh {
-        match i {
-            0 => &self.body,
-            1 => &self.main_rotor,
-            2 => &self.tail_rotor,
-            3 => &self.door,
-            _ => panic!("Invalid index, try [0,3]"),
+    fn triangle_count(mesh: &Mesh) -> usize {
+        mesh.indices.len() / 3
+    }
+
+    fn position(mesh: &Mesh, i: u32) -> [f64; 3] {
+        let p = &mesh.vertices[i as usize * 3..i as usize * 3 + 3];
+        [p[0] as f64, p[1] as f64, p[2] as f64]
+    }
+
+    fn assert_well_formed(mesh: &Mesh) {
+        assert_eq!(mesh.index_count as usize, mesh.indices.len());
+        assert_eq!(mesh.normals.len(), mesh.vertices.len());
+        assert_eq!(mesh.colors.len(), mesh.vertices.len() / 3 * 4);
+        let mut edge_faces = std::collections::HashMap::new();
+        for tri in mesh.indices.chunks_exact(3) {
+            assert!(tri.iter().all(|&i| (i as usize) < mesh.vertices.len() / 3), "{:?} is out of range", tri);
+            assert!(tri[0] != tri[1] && tri[1] != tri[2] && tri[2] != tri[0], "{:?} repeats a vertex", tri);
+            let [a, b, c] = [tri[0], tri[1], tri[2]].map(|i| position(mesh, i));
+            let n = cross3(sub3(b, a), sub3(c, a));
+            assert!(dot3(n, n) > 0.0, "{:?} has no area", tri);
+            for e in 0..3 {
+                let (a, b) = (tri[e], tri[(e + 1) % 3]);
+                *edge_faces.entry((a.min(b), a.max(b))).or_insert(0) += 1;
+            }
         }
+        assert!(edge_faces.values().all(|&faces| faces <= 2), "an edge has more than two faces");
     }
-}
 
-impl Helicopter {
-    pub fn load(path: &str) -> Self {
-        println!("Loading helicopter model...");
-        let before = std::time::Instant::now();
-        let (models, _materials)
-            = tobj::load_obj(path,
-                &tobj::LoadOptions{
-                    triangulate: true,
-                    single_index: true,
-                    ..Default::default()
-                }
-            ).expect("Failed to load helicopter model");
-        let after = std::time::Instant::now();
-        println!("Done in {:.3}ms!", after.duration_since(before).as_micros() as f32 / 1e3);
+    #[test]
+    fn simplify_reaches_the_target() {
+        let grid = grid(20);
+        for boundary in [Boundary::Weighted, Boundary::Locked] {
+            let simple = grid.simplify(300, boundary);
+            assert!(triangle_count(&simple) <= 300, "{:?} left {} triangles", boundary, triangle_count(&simple));
+            assert!(triangle_count(&simple) > 0);
+            assert_well_formed(&simple);
+        }
+    }
+
+    #[test]
+    fn simplify_keeps_meshes_under_the_target() {
+        let grid = grid(4);
+        let simple = grid.simplify(100, Boundary::Weighted);
+        assert_eq!(simple.indices, grid.indices);
+    }
 
-        for model in &models {
-            println!("Loaded {} with {} points and {} triangles.", model.name, model.mesh.positions.len() / 3, model.mesh.indices.len() / 3);
+    #[test]
+    fn simplify_as_far_as_possible_stays_well_formed() {
+        let grid = grid(12);
+        for boundary in [Boundary::Weighted, Boundary::Locked] {
+            let simple = grid.simplify(1, boundary);
+            assert!(triangle_count(&simple) > 0);
+            assert_well_formed(&simple);
         }
+    }
+
+    #[test]
+    fn locked_boundary_keeps_every_outline_vertex() {
+        let n = 12;
+        let grid = grid(n);
+        let simple = grid.simplify(1, Boundary::Locked);
+        let on_outline = |p: &[f32]| p[0] == 0.0 || p[0] == n as f32 || p[2] == 0.0 || p[2] == n as f32;
+        let outline = |mesh: &Mesh| {
+            let mut points: Vec<[f32; 3]> = mesh.vertices
+                .chunks_exact(3)
+                .filter(|p| on_outline(p))
+                .map(|p| [p[0], p[1], p[2]])
+                .collect();
+            points.sort_by(|a, b| a.partial_cmp(b).unwrap());
+            points
+        };
+        assert_eq!(outline(&simple), outline(&grid));
+    }
+
+    #[test]
+    fn tetrahedron_is_not_folded_flat() {
+        let tetrahedron = mesh(
+            vec![0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0],
+            vec![0, 2, 1, 0, 1, 3, 0, 3, 2, 1, 2, 3],
+        );
+        let simple = tetrahedron.simplify(1, Boundary::Weighted);
+        assert_eq!(triangle_count(&simple), 4);
+        assert_well_formed(&simple);
+    }
 
-        let body_model = models.iter().find(|m| m.name == "Body_body").expect("Incorrect model file!").to_owned();
-        let door_model = models.iter().find(|m| m.name == "Door_door").expect("Incorrect model file!").to_owned();
-        let main_rotor_model = models.iter().find(|m| m.name == "Main_Rotor_main_rotor").expect("Incorrect model file!").to_owned();
-        let tail_rotor_model = models.iter().find(|m| m.name == "Tail_Rotor_tail_rotor").expect("Incorrect model file!").to_owned();
+    #[test]
+    fn generate_lods_gets_simpler_at_every_level() {
+        let grid = grid(24);
+        let lods = grid.generate_lods(4, 0.3, Boundary::Weighted);
+        assert_eq!(lods.len(), 4);
+        assert_eq!(lods[0].indices, grid.indices);
+        for pair in lods.windows(2) {
+            assert!(triangle_count(&pair[1]) < triangle_count(&pair[0]));
+            assert!(triangle_count(&pair[1]) as f32 <= triangle_count(&pair[0]) as f32 * 0.3 + 1.0);
+        }
+        for lod in &lods {
+            assert_well_formed(lod);
+        }
+    }
 
-        Helicopter {
-            body:       Mesh::from(body_model.mesh,         [0.3, 0.3, 0.3, 1.0]),
-            door:       Mesh::from(door_model.mesh,         [0.1, 0.1, 0.3, 1.0]),
-            main_rotor: Mesh::from(main_rotor_model.mesh,   [0.3, 0.1, 0.1, 1.0]),
-            tail_rotor: Mesh::from(tail_rotor_model.mesh,   [0.1, 0.3, 0.1, 1.0]),
+    #[test]
+    fn chunks_share_out_every_triangle() {
+        let grid = grid(16);
+        let chunks = grid.split_into_chunks(4, 4);
+        assert_eq!(chunks.len(), 16);
+        assert_eq!(chunks.iter().map(triangle_count).sum::<usize>(), triangle_count(&grid));
+        for chunk in &chunks {
+            let bounds = chunk.bounds();
+            assert!(bounds.max.x - bounds.min.x <= 4.0 && bounds.max.z - bounds.min.z <= 4.0);
+            assert_well_formed(chunk);
         }
     }
+
+    #[test]
+    fn bounds_of_nothing_are_empty() {
+        assert!(mesh(vec![], vec![]).bounds().is_empty());
+        assert!(mesh(vec![], vec![]).split_into_chunks(2, 2).is_empty());
+    }
 }
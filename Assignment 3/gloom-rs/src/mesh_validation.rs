@@ -0,0 +1,580 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::mesh::Mesh;
+
+// Everything that can be wrong with a mesh before it is handed to OpenGL
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum MeshIssue {
+    PositionsNotTriples     { len: usize },
+    IndicesNotTriangles     { len: usize },
+    IndexCountMismatch      { index_count: i32, indices: usize },
+    IndexOutOfRange         { triangle: usize, index: u32, vertex_count: usize },
+    NonFinitePosition       { vertex: usize },
+    DegenerateTriangle      { triangle: usize },
+    NormalCountMismatch     { normals: usize, expected: usize },
+    InvalidNormal           { vertex: usize },
+    ColorCountMismatch      { colors: usize, expected: usize },
+    InconsistentWinding     { triangle: usize },
+    NonManifoldEdge         { a: u32, b: u32, faces: usize },
+}
+
+impl fmt::Display for MeshIssue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MeshIssue::PositionsNotTriples { len } =>
+                write!(f, "{} position components is not a multiple of 3", len),
+            MeshIssue::IndicesNotTriangles { len } =>
+                write!(f, "{} indices is not a multiple of 3", len),
+            MeshIssue::IndexCountMismatch { index_count, indices } =>
+                write!(f, "index_count is {} but there are {} indices", index_count, indices),
+            MeshIssue::IndexOutOfRange { triangle, index, vertex_count } =>
+                write!(f, "triangle {} uses index {}, but there are only {} vertices", triangle, index, vertex_count),
+            MeshIssue::NonFinitePosition { vertex } =>
+                write!(f, "vertex {} has a NaN or infinite position", vertex),
+            MeshIssue::DegenerateTriangle { triangle } =>
+                write!(f, "triangle {} has no area", triangle),
+            MeshIssue::NormalCountMismatch { normals, expected } =>
+                write!(f, "{} normal components, expected {}", normals, expected),
+            MeshIssue::InvalidNormal { vertex } =>
+                write!(f, "vertex {} has a zero, NaN or infinite normal", vertex),
+            MeshIssue::ColorCountMismatch { colors, expected } =>
+                write!(f, "{} color components, expected {}", colors, expected),
+            MeshIssue::InconsistentWinding { triangle } =>
+                write!(f, "triangle {} is wound opposite to its neighbours", triangle),
+            MeshIssue::NonManifoldEdge { a, b, faces } =>
+                write!(f, "edge ({}, {}) is shared by {} triangles", a, b, faces),
+        }
+    }
+}
+
+impl MeshIssue {
+    // Whether `repair` can do something about it. A non-manifold edge would need the surface
+    // around it cut apart or rebuilt, which is better done in a modelling tool.
+    pub fn is_repairable(&self) -> bool {
+        !matches!(self, MeshIssue::NonManifoldEdge { .. })
+    }
+}
+
+// Which repair passes to run, in the order listed
+#[derive(Clone, Copy, Debug)]
+pub struct RepairOptions {
+    pub weld_epsilon       : Option<f32>, // Merge vertices closer than this with matching attributes
+    pub drop_degenerate    : bool,
+    pub fix_winding        : bool,
+    pub renormalize        : bool,
+}
+
+impl Default for RepairOptions {
+    fn default() -> Self {
+        RepairOptions {
+            weld_epsilon: Some(1e-5),
+            drop_degenerate: true,
+            fix_winding: true,
+            renormalize: true,
+        }
+    }
+}
+
+impl RepairOptions {
+    /// Just the passes that fix the given issues. Vertices are only welded to fix the winding,
+    /// which needs neighbouring triangles to share their corners to find each other.
+    pub fn for_issues(issues: &[MeshIssue]) -> Self {
+        let any = |f: fn(&MeshIssue) -> bool| issues.iter().any(f);
+        let fix_winding = any(|i| matches!(i, MeshIssue::InconsistentWinding { .. }));
+        RepairOptions {
+            weld_epsilon: if fix_winding { RepairOptions::default().weld_epsilon } else { None },
+            drop_degenerate: any(|i| matches!(i, MeshIssue::DegenerateTriangle { .. })),
+            fix_winding,
+            renormalize: any(|i| matches!(i, MeshIssue::InvalidNormal { .. } | MeshIssue::NormalCountMismatch { .. })),
+        }
+    }
+}
+
+const DEGENERATE_AREA: f32 = 1e-12;
+
+impl Mesh {
+    fn vertex_count(&self) -> usize {
+        self.vertices.len() / 3
+    }
+
+    fn position(&self, v: u32) -> [f32; 3] {
+        let i = v as usize * 3;
+        [self.vertices[i], self.vertices[i + 1], self.vertices[i + 2]]
+    }
+
+    fn triangle_area2(&self, t: &[u32]) -> f32 {
+        let [a, b, c] = [self.position(t[0]), self.position(t[1]), self.position(t[2])];
+        let (u, v) = ([b[0] - a[0], b[1] - a[1], b[2] - a[2]], [c[0] - a[0], c[1] - a[1], c[2] - a[2]]);
+        let n = [u[1] * v[2] - u[2] * v[1], u[2] * v[0] - u[0] * v[2], u[0] * v[1] - u[1] * v[0]];
+        n[0] * n[0] + n[1] * n[1] + n[2] * n[2]
+    }
+
+    fn face_normal(&self, t: &[u32]) -> [f32; 3] {
+        let [a, b, c] = [self.position(t[0]), self.position(t[1]), self.position(t[2])];
+        let (u, v) = ([b[0] - a[0], b[1] - a[1], b[2] - a[2]], [c[0] - a[0], c[1] - a[1], c[2] - a[2]]);
+        [u[1] * v[2] - u[2] * v[1], u[2] * v[0] - u[0] * v[2], u[0] * v[1] - u[1] * v[0]]
+    }
+
+    /// Lists every problem with the mesh that would upset OpenGL or the renderer.
+    pub fn validate(&self) -> Vec<MeshIssue> {
+        let mut issues = vec![];
+        let vertex_count = self.vertex_count();
+
+        if self.vertices.len() % 3 != 0 {
+            issues.push(MeshIssue::PositionsNotTriples { len: self.vertices.len() });
+        }
+        if self.indices.len() % 3 != 0 {
+            issues.push(MeshIssue::IndicesNotTriangles { len: self.indices.len() });
+        }
+        if self.index_count as usize != self.indices.len() {
+            issues.push(MeshIssue::IndexCountMismatch { index_count: self.index_count, indices: self.indices.len() });
+        }
+        if self.normals.len() != vertex_count * 3 {
+            issues.push(MeshIssue::NormalCountMismatch { normals: self.normals.len(), expected: vertex_count * 3 });
+        } else {
+            for (v, n) in self.normals.chunks_exact(3).enumerate() {
+                let length2 = n[0] * n[0] + n[1] * n[1] + n[2] * n[2];
+                if !length2.is_finite() || length2 <= f32::EPSILON {
+                    issues.push(MeshIssue::InvalidNormal { vertex: v });
+                }
+            }
+        }
+        if self.colors.len() != vertex_count * 4 {
+            issues.push(MeshIssue::ColorCountMismatch { colors: self.colors.len(), expected: vertex_count * 4 });
+        }
+
+        let mut finite = vec![true; vertex_count];
+        for (v, p) in self.vertices.chunks_exact(3).enumerate() {
+            if !p.iter().all(|c| c.is_finite()) {
+                finite[v] = false;
+                issues.push(MeshIssue::NonFinitePosition { vertex: v });
+            }
+        }
+
+        let mut usable = vec![];
+        for (t, tri) in self.indices.chunks_exact(3).enumerate() {
+            let mut in_range = true;
+            for &index in tri {
+                if index as usize >= vertex_count {
+                    issues.push(MeshIssue::IndexOutOfRange { triangle: t, index, vertex_count });
+                    in_range = false;
+                }
+            }
+            if !in_range || !tri.iter().all(|&i| finite[i as usize]) {
+                continue;
+            }
+            if tri[0] == tri[1] || tri[1] == tri[2] || tri[2] == tri[0] || self.triangle_area2(tri) <= DEGENERATE_AREA {
+                issues.push(MeshIssue::DegenerateTriangle { triangle: t });
+                continue;
+            }
+            usable.push(t);
+        }
+
+        // Every interior edge should be walked once in each direction by its two triangles
+        let mut edges: HashMap<(u32, u32), Vec<(usize, bool)>> = HashMap::new();
+        for &t in &usable {
+            let tri = &self.indices[t * 3..t * 3 + 3];
+            for e in 0..3 {
+                let (a, b) = (tri[e], tri[(e + 1) % 3]);
+                edges.entry((a.min(b), a.max(b))).or_default().push((t, a < b));
+            }
+        }
+        let mut flagged = std::collections::HashSet::new();
+        for (&(a, b), faces) in &edges {
+            if faces.len() > 2 {
+                issues.push(MeshIssue::NonManifoldEdge { a, b, faces: faces.len() });
+            } else if faces.len() == 2 && faces[0].1 == faces[1].1 && flagged.insert(faces[1].0) {
+                issues.push(MeshIssue::InconsistentWinding { triangle: faces[1].0 });
+            }
+        }
+
+        issues
+    }
+
+    /// Runs the selected repair passes, leaving a mesh that is safe to upload.
+    /// Triangles that cannot be drawn at all are always removed.
+    pub fn repair(&mut self, options: &RepairOptions) {
+        self.drop_unusable_triangles();
+        self.fix_attribute_counts();
+        if let Some(epsilon) = options.weld_epsilon {
+            self.weld_vertices(epsilon);
+        }
+        if options.drop_degenerate {
+            self.drop_degenerate_triangles();
+        }
+        if options.fix_winding {
+            self.fix_winding();
+        }
+        if options.renormalize {
+            self.renormalize_normals();
+        }
+    }
+
+    // Removes triangles with out-of-range indices or non-finite corners, and any trailing
+    // indices that don't form a whole triangle
+    fn drop_unusable_triangles(&mut self) {
+        let vertex_count = self.vertex_count();
+        self.vertices.truncate(vertex_count * 3);
+        let finite: Vec<bool> = self.vertices.chunks_exact(3).map(|p| p.iter().all(|c| c.is_finite())).collect();
+        let indices: Vec<u32> = self.indices
+            .chunks_exact(3)
+            .filter(|t| t.iter().all(|&i| (i as usize) < vertex_count && finite[i as usize]))
+            .flatten()
+            .copied()
+            .collect();
+        for (p, ok) in self.vertices.chunks_exact_mut(3).zip(&finite) {
+            if !ok {
+                p.copy_from_slice(&[0.0; 3]);
+            }
+        }
+        self.set_indices(indices);
+    }
+
+    // Normals get recomputed later if they are missing, colors default to white
+    fn fix_attribute_counts(&mut self) {
+        let vertex_count = self.vertex_count();
+        if self.normals.len() != vertex_count * 3 {
+            self.normals = vec![0.0; vertex_count * 3];
+        }
+        if self.colors.len() != vertex_count * 4 {
+            self.colors.resize(vertex_count * 4, 1.0);
+        }
+    }
+
+    fn set_indices(&mut self, indices: Vec<u32>) {
+        self.index_count = indices.len() as i32;
+        self.indices = indices;
+    }
+
+    /// Merges vertices whose positions, normals and colors all lie within epsilon of each other.
+    pub fn weld_vertices(&mut self, epsilon: f32) {
+        let vertex_count = self.vertex_count();
+        let has_normals = self.normals.len() == vertex_count * 3;
+        let has_colors = self.colors.len() == vertex_count * 4;
+        let quantize = |x: f32| (x / epsilon).round() as i64;
+
+        let mut seen: HashMap<Vec<i64>, u32> = HashMap::new();
+        let mut remap = Vec::with_capacity(vertex_count);
+        let (mut vertices, mut normals, mut colors) = (vec![], vec![], vec![]);
+        for v in 0..vertex_count {
+            let mut key: Vec<i64> = self.vertices[v * 3..v * 3 + 3].iter().map(|&x| quantize(x)).collect();
+            if has_normals {
+                key.extend(self.normals[v * 3..v * 3 + 3].iter().map(|&x| quantize(x)));
+            }
+            if has_colors {
+                key.extend(self.colors[v * 4..v * 4 + 4].iter().map(|&x| quantize(x)));
+            }
+            let next = (vertices.len() / 3) as u32;
+            let index = *seen.entry(key).or_insert(next);
+            if index == next {
+                vertices.extend_from_slice(&self.vertices[v * 3..v * 3 + 3]);
+                if has_normals {
+                    normals.extend_from_slice(&self.normals[v * 3..v * 3 + 3]);
+                }
+                if has_colors {
+                    colors.extend_from_slice(&self.colors[v * 4..v * 4 + 4]);
+                }
+            }
+            remap.push(index);
+        }
+
+        let indices = self.indices.iter().map(|&i| remap.get(i as usize).copied().unwrap_or(i)).collect();
+        self.vertices = vertices;
+        if has_normals {
+            self.normals = normals;
+        }
+        if has_colors {
+            self.colors = colors;
+        }
+        self.set_indices(indices);
+    }
+
+    /// Removes triangles with repeated corners or (close to) zero area.
+    pub fn drop_degenerate_triangles(&mut self) {
+        let indices = self.indices
+            .chunks_exact(3)
+            .filter(|t| t[0] != t[1] && t[1] != t[2] && t[2] != t[0] && self.triangle_area2(t) > DEGENERATE_AREA)
+            .flatten()
+            .copied()
+            .collect();
+        self.set_indices(indices);
+    }
+
+    /// Flips triangles so that neighbours agree on their winding. Each connected patch is then
+    /// turned to agree with its vertex normals, if there are any.
+    pub fn fix_winding(&mut self) {
+        let triangle_count = self.indices.len() / 3;
+        let mut edges: HashMap<(u32, u32), Vec<usize>> = HashMap::new();
+        for t in 0..triangle_count {
+            for e in 0..3 {
+                let (a, b) = (self.indices[t * 3 + e], self.indices[t * 3 + (e + 1) % 3]);
+                edges.entry((a.min(b), a.max(b))).or_default().push(t);
+            }
+        }
+
+        // Does triangle t walk the edge from a to b?
+        let walks = |indices: &[u32], t: usize, a: u32, b: u32| {
+            (0..3).any(|e| indices[t * 3 + e] == a && indices[t * 3 + (e + 1) % 3] == b)
+        };
+
+        let mut visited = vec![false; triangle_count];
+        for seed in 0..triangle_count {
+            if visited[seed] {
+                continue;
+            }
+            visited[seed] = true;
+            let mut patch = vec![seed];
+            let mut queue = std::collections::VecDeque::from(vec![seed]);
+            while let Some(t) = queue.pop_front() {
+                for e in 0..3 {
+                    let (a, b) = (self.indices[t * 3 + e], self.indices[t * 3 + (e + 1) % 3]);
+                    let neighbours = &edges[&(a.min(b), a.max(b))];
+                    if neighbours.len() != 2 {
+                        continue; // Boundary or non-manifold, nothing sensible to propagate
+                    }
+                    for &n in neighbours {
+                        if n == t || visited[n] {
+                            continue;
+                        }
+                        visited[n] = true;
+                        if walks(&self.indices, n, a, b) {
+                            self.indices.swap(n * 3 + 1, n * 3 + 2);
+                        }
+                        patch.push(n);
+                        queue.push_back(n);
+                    }
+                }
+            }
+
+            if self.normals.len() == self.vertices.len() {
+                let agreement: f32 = patch.iter().map(|&t| {
+                    let tri = &self.indices[t * 3..t * 3 + 3];
+                    let face = self.face_normal(tri);
+                    tri.iter().map(|&v| {
+                        let n = &self.normals[v as usize * 3..v as usize * 3 + 3];
+                        (face[0] * n[0] + face[1] * n[1] + face[2] * n[2]).signum()
+                    }).sum::<f32>()
+                }).sum();
+                if agreement < 0.0 {
+                    for &t in &patch {
+                        self.indices.swap(t * 3 + 1, t * 3 + 2);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Normalizes the vertex normals, recomputing the ones that are zero or not finite
+    /// from the area weighted normals of the surrounding triangles.
+    pub fn renormalize_normals(&mut self) {
+        let vertex_count = self.vertex_count();
+        if self.normals.len() != vertex_count * 3 {
+            self.normals = vec![0.0; vertex_count * 3];
+        }
+
+        let mut face_sums = vec![0.0f32; vertex_count * 3];
+        for tri in self.indices.chunks_exact(3) {
+            let n = self.face_normal(tri);
+            for &v in tri {
+                for k in 0..3 {
+                    face_sums[v as usize * 3 + k] += n[k];
+                }
+            }
+        }
+
+        for (n, fallback) in self.normals.chunks_exact_mut(3).zip(face_sums.chunks_exact(3)) {
+            let mut length = (n[0] * n[0] + n[1] * n[1] + n[2] * n[2]).sqrt();
+            if !length.is_finite() || length <= f32::EPSILON {
+                n.copy_from_slice(fallback);
+                length = (n[0] * n[0] + n[1] * n[1] + n[2] * n[2]).sqrt();
+            }
+            if length.is_finite() && length > f32::EPSILON {
+                n.iter_mut().for_each(|c| *c /= length);
+            } else {
+                n.copy_from_slice(&[0.0, 1.0, 0.0]);
+            }
+        }
+    }
+
+    /// Reports any problems with a freshly loaded mesh and repairs the ones that can be.
+    pub fn validated(mut self, name: &str) -> Mesh {
+        let issues = self.validate();
+        if issues.is_empty() {
+            return self;
+        }
+        println!("Found {} problems with {}:", issues.len(), name);
+        for issue in issues.iter().take(10) {
+            println!("    {}", issue);
+        }
+        if issues.len() > 10 {
+            println!("    ... and {} more", issues.len() - 10);
+        }
+        if issues.iter().any(MeshIssue::is_repairable) {
+            self.repair(&RepairOptions::for_issues(&issues));
+            println!("Repaired {}: {} points and {} triangles.", name, self.vertices.len() / 3, self.indices.len() / 3);
+        }
+        let left = self.validate();
+        if !left.is_empty() {
+            println!("Warning: {} problems with {} could not be repaired and may show up when drawing it, starting with: {}",
+                left.len(), name, left[0]);
+        }
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A unit square in the XZ plane facing up, as two triangles sharing the edge from 0 to 2
+    fn square(indices: Vec<u32>) -> Mesh {
+        Mesh {
+            vertices: vec![0.0, 0.0, 0.0, 0.0, 0.0, 1.0, 1.0, 0.0, 1.0, 1.0, 0.0, 0.0],
+            normals: [0.0, 1.0, 0.0].repeat(4),
+            colors: vec![1.0; 16],
+            index_count: indices.len() as i32,
+            indices,
+        }
+    }
+
+    fn up_square() -> Mesh {
+        square(vec![0, 1, 2, 0, 2, 3])
+    }
+
+    // The square with a third triangle standing up on its diagonal
+    fn finned_square() -> Mesh {
+        let mut mesh = square(vec![0, 1, 2, 0, 2, 3, 0, 2, 4]);
+        mesh.vertices.extend_from_slice(&[0.5, 1.0, 0.5]);
+        mesh.normals.extend_from_slice(&[0.0, 1.0, 0.0]);
+        mesh.colors.extend_from_slice(&[1.0; 4]);
+        mesh
+    }
+
+    fn facing_up(mesh: &Mesh) -> bool {
+        mesh.indices.chunks_exact(3).all(|t| mesh.face_normal(t)[1] > 0.0)
+    }
+
+    #[test]
+    fn validate_accepts_a_good_mesh() {
+        assert_eq!(up_square().validate(), vec![]);
+    }
+
+    #[test]
+    fn validate_finds_broken_indices() {
+        let mut mesh = square(vec![0, 1, 2, 0, 2, 7, 1]);
+        mesh.index_count = 3;
+        let issues = mesh.validate();
+        assert!(issues.contains(&MeshIssue::IndicesNotTriangles { len: 7 }));
+        assert!(issues.contains(&MeshIssue::IndexCountMismatch { index_count: 3, indices: 7 }));
+        assert!(issues.contains(&MeshIssue::IndexOutOfRange { triangle: 1, index: 7, vertex_count: 4 }));
+    }
+
+    #[test]
+    fn validate_finds_broken_attributes() {
+        let mut mesh = up_square();
+        mesh.vertices[3] = f32::NAN;
+        mesh.normals[6..9].copy_from_slice(&[0.0; 3]);
+        mesh.colors.pop();
+        let issues = mesh.validate();
+        assert!(issues.contains(&MeshIssue::NonFinitePosition { vertex: 1 }));
+        assert!(issues.contains(&MeshIssue::InvalidNormal { vertex: 2 }));
+        assert!(issues.contains(&MeshIssue::ColorCountMismatch { colors: 15, expected: 16 }));
+    }
+
+    #[test]
+    fn validate_finds_bad_triangles() {
+        assert_eq!(square(vec![0, 1, 2, 0, 2, 2]).validate(), vec![MeshIssue::DegenerateTriangle { triangle: 1 }]);
+        assert_eq!(square(vec![0, 1, 2, 0, 3, 2]).validate(), vec![MeshIssue::InconsistentWinding { triangle: 1 }]);
+        assert_eq!(finned_square().validate(), vec![MeshIssue::NonManifoldEdge { a: 0, b: 2, faces: 3 }]);
+    }
+
+    #[test]
+    fn weld_merges_matching_vertices() {
+        // The square with each triangle having its own corners
+        let mut mesh = up_square();
+        mesh.vertices = [&mesh.vertices[0..9], &mesh.vertices[0..3], &mesh.vertices[6..12]].concat();
+        mesh.normals = [0.0, 1.0, 0.0].repeat(6);
+        mesh.colors = vec![1.0; 24];
+        mesh.set_indices(vec![0, 1, 2, 3, 4, 5]);
+
+        mesh.weld_vertices(1e-5);
+        assert_eq!(mesh.vertices, up_square().vertices);
+        assert_eq!(mesh.indices, up_square().indices);
+        assert_eq!(mesh.normals.len(), 12);
+        assert_eq!(mesh.colors.len(), 16);
+    }
+
+    #[test]
+    fn weld_keeps_vertices_with_different_normals() {
+        let mut mesh = up_square();
+        mesh.vertices.extend_from_slice(&[0.0, 0.0, 0.0]);
+        mesh.normals.extend_from_slice(&[1.0, 0.0, 0.0]);
+        mesh.colors.extend_from_slice(&[1.0; 4]);
+        mesh.set_indices(vec![4, 1, 2, 0, 2, 3]);
+
+        mesh.weld_vertices(1e-5);
+        assert_eq!(mesh.vertices.len(), 15);
+        assert_eq!(mesh.indices, vec![4, 1, 2, 0, 2, 3]);
+    }
+
+    #[test]
+    fn drop_degenerate_keeps_only_triangles_with_area() {
+        let mut mesh = square(vec![0, 1, 2, 1, 1, 3, 0, 2, 3, 0, 1, 0]);
+        // A sliver along the edge from 0 to 2
+        mesh.vertices.extend_from_slice(&[0.5, 0.0, 0.5]);
+        mesh.normals.extend_from_slice(&[0.0, 1.0, 0.0]);
+        mesh.colors.extend_from_slice(&[1.0; 4]);
+        mesh.indices.extend_from_slice(&[0, 4, 2]);
+
+        mesh.drop_degenerate_triangles();
+        assert_eq!(mesh.indices, up_square().indices);
+        assert_eq!(mesh.index_count, 6);
+    }
+
+    #[test]
+    fn fix_winding_turns_a_flipped_triangle() {
+        let mut mesh = square(vec![0, 1, 2, 0, 3, 2]);
+        mesh.fix_winding();
+        assert_eq!(mesh.validate(), vec![]);
+        assert!(facing_up(&mesh));
+    }
+
+    #[test]
+    fn fix_winding_follows_the_vertex_normals() {
+        let mut mesh = square(vec![0, 2, 1, 0, 3, 2]);
+        mesh.fix_winding();
+        assert!(facing_up(&mesh));
+
+        mesh.normals = [0.0, -1.0, 0.0].repeat(4);
+        mesh.fix_winding();
+        assert!(mesh.indices.chunks_exact(3).all(|t| mesh.face_normal(t)[1] < 0.0));
+    }
+
+    #[test]
+    fn repair_options_only_cover_the_issues() {
+        let options = RepairOptions::for_issues(&[MeshIssue::DegenerateTriangle { triangle: 0 }]);
+        assert!(options.drop_degenerate && !options.fix_winding && !options.renormalize);
+        assert!(options.weld_epsilon.is_none());
+
+        let options = RepairOptions::for_issues(&[MeshIssue::InconsistentWinding { triangle: 0 }]);
+        assert!(options.fix_winding && options.weld_epsilon.is_some());
+    }
+
+    #[test]
+    fn validated_leaves_what_it_cannot_repair() {
+        let mesh = finned_square();
+        let validated = mesh.clone().validated("test");
+        assert_eq!(validated.indices, mesh.indices);
+        assert_eq!(validated.vertices, mesh.vertices);
+    }
+
+    #[test]
+    fn validated_repairs_what_it_can() {
+        let mesh = square(vec![0, 1, 2, 0, 3, 2, 1, 1, 3]).validated("test");
+        assert_eq!(mesh.validate(), vec![]);
+        assert!(facing_up(&mesh));
+        assert_eq!(mesh.indices.len(), 6);
+    }
+}
@@ -0,0 +1,155 @@
+extern crate nalgebra_glm as glm;
+
+use rand::{Rng, SeedableRng};
+
+use crate::gl_objects::Buffer;
+use crate::shader::{self, Shader, ShaderError};
+use crate::vertex_layout::{sealed, Pod};
+
+// Particles falling under gravity and bouncing off the ground, updated by a compute shader.
+// The same update is implemented on the CPU, to check the GPU version against.
+//
+// This is only a library for now: the renderer doesn't create or draw a ParticleSystem, it is
+// exercised by the test at the bottom of this file.
+
+const PARTICLE_BINDING: u32 = 0; // The binding of the Particles block in particles.comp
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Particle {
+    pub position : [f32; 4], // xyz: position, w: age in seconds
+    pub velocity : [f32; 4], // xyz: velocity, w: unused
+}
+
+// Two vec4s in the shader storage buffer, without padding
+const _: () = assert!(std::mem::size_of::<Particle>() == 32);
+impl sealed::Sealed for Particle {}
+impl Pod for Particle {}
+
+#[derive(Clone, Copy, Debug)]
+pub struct ParticleParams {
+    pub gravity       : glm::Vec3,
+    pub ground_height : f32,
+    pub restitution   : f32, // How much of the speed is kept when bouncing off the ground
+}
+
+impl Default for ParticleParams {
+    fn default() -> Self {
+        ParticleParams {
+            gravity: glm::vec3(0.0, -9.81, 0.0),
+            ground_height: 0.0,
+            restitution: 0.6,
+        }
+    }
+}
+
+pub struct ParticleSystem {
+    pub params : ParticleParams,
+    buffer     : Buffer,
+    count      : usize,
+    shader     : Shader,
+}
+
+impl ParticleSystem {
+    pub unsafe fn new(particles: &[Particle], params: ParticleParams) -> Result<Self, ShaderError> {
+        let shader = shader::from_files(&["shaders/particles.comp"])?;
+        let buffer = Buffer::with_data(gl::SHADER_STORAGE_BUFFER, particles, gl::DYNAMIC_DRAW);
+        gl::BindBuffer(gl::SHADER_STORAGE_BUFFER, 0);
+        Ok(ParticleSystem {
+            params,
+            buffer,
+            count: particles.len(),
+            shader,
+        })
+    }
+
+    // The particles live here, and can be drawn straight from it as well
+    pub fn buffer(&self) -> &Buffer {
+        &self.buffer
+    }
+
+    pub fn count(&self) -> usize {
+        self.count
+    }
+
+    pub unsafe fn update(&self, delta_time: f32) {
+        self.shader.set_i32("uCount", self.count as i32);
+        self.shader.set_f32("uDeltaTime", delta_time);
+        self.shader.set_vec3("uGravity", &self.params.gravity);
+        self.shader.set_f32("uGroundHeight", self.params.ground_height);
+        self.shader.set_f32("uRestitution", self.params.restitution);
+
+        self.buffer.bind_base(PARTICLE_BINDING);
+        self.shader.dispatch_invocations([self.count as u32, 1, 1]);
+        // Whatever reads the particles next, make sure it sees the new ones
+        shader::memory_barrier(
+            gl::SHADER_STORAGE_BARRIER_BIT | gl::VERTEX_ATTRIB_ARRAY_BARRIER_BIT | gl::BUFFER_UPDATE_BARRIER_BIT
+        );
+    }
+
+    pub unsafe fn read(&self) -> Vec<Particle> {
+        let particles = self.buffer.read();
+        gl::BindBuffer(gl::SHADER_STORAGE_BUFFER, 0);
+        particles
+    }
+}
+
+// The same as particles.comp
+pub fn update_cpu(particles: &mut [Particle], params: &ParticleParams, delta_time: f32) {
+    for p in particles {
+        for axis in 0..3 {
+            p.velocity[axis] += params.gravity[axis] * delta_time;
+            p.position[axis] += p.velocity[axis] * delta_time;
+        }
+        if p.position[1] < params.ground_height {
+            p.position[1] = params.ground_height;
+            p.velocity[1] = -p.velocity[1] * params.restitution;
+        }
+        p.position[3] += delta_time;
+    }
+}
+
+pub fn random_particles(count: usize, seed: u64) -> Vec<Particle> {
+    let mut rng = rand::rngs::StdRng::seed_from_u64(seed);
+    (0..count).map(|_| Particle {
+        position: [rng.gen_range(-50.0..50.0), rng.gen_range(0.0..100.0), rng.gen_range(-50.0..50.0), 0.0],
+        velocity: [rng.gen_range(-5.0..5.0), rng.gen_range(-5.0..20.0), rng.gen_range(-5.0..5.0), 0.0],
+    }).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Runs the compute shader and the CPU version side by side, and compares the results
+    #[test]
+    #[ignore = "needs a GPU"]
+    fn compute_shader_matches_cpu() {
+        const COUNT: usize = 10_000; // Not a multiple of the work group size, on purpose
+        const STEPS: usize = 120;
+        const DELTA_TIME: f32 = 1.0 / 60.0;
+        // The GPU may fuse multiplies and adds, so the results differ slightly
+        const TOLERANCE: f32 = 1e-3;
+
+        let mut expected = random_particles(COUNT, 1234);
+        let params = ParticleParams::default();
+        let actual = unsafe {
+            crate::test_context::make_current();
+            let system = ParticleSystem::new(&expected, params).unwrap_or_else(|e| panic!("{}", e));
+            for _ in 0..STEPS {
+                system.update(DELTA_TIME);
+            }
+            system.read()
+        };
+        for _ in 0..STEPS {
+            update_cpu(&mut expected, &params, DELTA_TIME);
+        }
+
+        assert_eq!(actual.len(), expected.len());
+        for (a, e) in actual.iter().zip(&expected) {
+            for (x, y) in a.position.iter().chain(&a.velocity).zip(e.position.iter().chain(&e.velocity)) {
+                assert!((x - y).abs() / y.abs().max(1.0) <= TOLERANCE, "GPU {:?}, CPU {:?}", a, e);
+            }
+        }
+    }
+}
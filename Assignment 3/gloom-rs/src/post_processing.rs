@@ -0,0 +1,263 @@
+extern crate nalgebra_glm as glm;
+
+use crate::gl_objects::{Texture, VertexArray};
+use crate::image_filters::{FilterKind, GpuFilters};
+use crate::render_target::{PreviousTarget, RenderTarget, RenderTargetDescription};
+use crate::shader::{Shader, ShaderError};
+use crate::shader_reload::{self, ReloadingShader};
+
+// Fullscreen passes applied to the rendered scene before it is shown. Bloom runs first, on the
+// HDR colors, then every enabled pass in order, each reading what the previous one drew.
+// An image filter can be run on the final colors last.
+
+pub const FULLSCREEN_VERTEX_SHADER: &str = "shaders/post/fullscreen.vert";
+const INTERMEDIATE_FORMAT: gl::types::GLenum = gl::RGBA16F; // Keeps HDR colors until tone mapping
+const BLOOM_BLUR_PASSES: usize = 2;                           // Horizontal and vertical blurs each
+
+// A single fullscreen pass. Its parameters are float uniforms, set every time it is drawn.
+pub struct PostPass {
+    pub name    : &'static str,
+    pub enabled : bool,
+    shader      : ReloadingShader,
+    params      : Vec<(String, f32)>,
+}
+
+impl PostPass {
+    // The fragment shader reads the previous pass from `uInput`, at `uv`
+    pub unsafe fn new(name: &'static str, fragment_shader: &str, params: &[(&str, f32)]) -> Result<Self, ShaderError> {
+        Ok(PostPass {
+            name,
+            enabled: true,
+            shader: ReloadingShader::new(&[FULLSCREEN_VERTEX_SHADER, fragment_shader], shader_reload::no_check)?,
+            params: params.iter().map(|&(uniform, value)| (uniform.to_string(), value)).collect(),
+        })
+    }
+
+    pub fn params(&self) -> &[(String, f32)] {
+        &self.params
+    }
+
+    pub fn param(&self, uniform: &str) -> Option<f32> {
+        self.params.iter().find(|(name, _)| name == uniform).map(|&(_, value)| value)
+    }
+
+    // Returns false if the pass has no such parameter
+    pub fn set_param(&mut self, uniform: &str, value: f32) -> bool {
+        match self.params.iter_mut().find(|(name, _)| name == uniform) {
+            Some(param) => {
+                param.1 = value;
+                true
+            },
+            None => false,
+        }
+    }
+
+    // Draws into the bound target
+    unsafe fn draw(&self, input: &Texture) {
+        for (uniform, value) in &self.params {
+            self.shader.set_f32(uniform, *value);
+        }
+        draw_fullscreen(&self.shader, &[("uInput", input)]);
+    }
+}
+
+// Bright parts of the scene bleeding into their surroundings: they are extracted at half
+// resolution, blurred, and added back onto the scene
+pub struct Bloom {
+    pub enabled   : bool,
+    pub threshold : f32,   // Brightness where blooming starts, 1 being white
+    pub knee      : f32,   // Width of the soft transition around the threshold
+    pub intensity : f32,   // How much of the blurred light is added back
+    extract       : ReloadingShader,
+    blur          : ReloadingShader,
+    combine       : ReloadingShader,
+    targets       : [RenderTarget; 2],
+}
+
+impl Bloom {
+    unsafe fn new(width: i32, height: i32) -> Result<Self, ShaderError> {
+        let shader = |fragment| ReloadingShader::new(&[FULLSCREEN_VERTEX_SHADER, fragment], shader_reload::no_check);
+        Ok(Bloom {
+            enabled: true,
+            threshold: 1.0,
+            knee: 0.5,
+            intensity: 0.6,
+            extract: shader("shaders/post/bloom_extract.frag")?,
+            blur: shader("shaders/post/blur.frag")?,
+            combine: shader("shaders/post/bloom_combine.frag")?,
+            targets: [intermediate_target(width / 2, height / 2), intermediate_target(width / 2, height / 2)],
+        })
+    }
+
+    unsafe fn resize(&mut self, width: i32, height: i32) -> Result<(), String> {
+        for target in &mut self.targets {
+            target.resize(width / 2, height / 2)?;
+        }
+        Ok(())
+    }
+
+    // Draws the scene with bloom added into `output`
+    unsafe fn apply(&self, input: &Texture, output: &RenderTarget) {
+        let [a, b] = &self.targets;
+        a.bind();
+        self.extract.set_f32("uThreshold", self.threshold);
+        self.extract.set_f32("uKnee", self.knee);
+        draw_fullscreen(&self.extract, &[("uInput", input)]);
+
+        for _ in 0..BLOOM_BLUR_PASSES {
+            b.bind();
+            self.blur.set_vec2("uDirection", &glm::vec2(1.0, 0.0));
+            draw_fullscreen(&self.blur, &[("uInput", a.color(0))]);
+            a.bind();
+            self.blur.set_vec2("uDirection", &glm::vec2(0.0, 1.0));
+            draw_fullscreen(&self.blur, &[("uInput", b.color(0))]);
+        }
+
+        output.bind();
+        self.combine.set_f32("uIntensity", self.intensity);
+        draw_fullscreen(&self.combine, &[("uInput", input), ("uBloom", a.color(0))]);
+    }
+}
+
+pub struct PostProcessing {
+    pub bloom   : Bloom,
+    pub passes  : Vec<PostPass>,      // Applied in this order, after the bloom
+    pub filter  : Option<FilterKind>, // Shown in place of the colors after everything else, if any
+    pub filters : GpuFilters,
+    targets     : [RenderTarget; 2],  // Each pass reads from one and draws into the other
+    empty_vao   : VertexArray,        // The fullscreen triangle needs no vertex data, but a VAO has to be bound
+}
+
+impl PostProcessing {
+    pub unsafe fn new(width: i32, height: i32) -> Result<Self, ShaderError> {
+        let passes = vec![
+            PostPass::new("tone mapping", "shaders/post/tonemap.frag", &[("uExposure", 0.6)])?,
+            PostPass::new("gamma correction", "shaders/post/gamma.frag", &[("uGamma", 2.2)])?,
+            PostPass::new("FXAA", "shaders/post/fxaa.frag", &[
+                ("uSpanMax", 8.0),
+                ("uReduceMin", 1.0 / 128.0),
+                ("uReduceMul", 1.0 / 8.0),
+            ])?,
+            PostPass::new("vignette", "shaders/post/vignette.frag", &[
+                ("uStrength", 0.4),
+                ("uRadius", 0.6),
+                ("uSoftness", 0.5),
+            ])?,
+        ];
+        Ok(PostProcessing {
+            bloom: Bloom::new(width, height)?,
+            passes,
+            filter: None,
+            filters: GpuFilters::new()?,
+            targets: [intermediate_target(width, height), intermediate_target(width, height)],
+            empty_vao: VertexArray::new(),
+        })
+    }
+
+    pub unsafe fn resize(&mut self, width: i32, height: i32) -> Result<(), String> {
+        for target in &mut self.targets {
+            target.resize(width, height)?;
+        }
+        self.bloom.resize(width, height)
+    }
+
+    pub fn pass_mut(&mut self, name: &str) -> Option<&mut PostPass> {
+        self.passes.iter_mut().find(|pass| pass.name == name)
+    }
+
+    // Names of all effects in the order they are applied, and whether they are enabled
+    pub fn effects(&self) -> Vec<(&'static str, bool)> {
+        let mut effects = vec![("bloom", self.bloom.enabled)];
+        effects.extend(self.passes.iter().map(|pass| (pass.name, pass.enabled)));
+        effects
+    }
+
+    // Switches the effect at the given index in effects() on or off, returning its new state
+    pub fn toggle(&mut self, index: usize) -> Option<(&'static str, bool)> {
+        if index == 0 {
+            self.bloom.enabled = !self.bloom.enabled;
+            return Some(("bloom", self.bloom.enabled));
+        }
+        let pass = self.passes.get_mut(index - 1)?;
+        pass.enabled = !pass.enabled;
+        Some((pass.name, pass.enabled))
+    }
+
+    // Goes from no filter through each of them in turn, returning the new one
+    pub fn cycle_filter(&mut self) -> Option<FilterKind> {
+        self.filter = match self.filter {
+            None => Some(FilterKind::ALL[0]),
+            Some(kind) => {
+                let i = FilterKind::ALL.iter().position(|&k| k == kind).unwrap();
+                FilterKind::ALL.get(i + 1).copied()
+            },
+        };
+        self.filter
+    }
+
+    /// Runs the enabled effects on the first color attachment of the scene,
+    /// and copies the result into the viewport of the output.
+    pub unsafe fn apply(&mut self, scene: &RenderTarget, output: &PreviousTarget) {
+        scene.resolve();
+        gl::Disable(gl::DEPTH_TEST);
+        gl::Disable(gl::BLEND);
+        gl::Disable(gl::CULL_FACE);
+        self.empty_vao.bind();
+
+        let mut input = scene.color(0);
+        let mut result: Option<usize> = None; // The target holding the latest result
+        let mut next = 0;
+        if self.bloom.enabled {
+            self.bloom.apply(input, &self.targets[next]);
+            input = self.targets[next].color(0);
+            result = Some(next);
+            next = 1 - next;
+        }
+        for pass in self.passes.iter().filter(|pass| pass.enabled) {
+            self.targets[next].bind();
+            pass.draw(input);
+            input = self.targets[next].color(0);
+            result = Some(next);
+            next = 1 - next;
+        }
+        if let Some(kind) = self.filter {
+            self.filters.apply(kind, input, &self.targets[next]);
+            result = Some(next);
+        }
+
+        match result {
+            Some(i) => self.targets[i].blit_to(output.framebuffer(), output.viewport()),
+            None => scene.blit_to(output.framebuffer(), output.viewport()),
+        }
+
+        gl::BindVertexArray(0);
+        gl::ActiveTexture(gl::TEXTURE0);
+        gl::Enable(gl::DEPTH_TEST);
+        gl::Enable(gl::BLEND);
+    }
+
+    /// Rebuilds the shaders whose files were edited, see ReloadingShader::poll
+    pub unsafe fn reload_shaders(&mut self) -> Vec<(Result<(), ShaderError>, String)> {
+        let mut shaders: Vec<&mut ReloadingShader> = vec![&mut self.bloom.extract, &mut self.bloom.blur, &mut self.bloom.combine];
+        shaders.extend(self.passes.iter_mut().map(|pass| &mut pass.shader));
+        let mut results: Vec<(Result<(), ShaderError>, String)> = shaders.into_iter()
+            .filter_map(|shader| shader.poll().map(|result| (result, shader.paths().join(", "))))
+            .collect();
+        results.extend(self.filters.reload_shaders());
+        results
+    }
+}
+
+unsafe fn intermediate_target(width: i32, height: i32) -> RenderTarget {
+    RenderTarget::new(&RenderTargetDescription::new().color(INTERMEDIATE_FORMAT), width, height)
+        .unwrap_or_else(|e| panic!("{}", e))
+}
+
+// Draws into the bound target with the textures bound to consecutive units
+pub unsafe fn draw_fullscreen(shader: &Shader, inputs: &[(&str, &Texture)]) {
+    shader.activate();
+    for (unit, (uniform, texture)) in inputs.iter().enumerate() {
+        shader.set_texture(uniform, unit as u32, gl::TEXTURE_2D, texture.id());
+    }
+    gl::DrawArrays(gl::TRIANGLES, 0, 3);
+}
@@ -0,0 +1,286 @@
+use crate::gl_objects::{Framebuffer, Renderbuffer, Texture};
+
+// Offscreen framebuffers to render into, with textures as attachments so that later passes can
+// sample what was drawn. A multisampled target draws into renderbuffers instead, which are
+// resolved into the textures when needed.
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct RenderTargetDescription {
+    pub colors  : Vec<gl::types::GLenum>,    // Internal formats of the color attachments, in order
+    pub depth   : Option<gl::types::GLenum>, // Internal format of the depth attachment, if any
+    pub samples : i32,                       // Above 1 to multisample
+}
+
+impl RenderTargetDescription {
+    pub fn new() -> Self {
+        RenderTargetDescription { colors: vec![], depth: None, samples: 0 }
+    }
+
+    pub fn color(mut self, internal_format: gl::types::GLenum) -> Self {
+        self.colors.push(internal_format);
+        self
+    }
+
+    pub fn depth(mut self, internal_format: gl::types::GLenum) -> Self {
+        self.depth = Some(internal_format);
+        self
+    }
+
+    pub fn samples(mut self, samples: i32) -> Self {
+        self.samples = samples;
+        self
+    }
+}
+
+// The drawing into a multisampled target goes here
+struct Multisampled {
+    framebuffer   : Framebuffer,
+    renderbuffers : Vec<Renderbuffer>,
+}
+
+pub struct RenderTarget {
+    description  : RenderTargetDescription,
+    width        : i32,
+    height       : i32,
+    framebuffer  : Framebuffer,       // With the textures attached
+    colors       : Vec<Texture>,
+    depth        : Option<Texture>,
+    multisampled : Option<Multisampled>,
+}
+
+impl RenderTarget {
+    pub unsafe fn new(description: &RenderTargetDescription, width: i32, height: i32) -> Result<Self, String> {
+        let previous = PreviousTarget::save();
+        let result = RenderTarget::create(description, width.max(1), height.max(1));
+        previous.restore();
+        result
+    }
+
+    unsafe fn create(description: &RenderTargetDescription, width: i32, height: i32) -> Result<Self, String> {
+        // Caught here, as the driver would raise an error about it
+        let (mut max_texture_size, mut max_renderbuffer_size) = (0, 0);
+        gl::GetIntegerv(gl::MAX_TEXTURE_SIZE, &mut max_texture_size);
+        gl::GetIntegerv(gl::MAX_RENDERBUFFER_SIZE, &mut max_renderbuffer_size);
+        let max_size = max_texture_size.min(max_renderbuffer_size);
+        if width > max_size || height > max_size {
+            return Err(format!("A {}x{} render target is too large, the limit is {}x{}", width, height, max_size, max_size));
+        }
+
+        let framebuffer = Framebuffer::new();
+        framebuffer.bind();
+        let colors: Vec<Texture> = description.colors.iter()
+            .map(|&format| Texture::storage_2d(width, height, format, 1))
+            .collect();
+        for (i, texture) in colors.iter().enumerate() {
+            gl::FramebufferTexture2D(gl::FRAMEBUFFER, gl::COLOR_ATTACHMENT0 + i as u32, gl::TEXTURE_2D, texture.id(), 0);
+        }
+        let depth = description.depth.map(|format| Texture::storage_2d(width, height, format, 1));
+        if let Some(texture) = &depth {
+            gl::FramebufferTexture2D(gl::FRAMEBUFFER, gl::DEPTH_ATTACHMENT, gl::TEXTURE_2D, texture.id(), 0);
+        }
+        set_draw_buffers(colors.len());
+        check_complete("render target")?;
+
+        let mut max_samples = 0;
+        gl::GetIntegerv(gl::MAX_SAMPLES, &mut max_samples);
+        let samples = description.samples.min(max_samples);
+        let multisampled = if samples > 1 {
+            let framebuffer = Framebuffer::new();
+            framebuffer.bind();
+            let mut renderbuffers = vec![];
+            for (i, &format) in description.colors.iter().enumerate() {
+                let renderbuffer = Renderbuffer::new(width, height, format, samples);
+                gl::FramebufferRenderbuffer(gl::FRAMEBUFFER, gl::COLOR_ATTACHMENT0 + i as u32, gl::RENDERBUFFER, renderbuffer.id());
+                renderbuffers.push(renderbuffer);
+            }
+            if let Some(format) = description.depth {
+                let renderbuffer = Renderbuffer::new(width, height, format, samples);
+                gl::FramebufferRenderbuffer(gl::FRAMEBUFFER, gl::DEPTH_ATTACHMENT, gl::RENDERBUFFER, renderbuffer.id());
+                renderbuffers.push(renderbuffer);
+            }
+            set_draw_buffers(description.colors.len());
+            check_complete("multisampled render target")?;
+            Some(Multisampled { framebuffer, renderbuffers })
+        } else {
+            None
+        };
+
+        Ok(RenderTarget {
+            description: description.clone(),
+            width,
+            height,
+            framebuffer,
+            colors,
+            depth,
+            multisampled,
+        })
+    }
+
+    pub fn width(&self) -> i32 {
+        self.width
+    }
+
+    pub fn height(&self) -> i32 {
+        self.height
+    }
+
+    pub fn is_multisampled(&self) -> bool {
+        self.multisampled.is_some()
+    }
+
+    // Only up to date after resolve() if multisampled
+    pub fn color(&self, index: usize) -> &Texture {
+        &self.colors[index]
+    }
+
+    pub fn depth(&self) -> Option<&Texture> {
+        self.depth.as_ref()
+    }
+
+    /// Recreates the attachments at the new size, losing their contents. Does nothing if the size is the same.
+    pub unsafe fn resize(&mut self, width: i32, height: i32) -> Result<(), String> {
+        if (width.max(1), height.max(1)) == (self.width, self.height) {
+            return Ok(());
+        }
+        *self = RenderTarget::new(&self.description, width, height)?;
+        Ok(())
+    }
+
+    // Draws go into the target from now on, covering all of it
+    pub unsafe fn bind(&self) {
+        match &self.multisampled {
+            Some(multisampled) => multisampled.framebuffer.bind(),
+            None => self.framebuffer.bind(),
+        }
+        gl::Viewport(0, 0, self.width, self.height);
+    }
+
+    /// Averages the samples into the textures. Does nothing if the target isn't multisampled.
+    pub unsafe fn resolve(&self) {
+        let multisampled = match &self.multisampled {
+            Some(multisampled) => multisampled,
+            None => return,
+        };
+        let previous = PreviousTarget::save();
+        gl::BindFramebuffer(gl::READ_FRAMEBUFFER, multisampled.framebuffer.id());
+        gl::BindFramebuffer(gl::DRAW_FRAMEBUFFER, self.framebuffer.id());
+        // One attachment at a time, as blits go from the read buffer to all draw buffers
+        for i in 0..self.colors.len() as u32 {
+            gl::ReadBuffer(gl::COLOR_ATTACHMENT0 + i);
+            gl::DrawBuffer(gl::COLOR_ATTACHMENT0 + i);
+            gl::BlitFramebuffer(0, 0, self.width, self.height, 0, 0, self.width, self.height, gl::COLOR_BUFFER_BIT, gl::NEAREST);
+        }
+        if self.depth.is_some() {
+            gl::BlitFramebuffer(0, 0, self.width, self.height, 0, 0, self.width, self.height, gl::DEPTH_BUFFER_BIT, gl::NEAREST);
+        }
+        set_draw_buffers(self.colors.len());
+        gl::ReadBuffer(gl::COLOR_ATTACHMENT0);
+        previous.restore();
+    }
+
+    /// Resolves the first color attachment and copies it into the viewport of another framebuffer,
+    /// e.g. 0 for the window, stretching it if the sizes differ.
+    pub unsafe fn blit_to(&self, framebuffer: u32, viewport: [i32; 4]) {
+        self.resolve();
+        let previous = PreviousTarget::save();
+        gl::BindFramebuffer(gl::READ_FRAMEBUFFER, self.framebuffer.id());
+        gl::ReadBuffer(gl::COLOR_ATTACHMENT0);
+        gl::BindFramebuffer(gl::DRAW_FRAMEBUFFER, framebuffer);
+        let [x, y, width, height] = viewport;
+        gl::BlitFramebuffer(0, 0, self.width, self.height, x, y, x + width, y + height, gl::COLOR_BUFFER_BIT, gl::LINEAR);
+        previous.restore();
+    }
+
+    /// The first color attachment as an image, top row first
+    pub unsafe fn read_rgba(&self) -> image::RgbaImage {
+        self.resolve();
+        read_framebuffer_rgba(self.framebuffer.id(), gl::COLOR_ATTACHMENT0, self.width, self.height)
+    }
+
+    /// The red channel of the first color attachment as floats, top row first, e.g. for grayscale results
+    pub unsafe fn read_red_f32(&self) -> image::ImageBuffer<image::Luma<f32>, Vec<f32>> {
+        self.resolve();
+        let previous = PreviousTarget::save();
+        gl::BindFramebuffer(gl::READ_FRAMEBUFFER, self.framebuffer.id());
+        gl::ReadBuffer(gl::COLOR_ATTACHMENT0);
+        let mut values = vec![0.0f32; (self.width * self.height) as usize];
+        gl::ReadPixels(0, 0, self.width, self.height, gl::RED, gl::FLOAT, values.as_mut_ptr() as *mut _);
+        previous.restore();
+
+        let image = image::ImageBuffer::from_raw(self.width as u32, self.height as u32, values).unwrap();
+        image::imageops::flip_vertical(&image)
+    }
+}
+
+/// Reads the pixels of one buffer of a framebuffer as an image, top row first.
+/// For the window, pass framebuffer 0 and gl::BACK or gl::FRONT.
+pub unsafe fn read_framebuffer_rgba(framebuffer: u32, buffer: gl::types::GLenum, width: i32, height: i32) -> image::RgbaImage {
+    let previous = PreviousTarget::save();
+    gl::BindFramebuffer(gl::READ_FRAMEBUFFER, framebuffer);
+    gl::ReadBuffer(buffer);
+    gl::PixelStorei(gl::PACK_ALIGNMENT, 1);
+    let mut pixels = vec![0u8; (width * height * 4) as usize];
+    gl::ReadPixels(0, 0, width, height, gl::RGBA, gl::UNSIGNED_BYTE, pixels.as_mut_ptr() as *mut _);
+    gl::PixelStorei(gl::PACK_ALIGNMENT, 4);
+    previous.restore();
+
+    // OpenGL starts at the bottom row
+    let image = image::RgbaImage::from_raw(width as u32, height as u32, pixels).unwrap();
+    image::imageops::flip_vertical(&image)
+}
+
+// The framebuffers and viewport in use, to go back to after drawing into a render target
+pub struct PreviousTarget {
+    draw_framebuffer : u32,
+    read_framebuffer : u32,
+    viewport         : [i32; 4],
+}
+
+impl PreviousTarget {
+    pub unsafe fn save() -> Self {
+        let (mut draw, mut read) = (0, 0);
+        let mut viewport = [0; 4];
+        gl::GetIntegerv(gl::DRAW_FRAMEBUFFER_BINDING, &mut draw);
+        gl::GetIntegerv(gl::READ_FRAMEBUFFER_BINDING, &mut read);
+        gl::GetIntegerv(gl::VIEWPORT, viewport.as_mut_ptr());
+        PreviousTarget {
+            draw_framebuffer: draw as u32,
+            read_framebuffer: read as u32,
+            viewport,
+        }
+    }
+
+    pub fn framebuffer(&self) -> u32 {
+        self.draw_framebuffer
+    }
+
+    pub fn viewport(&self) -> [i32; 4] {
+        self.viewport
+    }
+
+    pub unsafe fn restore(&self) {
+        gl::BindFramebuffer(gl::DRAW_FRAMEBUFFER, self.draw_framebuffer);
+        gl::BindFramebuffer(gl::READ_FRAMEBUFFER, self.read_framebuffer);
+        let [x, y, width, height] = self.viewport;
+        gl::Viewport(x, y, width, height);
+    }
+}
+
+// Draws go to every color attachment of the bound framebuffer, or nowhere for depth only targets
+unsafe fn set_draw_buffers(count: usize) {
+    if count == 0 {
+        gl::DrawBuffer(gl::NONE);
+        gl::ReadBuffer(gl::NONE);
+    } else {
+        let buffers: Vec<u32> = (0..count as u32).map(|i| gl::COLOR_ATTACHMENT0 + i).collect();
+        gl::DrawBuffers(count as i32, buffers.as_ptr());
+    }
+}
+
+unsafe fn check_complete(what: &str) -> Result<(), String> {
+    let status = gl::CheckFramebufferStatus(gl::FRAMEBUFFER);
+    if status != gl::FRAMEBUFFER_COMPLETE {
+        return Err(format!("The {} is incomplete (status 0x{:X})", what, status));
+    }
+    Ok(())
+}
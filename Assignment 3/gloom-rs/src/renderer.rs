@@ -1,10 +1,23 @@
+use crate::articulated::{ArticulatedModel, ModelDescription, ModelInstance};
 use crate::camera::Camera;
-use crate::graphics;
-use crate::mesh::{Helicopter, Terrain};
+use crate::debug_lines::DebugLines;
+use crate::gl_objects::{StreamBuffer, VertexArray};
+use crate::graphics::{self, GpuMesh};
+use crate::heightfield::HeightField;
+use crate::light::{self, Light};
+use crate::material::Material;
+use crate::mesh::{Boundary, Mesh, Terrain, TERRAIN_COLOR};
+use crate::post_processing::PostProcessing;
+use crate::render_target::{PreviousTarget, RenderTarget, RenderTargetDescription};
 use crate::scene::Scene;
-use crate::scene_graph::{Node, SceneNode};
-use crate::shader;
+use crate::scene_graph::{LodChain, Node, SceneNode};
+use crate::shader::{Shader, ShaderError};
+use crate::shader_library::{ShaderFeatures, ShaderLibrary};
+use crate::shadow::ShadowMap;
+use crate::terrain_tessellation::TessellatedTerrain;
 use crate::toolbox;
+use crate::uniform_buffer::{FrameUniforms, LightUniform, UniformBuffer, FRAME_UNIFORMS_BINDING, MAX_LIGHTS};
+use crate::vertex_layout::{sealed, Pod, VertexLayout};
 
 // this is needed for dereferencing raw pointers in the scene graph
 
@@ -12,215 +25,600 @@ extern crate nalgebra_glm as glm;
 
 #[inline]
 fn node_ref(n: &Node) -> &SceneNode {
-    unsafe { &**n }
+    n
 }
 #[inline]
 fn node_mut(n: &mut Node) -> &mut SceneNode {
-    unsafe { &mut ***n }
+    n
+}
+
+// How high above the ground the helicopters fly
+const HELICOPTER_CRUISE_HEIGHT: f32 = 20.0;
+const HELICOPTER_GROUND_CLEARANCE: f32 = 8.0;
+
+// Camera distances at which the terrain and helicopter parts switch to simpler meshes
+const TERRAIN_LOD_DISTANCES: [f32; 3] = [0.0, 100.0, 250.0];
+const HELICOPTER_LOD_DISTANCES: [f32; 3] = [0.0, 60.0, 180.0];
+const LOD_TRIANGLE_RATIO: f32 = 0.3;
+
+// The terrain is cut into this many chunks along X and Z, each choosing its own level of detail.
+// As a whole the camera is almost always above it, which would keep it at full detail.
+const TERRAIN_CHUNKS: usize = 8;
+
+// Tints of the terrain at each level of detail, when showing which chunk uses which
+const TERRAIN_LOD_COLORS: [[f32; 4]; 3] = [[1.0, 0.55, 0.55, 1.0], [0.55, 1.0, 0.55, 1.0], [0.55, 0.55, 1.0, 1.0]];
+
+const HELICOPTER_COUNT: usize = 5;
+
+// Per instance matrices are streamed to the GPU every frame. There is room for this many
+// to begin with, and more is made when a frame needs it.
+const INITIAL_INSTANCES_PER_FRAME: usize = 64 * 1024;
+const INSTANCE_MATRIX_LOCATION: u32 = 3;
+const INSTANCE_NORMAL_MATRIX_LOCATION: u32 = 7;
+
+// The sun, shining on everything in the scene
+const SUN_DIRECTION: [f32; 3] = [0.8, -0.5, 0.6];
+const SUN_COLOR: [f32; 3] = [1.0, 1.0, 1.0];
+const SUN_INTENSITY: f32 = 1.0;
+const AMBIENT_LIGHT: [f32; 3] = [0.12, 0.12, 0.16];
+const SKY_COLOR: [f32; 3] = [0.035, 0.046, 0.078]; // As displayed
+
+// The searchlight under each helicopter, sweeping the ground in a slow circle
+const SPOTLIGHT_COLOR: [f32; 3] = [1.0, 0.92, 0.75];
+const SPOTLIGHT_INTENSITY: f32 = 500.0;
+const SPOTLIGHT_RANGE: f32 = 120.0;
+const SPOTLIGHT_INNER_ANGLE: f32 = 0.2;   // Radians
+const SPOTLIGHT_OUTER_ANGLE: f32 = 0.3;
+const SPOTLIGHT_SWEEP_ANGLE: f32 = 0.5;   // How far off straight down it points
+const SPOTLIGHT_SWEEP_SPEED: f32 = 0.8;   // Radians per second
+
+// The scene is drawn into an offscreen HDR target with this many samples per pixel,
+// then post-processed into the window
+const SCENE_SAMPLES: i32 = 4;
+
+// The scene shader files, built into one variant per material that is used
+const SCENE_SHADER: [&str; 2] = ["shaders/simple.vert", "shaders/simple.frag"];
+
+// What is streamed per instance, laid out as aModelMatrix and aNormalMatrix in simple.vert
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct Instance {
+    model_matrix  : glm::Mat4,
+    normal_matrix : glm::Mat3, // The inverse transpose of the model matrix, for the normals
+}
+
+impl Instance {
+    fn new(model_matrix: glm::Mat4) -> Self {
+        // A node scaled to nothing isn't seen, and gets no normals
+        let normal_matrix = glm::mat4_to_mat3(&model_matrix)
+            .try_inverse()
+            .unwrap_or_else(glm::Mat3::zeros)
+            .transpose();
+        Instance { model_matrix, normal_matrix }
+    }
+}
+
+// 16 + 9 floats, without padding
+const _: () = assert!(std::mem::size_of::<Instance>() == 100);
+impl sealed::Sealed for Instance {}
+impl Pod for Instance {}
+
+// All visible nodes drawing the same mesh with the same material, found while traversing the
+// scene graph. They are drawn together with a single instanced draw call.
+struct DrawBatch {
+    vao_id          : u32,
+    index_count     : i32,
+    material        : Material,
+    instances       : Vec<Instance>,
+    instance_offset : isize, // Where the instances are in the stream this frame
+}
+
+// Uploads every level of detail of the mesh
+unsafe fn create_lod_vaos(mesh: &Mesh, levels: usize, boundary: Boundary) -> Vec<VertexArray> {
+    mesh.generate_lods(levels, LOD_TRIANGLE_RATIO, boundary)
+        .iter()
+        .map(|lod| graphics::create_vao(&lod.vertices, &lod.indices, &lod.colors, &lod.normals))
+        .collect()
+}
+
+// Same as above, keeping the meshes around to be edited
+unsafe fn create_lod_meshes(mesh: &Mesh, levels: usize, boundary: Boundary) -> Vec<GpuMesh> {
+    mesh.generate_lods(levels, LOD_TRIANGLE_RATIO, boundary)
+        .into_iter()
+        .map(|lod| GpuMesh::new(lod))
+        .collect()
+}
+
+// The scene shader has to read the mesh data and frame uniforms as we lay them out
+fn check_scene_shader(shader: &Shader) -> Result<(), String> {
+    unsafe {
+        Mesh::vertex_layout()
+            .validate_against_program(shader.program_id())
+            .map_err(|e| format!("The mesh data doesn't fit the shader: {}", e))?;
+        FrameUniforms::check_layout(shader.program_id())
+            .map_err(|e| format!("The frame uniforms don't fit the shader: {}", e))
+    }
+}
+
+// Streams the instances of the batch once per frame, for every pass to draw them from.
+// The stream has to have room for them, see Renderer::reserve_instances.
+unsafe fn upload_instances(stream: &mut StreamBuffer, batch: &mut DrawBatch) {
+    batch.instance_offset = stream.push(&batch.instances)
+        .expect("The instance stream is out of room, it should have been grown to fit the frame");
+}
+
+// Draws every instance of the batch, with the program, the batch's VAO and the stream bound
+unsafe fn draw_instances(layout: &VertexLayout, batch: &DrawBatch) {
+    layout.apply_at(0, batch.instance_offset);
+    gl::DrawElementsInstanced(
+        gl::TRIANGLES,
+        batch.index_count,
+        gl::UNSIGNED_INT,
+        std::ptr::null(),
+        batch.instances.len() as i32,
+    );
+}
+
+fn lod_chain<'a>(mesh: &Mesh, vaos: impl Iterator<Item = &'a VertexArray>, distances: &[f32]) -> LodChain {
+    let mut chain = LodChain::new(mesh.bounds());
+    for (vao, &distance) in vaos.zip(distances) {
+        chain.push(vao.id(), vao.index_count(), distance);
+    }
+    chain
 }
 
 pub struct Renderer {
     pub root_node: Node,
-    pub helicopters: Vec<Node>, 
+    pub helicopters: Vec<ModelInstance>, 
+    helicopter_spotlights: Vec<Node>,  // One per helicopter, under its root
+    pub terrain: HeightField,
+    pub tessellated_terrain: TessellatedTerrain,
+    pub use_tessellated_terrain: bool, // Draw the terrain from the heightmap instead of the mesh
+    pub shadow_map: ShadowMap,
+    pub shadows_enabled: bool,
+    terrain_vao_ids: Vec<u32>,         // The terrain mesh, skipped when tessellating
+    terrain_meshes: Vec<Vec<GpuMesh>>, // Every level of detail of every terrain chunk
+    show_terrain_lods: bool,           // Whether the terrain is tinted by level of detail
+    pub vertex_arrays: Vec<VertexArray>,
+    pub debug_lines: DebugLines,
+    pub show_terrain_probes: bool, // Draw the terrain height and normal found below each helicopter
     // Shader + uniforms
-    pub shader_program: shader::Shader,
-    pub alpha_location: i32,
-    pub mvp_matrix_location: i32,
-    pub model_matrix_location: i32,
+    pub shaders: ShaderLibrary,
+    // Camera and lights, shared by all shader programs
+    frame_uniforms: UniformBuffer<FrameUniforms>,
+    pub max_lights: usize, // The most relevant lights in the scene are used, up to MAX_LIGHTS
+    lights: Vec<LightUniform>,
+    // Where the scene is drawn before it is shown
+    scene_target: RenderTarget,
+    pub post_processing: PostProcessing,
+    // Instanced drawing
+    batches: Vec<DrawBatch>,
+    instance_stream: StreamBuffer,
+    instance_layout: VertexLayout,
 }
 
 impl Renderer {
     pub unsafe fn new(_scene: &Scene) -> Self {
     
-        let helicopter_model = Helicopter::load("resources/helicopter.obj");
+        let helicopter_model = ArticulatedModel::load("resources/helicopter.obj", &ModelDescription::helicopter());
         let terrain = Terrain::load("resources/lunarsurface.obj");
-
-        // creating terrain vao
-        let terrain_vao = graphics::create_vao(
-            &terrain.vertices,
-            &terrain.indices,
-            &terrain.colors,
-            &terrain.normals,
+        let terrain_heights = HeightField::new(&terrain);
+
+        // creating terrain vaos per chunk, along with simpler versions for when it is far away.
+        // The chunk outlines stay put, so that neighbours at different levels still meet.
+        println!("Generating levels of detail...");
+        let before = std::time::Instant::now();
+        let terrain_chunks = terrain.split_into_chunks(TERRAIN_CHUNKS, TERRAIN_CHUNKS);
+        let terrain_lods: Vec<Vec<GpuMesh>> = terrain_chunks
+            .iter()
+            .map(|chunk| create_lod_meshes(chunk, TERRAIN_LOD_DISTANCES.len(), Boundary::Locked))
+            .collect();
+
+        // creating helicopter vaos only once, one chain of detail levels per part
+        let helicopter_lods: Vec<Vec<VertexArray>> = helicopter_model.parts
+            .iter()
+            .map(|part| create_lod_vaos(&part.mesh, HELICOPTER_LOD_DISTANCES.len(), Boundary::Weighted))
+            .collect();
+        let after = std::time::Instant::now();
+        let terrain_triangles: Vec<i32> = (0..TERRAIN_LOD_DISTANCES.len())
+            .map(|level| terrain_lods.iter().map(|lods| lods[level].vao.index_count() / 3).sum())
+            .collect();
+        let helicopter_triangles: Vec<i32> = (0..HELICOPTER_LOD_DISTANCES.len())
+            .map(|level| helicopter_lods.iter().map(|lods| lods[level].index_count() / 3).sum())
+            .collect();
+        println!("Done in {:.3}ms, with {:?} terrain and {:?} helicopter triangles.",
+            after.duration_since(before).as_micros() as f32 / 1e3,
+            terrain_triangles,
+            helicopter_triangles,
         );
 
-        // creating helicopter vaos only once
-        let body_vao = graphics::create_vao(
-            &helicopter_model.body.vertices,
-            &helicopter_model.body.indices,
-            &helicopter_model.body.colors,
-            &helicopter_model.body.normals,
-        );
-        let door_vao = graphics::create_vao(
-            &helicopter_model.door.vertices,
-            &helicopter_model.door.indices,
-            &helicopter_model.door.colors,
-            &helicopter_model.door.normals,
-        );
-        let main_vao = graphics::create_vao(
-            &helicopter_model.main_rotor.vertices,
-            &helicopter_model.main_rotor.indices,
-            &helicopter_model.main_rotor.colors,
-            &helicopter_model.main_rotor.normals,
-        );
-        let tail_vao = graphics::create_vao(
-            &helicopter_model.tail_rotor.vertices,
-            &helicopter_model.tail_rotor.indices,
-            &helicopter_model.tail_rotor.colors,
-            &helicopter_model.tail_rotor.normals,
-        );
-
-        let shader_program = shader::ShaderBuilder::new()
-            .attach_file("shaders/simple.vert")
-            .attach_file("shaders/simple.frag")
-            .link();
-
-        shader_program.activate();
-
-        
-        let alpha_location = {
-            let mut current_program: i32 = 0;
-            gl::GetIntegerv(gl::CURRENT_PROGRAM, &mut current_program);
-            gl::GetUniformLocation(current_program as u32, b"uAlpha\0".as_ptr() as *const _)
-        };
-
-        let mvp_matrix_location = {
-            let mut current_program: i32 = 0;
-            gl::GetIntegerv(gl::CURRENT_PROGRAM, &mut current_program);
-            gl::GetUniformLocation(
-                current_program as u32,
-                b"uMVPMatrix\0".as_ptr() as *const _,
-            )
-        };
-
-        let model_matrix_location = {
-            let mut current_program: i32 = 0;
-            gl::GetIntegerv(gl::CURRENT_PROGRAM, &mut current_program);
-            gl::GetUniformLocation(
-                current_program as u32,
-                b"uModelMatrix\0".as_ptr() as *const _,
-            )
-        };
-
-      
-        let mut terrain_node = SceneNode::from_vao(terrain_vao, terrain.index_count);
+        // Build the default variant up front, there's no point in going on without it
+        let mut shaders = ShaderLibrary::new(check_scene_shader);
+        if shaders.get(&SCENE_SHADER, Material::default().features).is_none() {
+            panic!("Failed to build the scene shader.");
+        }
 
-        // Create 5 helicopters that reference the shared VAOs
-        let mut helicopters: Vec<Node> = Vec::new();
-        for i in 0..5 {
-            
-            let mut body_node = SceneNode::from_vao(body_vao, helicopter_model.body.index_count);
-            let mut door_node = SceneNode::from_vao(door_vao, helicopter_model.door.index_count);
-            let mut main_node = SceneNode::from_vao(main_vao, helicopter_model.main_rotor.index_count);
-            let mut tail_node = SceneNode::from_vao(tail_vao, helicopter_model.tail_rotor.index_count);
+        let frame_uniforms = UniformBuffer::new(FRAME_UNIFORMS_BINDING);
 
+        // As large as the viewport, and resized along with the window
+        let mut viewport = [0; 4];
+        gl::GetIntegerv(gl::VIEWPORT, viewport.as_mut_ptr());
+        let scene_description = RenderTargetDescription::new()
+            .color(gl::RGBA16F)
+            .depth(gl::DEPTH_COMPONENT24)
+            .samples(SCENE_SAMPLES);
+        let scene_target = RenderTarget::new(&scene_description, viewport[2], viewport[3])
+            .unwrap_or_else(|e| panic!("{}", e));
+        let post_processing = PostProcessing::new(viewport[2], viewport[3])
+            .unwrap_or_else(|e| panic!("{}", e));
 
-            // Tail: given by assignment
-            node_mut(&mut tail_node).reference_point = glm::vec3(0.35, 2.3, 10.4);
-            
-            node_mut(&mut door_node).reference_point = glm::vec3(-1.0, 0.0, 0.0);
       
+        let mut terrain_node = SceneNode::new();
+        let terrain_chunk_nodes: Vec<Node> = terrain_chunks
+            .iter()
+            .zip(&terrain_lods)
+            .map(|(chunk, lods)| {
+                let mut node = SceneNode::from_vao(lods[0].vao.id(), lods[0].vao.index_count());
+                node.lod = Some(lod_chain(chunk, lods.iter().map(|lod| &lod.vao), &TERRAIN_LOD_DISTANCES));
+                node.material = Material::new(ShaderFeatures::LIT | ShaderFeatures::VERTEX_COLORS);
+                node
+            })
+            .collect();
+        for chunk in &terrain_chunk_nodes {
+            node_mut(&mut terrain_node).add_child(node_ref(chunk));
+        }
 
-            // Helicopter root (group node, not drawable)
-            let mut heli_root = SceneNode::new();
-
-            // Hook up parts under the helicopter root
-            node_mut(&mut heli_root).add_child(node_ref(&body_node));
-            node_mut(&mut heli_root).add_child(node_ref(&door_node));
-            node_mut(&mut heli_root).add_child(node_ref(&main_node));
-            node_mut(&mut heli_root).add_child(node_ref(&tail_node));
+        // Create the helicopters, all referencing the shared VAOs
+        let mut helicopters: Vec<ModelInstance> = Vec::new();
+        let mut helicopter_spotlights: Vec<Node> = Vec::new();
+        for i in 0..HELICOPTER_COUNT {
+            let mut heli = ModelInstance::new(&helicopter_model, |p, part| {
+                let mut node = SceneNode::from_vao(helicopter_lods[p][0].id(), helicopter_lods[p][0].index_count());
+                node.lod = Some(lod_chain(&part.mesh, helicopter_lods[p].iter(), &HELICOPTER_LOD_DISTANCES));
+                node.material = Material {
+                    specular: glm::vec3(0.6, 0.6, 0.6),
+                    shininess: 64.0,
+                    ..Material::new(ShaderFeatures::LIT | ShaderFeatures::VERTEX_COLORS)
+                };
+                node
+            });
 
             // Spread them out a bit initially to avoid overlap before animation kicks in
-            node_mut(&mut heli_root).position = glm::vec3(i as f32 * 50.0, 20.0, 0.0);
+            node_mut(&mut heli.root).position = glm::vec3(i as f32 * 50.0, 20.0, 0.0);
 
             // Add a test rotation to the first helicopter to verify transformations work
             if i == 0 {
-                node_mut(&mut heli_root).rotation.y = std::f32::consts::PI / 4.0; // 45 degrees
+                node_mut(&mut heli.root).rotation.y = std::f32::consts::PI / 4.0; // 45 degrees
             }
             
-            helicopters.push(heli_root);
+            let mut spotlight = SceneNode::new();
+            spotlight.light = Some(Light::spot(
+                glm::vec3(0.0, -1.0, 0.0),
+                glm::Vec3::from(SPOTLIGHT_COLOR),
+                SPOTLIGHT_INTENSITY,
+                SPOTLIGHT_RANGE,
+                SPOTLIGHT_INNER_ANGLE,
+                SPOTLIGHT_OUTER_ANGLE,
+            ));
+            node_mut(&mut heli.root).add_child(node_ref(&spotlight));
+
+            helicopters.push(heli);
+            helicopter_spotlights.push(spotlight);
         }
 
         // add helicopters under terrain
-        for heli_root in &helicopters {
-            node_mut(&mut terrain_node).add_child(node_ref(heli_root));
+        for heli in &helicopters {
+            node_mut(&mut terrain_node).add_child(node_ref(&heli.root));
         }
 
         // Create root and add terrain
         let mut root_node = SceneNode::new();
         node_mut(&mut root_node).add_child(node_ref(&terrain_node));
+        node_mut(&mut root_node).light = Some(Light::directional(
+            glm::Vec3::from(SUN_DIRECTION),
+            glm::Vec3::from(SUN_COLOR),
+            SUN_INTENSITY,
+        ));
 
         println!("Scene Graph ready. Terrain + {} helicopters.", helicopters.len());
 
+        let tessellated_terrain = TessellatedTerrain::new(&terrain_heights)
+            .unwrap_or_else(|e| panic!("{}", e));
+        let shadow_map = ShadowMap::new(check_scene_shader)
+            .unwrap_or_else(|e| panic!("{}", e));
+
+        // Keep the VAOs alive for as long as the scene graph refers to them
+        let terrain_vao_ids = terrain_lods.iter().flatten().map(|lod| lod.vao.id()).collect();
+        let vertex_arrays: Vec<VertexArray> = helicopter_lods.into_iter().flatten().collect();
+
         Renderer {
             root_node,
             helicopters,
-            shader_program,
-            alpha_location,
-            mvp_matrix_location,
-            model_matrix_location
+            helicopter_spotlights,
+            vertex_arrays,
+            debug_lines: DebugLines::new(),
+            show_terrain_probes: false,
+            terrain: terrain_heights,
+            tessellated_terrain,
+            use_tessellated_terrain: false,
+            shadow_map,
+            shadows_enabled: true,
+            terrain_vao_ids,
+            terrain_meshes: terrain_lods,
+            show_terrain_lods: false,
+            shaders,
+            frame_uniforms,
+            max_lights: MAX_LIGHTS,
+            lights: vec![],
+            scene_target,
+            post_processing,
+            batches: vec![],
+            instance_stream: StreamBuffer::new(
+                gl::ARRAY_BUFFER,
+                (INITIAL_INSTANCES_PER_FRAME * std::mem::size_of::<Instance>()) as isize,
+            ),
+            instance_layout: VertexLayout::new().per_instance(
+                [
+                    VertexLayout::mat4_attributes("aModelMatrix", INSTANCE_MATRIX_LOCATION),
+                    VertexLayout::mat3_attributes("aNormalMatrix", INSTANCE_NORMAL_MATRIX_LOCATION),
+                ].concat()
+            ),
         }
     }
 
-    pub unsafe fn render(&self, camera: &Camera) {
-        // Clear
-        gl::ClearColor(0.035, 0.046, 0.078, 1.0);
+    // `time` is the number of seconds since the start, made available to the shaders
+    pub unsafe fn render(&mut self, camera: &Camera, time: f32) {
+        // Normally the window, but whatever is bound when we start
+        let output = PreviousTarget::save();
+        self.scene_target.bind();
+
+        // Clear, in linear colors as the gamma correction comes later
+        let [r, g, b] = SKY_COLOR;
+        gl::ClearColor(r.powf(2.2), g.powf(2.2), b.powf(2.2), 1.0);
         gl::Clear(gl::COLOR_BUFFER_BIT | gl::DEPTH_BUFFER_BIT);
 
-        // Shader and global state
-        self.shader_program.activate();
+        // Global state, antialiased by the samples of the scene target
         gl::Enable(gl::CULL_FACE);
+        gl::Enable(gl::MULTISAMPLE);
         gl::DepthMask(gl::TRUE);
-        gl::Uniform1f(self.alpha_location, 0.9);
 
-        // VP and identity
-        let vp = camera.get_view_projection_matrix();
+        let camera_position = camera.position();
         let identity: glm::Mat4 = glm::identity();
 
-        // Traverse and draw
-        Self::draw_scene(
+        // Traverse, sorting what to draw by mesh and material, and finding the lights
+        for batch in &mut self.batches {
+            batch.instances.clear();
+        }
+        self.lights.clear();
+        Self::collect_draws(
             node_ref(&self.root_node),
-            &vp,
+            &camera_position,
             &identity,
-            self.mvp_matrix_location,
-            self.model_matrix_location,
+            &mut self.batches,
+            &mut self.lights,
         );
+
+        // Camera and lights, uploaded once for every program to read
+        let mut frame = FrameUniforms::new(
+            &camera.get_view_matrix(),
+            &camera.get_perspective_matrix(),
+            &camera_position,
+            time,
+        );
+        frame.set_ambient_light(&glm::Vec3::from(AMBIENT_LIGHT));
+        light::sort_by_relevance(&mut self.lights, &camera_position);
+        for light in self.lights.iter().take(self.max_lights) {
+            frame.add_light(light);
+        }
+
+        // The first directional light, normally the sun, casts shadows
+        let shadow_light = self.lights.iter()
+            .take(self.max_lights)
+            .position(|light| light.position[3] == LightUniform::DIRECTIONAL)
+            .filter(|_| self.shadows_enabled);
+        if let Some(i) = shadow_light {
+            let direction = &self.lights[i].direction;
+            let corners = camera.frustum_corners(camera.near, self.shadow_map.distance.min(camera.far));
+            let view_projection = self.shadow_map.fit_to_frustum(&glm::vec3(direction[0], direction[1], direction[2]), &corners);
+            frame.set_shadow(i, &view_projection, self.shadow_map.normal_offset(&view_projection));
+        }
+        self.frame_uniforms.update(&frame);
+        self.frame_uniforms.bind();
+        // Keep batches with the same shader variant together, to switch programs less
+        self.batches.sort_by_key(|b| b.material.features);
+
+        // The terrain mesh is replaced when tessellating, in the shadow map as well
+        let skip_terrain = self.use_tessellated_terrain;
+        let terrain_vao_ids = &self.terrain_vao_ids;
+        let skipped = |batch: &DrawBatch| {
+            batch.instances.is_empty() || (skip_terrain && terrain_vao_ids.contains(&batch.vao_id))
+        };
+
+        // Uploaded once, and drawn from by both passes
+        Self::reserve_instances(&mut self.instance_stream, self.batches.iter().filter(|b| !skipped(b)));
+        for batch in self.batches.iter_mut().filter(|b| !skipped(b)) {
+            upload_instances(&mut self.instance_stream, batch);
+        }
+
+        if shadow_light.is_some() {
+            let mut viewport = [0i32; 4];
+            gl::GetIntegerv(gl::VIEWPORT, viewport.as_mut_ptr());
+            let (batches, stream, layout) = (&self.batches, &self.instance_stream, &self.instance_layout);
+            let tessellated_terrain = Some(&self.tessellated_terrain).filter(|_| self.use_tessellated_terrain);
+            self.shadow_map.render(|_| {
+                stream.buffer().bind();
+                for batch in batches.iter().filter(|b| !skipped(b)) {
+                    gl::BindVertexArray(batch.vao_id);
+                    draw_instances(layout, batch);
+                }
+                // Last, as it switches programs
+                if let Some(terrain) = tessellated_terrain {
+                    terrain.draw_depth(&glm::vec2(viewport[2] as f32, viewport[3] as f32));
+                }
+            });
+        }
+        self.shadow_map.bind();
+
+        // Draw every mesh once, with all its model matrices in the instance buffer
+        self.instance_stream.buffer().bind();
+        let mut active_material: Option<Material> = None;
+        for batch in &self.batches {
+            if skipped(batch) {
+                continue;
+            }
+            if active_material != Some(batch.material) {
+                let shader = match self.shaders.get(&SCENE_SHADER, batch.material.features) {
+                    Some(shader) => shader,
+                    None => continue, // Broken variant, already reported
+                };
+                if active_material.map_or(true, |m| m.features != batch.material.features) {
+                    shader.activate();
+                    shader.set_f32("uAlpha", 0.9);
+                }
+                batch.material.apply(shader);
+                active_material = Some(batch.material);
+            }
+            gl::BindVertexArray(batch.vao_id);
+            draw_instances(&self.instance_layout, batch);
+        }
+        gl::BindBuffer(gl::ARRAY_BUFFER, 0);
+        gl::BindVertexArray(0);
+        self.instance_stream.finish_frame();
+
+        if self.use_tessellated_terrain {
+            self.tessellated_terrain.draw(0.9);
+        }
+
+        self.debug_lines.draw();
+
+        self.post_processing.apply(&self.scene_target, &output);
+        output.restore();
+    }
+
+    // To be called when the window changes size. If the render targets can't be made that large,
+    // the error is reported and they stay the size they were, stretched over the window.
+    pub unsafe fn resize(&mut self, width: u32, height: u32) {
+        let (old_width, old_height) = (self.scene_target.width(), self.scene_target.height());
+        let result = self.scene_target.resize(width as i32, height as i32)
+            .and_then(|_| self.post_processing.resize(width as i32, height as i32));
+        if let Err(e) = result {
+            println!(
+                "ERROR: Failed to resize the render targets to {}x{}: {}\nKeeping them at {}x{}.",
+                width, height, e, old_width, old_height,
+            );
+            // Those resized before the failure are put back, so they all agree
+            let restored = self.scene_target.resize(old_width, old_height)
+                .and_then(|_| self.post_processing.resize(old_width, old_height));
+            if let Err(e) = restored {
+                println!("ERROR: Failed to restore the render targets: {}", e);
+            }
+        }
     }
 
 
+    /// Rebuilds the shaders whose files were edited. Broken edits are reported,
+    /// and the previous programs are kept until they are fixed.
+    pub unsafe fn reload_shaders(&mut self) {
+        let mut results: Vec<(Result<(), ShaderError>, String)> = self.shaders.poll()
+            .into_iter()
+            .map(|(key, result)| (result, format!("{} ({:?})", key.sources.join(", "), key.features)))
+            .collect();
+        results.extend(self.debug_lines.reload_shader());
+        for terrain_shader in self.tessellated_terrain.shaders_mut() {
+            results.extend(terrain_shader.poll().map(|r| (r, terrain_shader.paths().join(", "))));
+        }
+        let shadow_shader = self.shadow_map.shader_mut();
+        results.extend(shadow_shader.poll().map(|r| (r, shadow_shader.paths().join(", "))));
+        results.extend(self.post_processing.reload_shaders());
+        for (result, files) in results {
+            match result {
+                Ok(()) => println!("Reloaded {}", files),
+                Err(e) => println!("ERROR: {}\nKeeping the previous program.", e),
+            }
+        }
+    }
+
+    pub fn show_terrain_lods(&self) -> bool {
+        self.show_terrain_lods
+    }
+
+    // Tints every terrain chunk by the level of detail it is drawn at, or takes the tint off again
+    pub unsafe fn set_show_terrain_lods(&mut self, show: bool) {
+        self.show_terrain_lods = show;
+        for lods in &mut self.terrain_meshes {
+            for (level, lod) in lods.iter_mut().enumerate() {
+                let color = if show { TERRAIN_LOD_COLORS[level % TERRAIN_LOD_COLORS.len()] } else { TERRAIN_COLOR };
+                let vertex_count = lod.mesh.vertices.len() / 3;
+                for c in lod.colors_mut(0..vertex_count).chunks_exact_mut(4) {
+                    c.copy_from_slice(&color);
+                }
+                lod.sync();
+            }
+        }
+    }
+
     pub fn update_animations(&mut self, elapsed: f32) {
         let main_rotor_speed = 5_000.0;
         let tail_rotor_speed = 5_000.0;
 
-        for (i, heli_node) in self.helicopters.iter_mut().enumerate() {
-            let heli = node_mut(heli_node);
+        for (i, (heli, spotlight)) in self.helicopters.iter_mut().zip(&mut self.helicopter_spotlights).enumerate() {
 
             // Offset each helicopter along the same path to avoid collisions
             let offset = i as f32 * 0.75;
             let heading = toolbox::simple_heading_animation(elapsed + offset);
 
-           // Update helicopter position to move forward
-            heli.position = glm::vec3(heading.x, 20.0, heading.z);
-            heli.rotation = glm::vec3(heading.pitch, heading.yaw, heading.roll);
+            // Update helicopter position to move forward, staying clear of the terrain below
+            let ground = self.terrain.sample(heading.x, heading.z);
+            let height = match ground {
+                Some(ground) => (ground.height + HELICOPTER_GROUND_CLEARANCE).max(HELICOPTER_CRUISE_HEIGHT),
+                None => HELICOPTER_CRUISE_HEIGHT,
+            };
+            if let (Some(ground), true) = (ground, self.show_terrain_probes) {
+                let below = glm::vec3(heading.x, ground.height, heading.z);
+                self.debug_lines.line(&glm::vec3(heading.x, height, heading.z), &below, [1.0, 1.0, 0.0, 1.0]);
+                self.debug_lines.line(&below, &(below + ground.normal * 5.0), [0.0, 1.0, 1.0, 1.0]);
+            }
+            let root = node_mut(&mut heli.root);
+            root.position = glm::vec3(heading.x, height, heading.z);
+            root.rotation = glm::vec3(heading.pitch, heading.yaw, heading.roll);
 
-            // Children order: 0=body, 1=door, 2=main rotor, 3=tail rotor
-            if heli.n_children() >= 4 {
-                let main_rotor = heli.get_child(2);
-                main_rotor.rotation = glm::vec3(0.0, 1.0, 0.0) * main_rotor_speed * elapsed;
+            // Tilted away from straight down, in a direction that keeps turning
+            let sweep = SPOTLIGHT_SWEEP_SPEED * elapsed + offset;
+            let spotlight = node_mut(spotlight);
+            spotlight.rotation = glm::vec3(sweep.cos(), 0.0, sweep.sin()) * SPOTLIGHT_SWEEP_ANGLE;
 
-                let tail_rotor = heli.get_child(3);
+            if let Some(main_rotor) = heli.part_mut("main_rotor") {
+                main_rotor.rotation = glm::vec3(0.0, 1.0, 0.0) * main_rotor_speed * elapsed;
+            }
+            if let Some(tail_rotor) = heli.part_mut("tail_rotor") {
                 tail_rotor.rotation = glm::vec3(1.0, 0.0, 0.0) * tail_rotor_speed * elapsed;
             }
         }
 
     }
 
-    /// Recursive scene traversal + draw
-    unsafe fn draw_scene(
+    // Grows the instance stream if it can't take the instances of these batches in one frame.
+    // Only call it before anything is pushed this frame.
+    unsafe fn reserve_instances<'a>(stream: &mut StreamBuffer, batches: impl Iterator<Item = &'a DrawBatch>) {
+        let size = std::mem::size_of::<Instance>();
+        // Every batch starts 16 byte aligned, see StreamBuffer::push
+        let bytes: usize = batches.map(|b| (b.instances.len() * size + 15) & !15).sum();
+        if bytes <= stream.capacity() as usize {
+            return;
+        }
+        let capacity = bytes.next_power_of_two();
+        println!("Making room for {} instances per frame", capacity / size);
+        *stream = StreamBuffer::new(gl::ARRAY_BUFFER, capacity as isize);
+    }
+
+    /// Recursive scene traversal, adding every drawable node to the batch for its mesh,
+    /// and every light to the lights, placed in the world
+    fn collect_draws(
         node: &SceneNode,
-        view_projection_matrix: &glm::Mat4,
+        camera_position: &glm::Vec3,
         parent: &glm::Mat4,
-        mvp_matrix_location: i32,
-        model_matrix_location: i32,
+        batches: &mut Vec<DrawBatch>,
+        lights: &mut Vec<LightUniform>,
     ) {
 
         let x = glm::rotation(node.rotation.x, &glm::vec3(1.0, 0.0, 0.0));
@@ -238,30 +636,34 @@ impl Renderer {
 
         let world = parent * local;
 
-        // Draw if this node is drawable
-        if node.vao_id != 0 && node.index_count > 0 {
-            gl::BindVertexArray(node.vao_id);
-
-            let mvp = view_projection_matrix * world;
-            gl::UniformMatrix4fv(mvp_matrix_location, 1, gl::FALSE, mvp.as_ptr());
-            gl::UniformMatrix4fv(model_matrix_location, 1, gl::FALSE, world.as_ptr());
+        // Draw if this node is drawable, using the level of detail fitting the distance
+        let (vao_id, index_count) = node.select_lod(&world, camera_position);
+        if vao_id != 0 && index_count > 0 {
+            let material = node.material;
+            match batches.iter_mut().find(|b| b.vao_id == vao_id && b.index_count == index_count && b.material == material) {
+                Some(batch) => batch.instances.push(Instance::new(world)),
+                None => batches.push(DrawBatch {
+                    vao_id,
+                    index_count,
+                    material,
+                    instances: vec![Instance::new(world)],
+                    instance_offset: 0,
+                }),
+            }
+        }
 
-            gl::DrawElements(
-                gl::TRIANGLES,
-                node.index_count,
-                gl::UNSIGNED_INT,
-                std::ptr::null(),
-            );
+        if let Some(light) = &node.light {
+            lights.push(light.in_world(&world));
         }
 
         // Recurse
         for &child in &node.children {
-            Self::draw_scene(
+            Self::collect_draws(
                 unsafe { &*child },
-                view_projection_matrix,
+                camera_position,
                 &world,
-                mvp_matrix_location,
-                model_matrix_location,
+                batches,
+                lights,
             );
         }
     }
@@ -51,3 +51,9 @@ impl Scene {
         }
     }
 }
+
+impl Default for Scene {
+    fn default() -> Self {
+        Scene::new()
+    }
+}
@@ -1,8 +1,13 @@
 extern crate nalgebra_glm as glm;
 
+use std::cell::Cell;
 use std::mem::ManuallyDrop;
 use std::pin::Pin;
 
+use crate::bvh::Aabb;
+use crate::light::Light;
+use crate::material::Material;
+
 // Used to create an unholy abomination upon which you should not cast your gaze. This ended up
 // being a necessity due to wanting to keep the code written by students as "straight forward" as
 // possible. It is very very double plus ungood Rust, and intentionally leaks memory like a sieve.
@@ -16,6 +21,56 @@ use std::pin::Pin;
 // having what I arbitrarily decided to be the required level of "simplicity of use".
 pub type Node = ManuallyDrop<Pin<Box<SceneNode>>>;
 
+// How far past a switching distance the camera has to move before the level changes,
+// as a fraction of that distance. Keeps the mesh from popping back and forth at the border.
+const LOD_HYSTERESIS: f32 = 0.1;
+
+pub struct LodLevel {
+    pub vao_id      : u32,
+    pub index_count : i32,
+    pub distance    : f32,             // Used from this distance to the camera and outwards
+}
+
+pub struct LodChain {
+    pub levels : Vec<LodLevel>,        // From the most to the least detailed
+    pub bounds : Aabb,                 // Around the mesh, in model space
+    current    : Cell<usize>,
+}
+
+impl LodChain {
+    pub fn new(bounds: Aabb) -> Self {
+        LodChain {
+            levels: vec![],
+            bounds,
+            current: Cell::new(0),
+        }
+    }
+
+    pub fn push(&mut self, vao_id: u32, index_count: i32, distance: f32) {
+        self.levels.push(LodLevel { vao_id, index_count, distance });
+    }
+
+    pub fn current(&self) -> usize {
+        self.current.get()
+    }
+
+    // Picks the level for the given distance from the camera to the mesh bounds,
+    // only moving away from the current level once the camera is clearly past the border
+    pub fn select(&self, distance: f32) -> &LodLevel {
+        let mut level = self.current.get().min(self.levels.len() - 1);
+        while level + 1 < self.levels.len()
+            && distance > self.levels[level + 1].distance * (1.0 + LOD_HYSTERESIS)
+        {
+            level += 1;
+        }
+        while level > 0 && distance < self.levels[level].distance * (1.0 - LOD_HYSTERESIS) {
+            level -= 1;
+        }
+        self.current.set(level);
+        &self.levels[level]
+    }
+}
+
 pub struct SceneNode {
     pub position        : glm::Vec3,   // Where I should be in relation to my parent
     pub rotation        : glm::Vec3,   // How I should be rotated, around the X, the Y and the Z axes
@@ -24,6 +79,9 @@ pub struct SceneNode {
 
     pub vao_id      : u32,             // What I should draw
     pub index_count : i32,             // How much of it there is to draw
+    pub lod         : Option<LodChain>, // Simpler versions of the above, chosen by distance
+    pub material    : Material,        // How it should look
+    pub light       : Option<Light>,   // What I shine on my surroundings
 
     pub children: Vec<*mut SceneNode>, // Those I command
 }
@@ -38,6 +96,9 @@ impl SceneNode {
             reference_point : glm::zero(),
             vao_id          : 0,
             index_count     : -1,
+            lod             : None,
+            material        : Material::default(),
+            light           : None,
             children        : vec![],
         })))
     }
@@ -50,6 +111,9 @@ impl SceneNode {
             reference_point : glm::zero(),
             vao_id,
             index_count,
+            lod: None,
+            material: Material::default(),
+            light: None,
             children: vec![],
         })))
     }
@@ -70,6 +134,19 @@ impl SceneNode {
         self.children.len()
     }
 
+    // What to draw when seen from the given camera position, given the node's world transform
+    pub fn select_lod(&self, world: &glm::Mat4, camera_position: &glm::Vec3) -> (u32, i32) {
+        match &self.lod {
+            Some(lod) if !lod.levels.is_empty() => {
+                // Zero from inside the box, so large meshes need cutting up to ever get simpler
+                let distance = lod.bounds.transformed(world).distance_squared_to(camera_position).sqrt();
+                let level = lod.select(distance);
+                (level.vao_id, level.index_count)
+            }
+            _ => (self.vao_id, self.index_count),
+        }
+    }
+
     #[allow(dead_code)]
     pub fn print(&self) {
         println!(
@@ -116,3 +193,48 @@ impl IndexMut<usize> for SceneNode {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chain() -> LodChain {
+        let mut chain = LodChain::new(Aabb::new(glm::vec3(-50.0, -1.0, -50.0), glm::vec3(50.0, 1.0, 50.0)));
+        chain.push(1, 300, 0.0);
+        chain.push(2, 30, 100.0);
+        chain.push(3, 3, 250.0);
+        chain
+    }
+
+    #[test]
+    fn select_switches_past_the_distance_and_back_before_it() {
+        let chain = chain();
+        assert_eq!(chain.select(50.0).vao_id, 1);
+        assert_eq!(chain.select(105.0).vao_id, 1);
+        assert_eq!(chain.select(115.0).vao_id, 2);
+        assert_eq!(chain.select(95.0).vao_id, 2);
+        assert_eq!(chain.select(85.0).vao_id, 1);
+        assert_eq!(chain.select(1000.0).vao_id, 3);
+    }
+
+    #[test]
+    fn select_lod_measures_to_the_box() {
+        let mut node = SceneNode::new();
+        node.lod = Some(chain());
+        let world = glm::translation(&glm::vec3(200.0, 0.0, 0.0));
+        let above = glm::vec3(200.0, 20.0, 0.0);
+        assert_eq!(node.select_lod(&world, &above), (1, 300));
+        // 120 past the side of the box, much closer than to its center
+        let beside = glm::vec3(370.0, 0.0, 0.0);
+        assert_eq!(node.select_lod(&world, &beside), (2, 30));
+        // Scaled up, the same point is inside
+        let world = world * glm::scaling(&glm::vec3(4.0, 1.0, 4.0));
+        assert_eq!(node.select_lod(&world, &beside), (1, 300));
+    }
+
+    #[test]
+    fn nodes_without_levels_draw_their_vao() {
+        let node = SceneNode::from_vao(7, 12);
+        assert_eq!(node.select_lod(&glm::identity(), &glm::vec3(1e6, 0.0, 0.0)), (7, 12));
+    }
+}
@@ -1,18 +1,41 @@
-use gl;
+extern crate nalgebra_glm as glm;
+
 use std::{
     ptr,
     str,
-    ffi::CString,
+    cell::RefCell,
+    collections::{HashMap, HashSet},
+    ffi::{CStr, CString},
     path::Path,
 };
 
+use crate::gl_objects::{Program, ShaderObject};
+use crate::shader_preprocessor;
+
+// An active uniform or attribute of a linked program
+#[derive(Clone, Copy, Debug)]
+pub struct ShaderInput {
+    pub location : i32,
+    pub ty       : gl::types::GLenum, // e.g. gl::FLOAT_MAT4
+    pub size     : i32,               // Number of array elements, 1 if not an array
+}
+
+// The program is deleted when the shader is dropped.
+// Its uniforms and attributes are looked up once after linking.
 pub struct Shader {
-    pub program_id: u32,
+    pub program : Program,
+    uniforms    : HashMap<String, ShaderInput>,
+    attributes  : HashMap<String, ShaderInput>,
+    warned      : RefCell<HashSet<String>>, // Names we already complained about
+    sources     : Vec<String>,               // Every file read to build it, includes too
 }
 
 pub struct ShaderBuilder {
-    program_id: u32,
-    shaders: Vec::<u32>,
+    program: Program,
+    shaders: Vec<ShaderObject>, // Deleted along with the builder, whether linking got that far or not
+    files: Vec<String>, // Where each shader came from, for error messages
+    sources: Vec<String>, // Every file read, includes too
+    defines: Vec<(String, String)>, // Injected into every file attached after they are added
 }
 
 #[allow(dead_code)]
@@ -22,28 +45,218 @@ pub enum ShaderType {
     TessellationControl,
     TessellationEvaluation,
     Geometry,
+    Compute,
 }
 
 impl Shader {
-    // Make sure the shader is active before calling this
-    pub unsafe fn get_uniform_location(&self, name: &str) -> i32 {
-        let name_cstr = CString::new(name).expect("CString::new failed");
-        gl::GetUniformLocation(self.program_id, name_cstr.as_ptr())
+    unsafe fn new(program: Program, sources: Vec<String>) -> Shader {
+        let mut uniforms = HashMap::new();
+        for (name, mut input) in active_inputs(program.id(), gl::ACTIVE_UNIFORMS) {
+            let c_name = CString::new(name.as_str()).unwrap();
+            input.location = gl::GetUniformLocation(program.id(), c_name.as_ptr());
+            if input.location < 0 {
+                continue; // Lives in a uniform block
+            }
+            // Arrays are reported as "name[0]", but should be found by "name" as well
+            if let Some(base) = name.strip_suffix("[0]") {
+                uniforms.insert(base.to_string(), input);
+            }
+            uniforms.insert(name, input);
+        }
+
+        let mut attributes = HashMap::new();
+        for (name, mut input) in active_inputs(program.id(), gl::ACTIVE_ATTRIBUTES) {
+            if name.starts_with("gl_") {
+                continue;
+            }
+            let c_name = CString::new(name.as_str()).unwrap();
+            input.location = gl::GetAttribLocation(program.id(), c_name.as_ptr());
+            attributes.insert(name, input);
+        }
+
+        Shader {
+            program,
+            uniforms,
+            attributes,
+            warned: RefCell::new(HashSet::new()),
+            sources,
+        }
+    }
+
+    pub fn program_id(&self) -> u32 {
+        self.program.id()
+    }
+
+    pub fn source_files(&self) -> &[String] {
+        &self.sources
+    }
+
+    pub fn uniforms(&self) -> &HashMap<String, ShaderInput> {
+        &self.uniforms
+    }
+
+    pub fn attributes(&self) -> &HashMap<String, ShaderInput> {
+        &self.attributes
+    }
+
+    pub fn attribute_location(&self, name: &str) -> Option<i32> {
+        self.attributes.get(name).map(|a| a.location)
+    }
+
+    // -1 if the program has no such uniform, which GL quietly ignores when setting it
+    pub fn get_uniform_location(&self, name: &str) -> i32 {
+        self.uniform(name, None).map_or(-1, |u| u.location)
     }
 
     pub unsafe fn activate(&self) {
-        gl::UseProgram(self.program_id);
+        gl::UseProgram(self.program_id());
+    }
+
+    // Looks up a uniform, warning the first time it is missing or of the wrong type.
+    // Unused uniforms are optimized away by the driver, so this is not an error.
+    fn uniform(&self, name: &str, expected: Option<&[gl::types::GLenum]>) -> Option<&ShaderInput> {
+        let problem = match self.uniforms.get(name) {
+            None => "is not an active uniform of".to_string(),
+            Some(u) if expected.is_some_and(|types| !types.contains(&u.ty)) => format!(
+                "has type {:#x}, not {:#x?}, in", u.ty, expected.unwrap()
+            ),
+            Some(u) => return Some(u),
+        };
+        if self.warned.borrow_mut().insert(name.to_string()) {
+            println!("WARNING: {} {} shader program {}", name, problem, self.program_id());
+        }
+        None
+    }
+
+    // Typed setters, which don't need the shader to be active
+
+    pub unsafe fn set_f32(&self, name: &str, value: f32) {
+        if let Some(u) = self.uniform(name, Some(&[gl::FLOAT])) {
+            gl::ProgramUniform1f(self.program_id(), u.location, value);
+        }
+    }
+
+    pub unsafe fn set_i32(&self, name: &str, value: i32) {
+        if let Some(u) = self.uniform(name, Some(&[gl::INT, gl::BOOL])) {
+            gl::ProgramUniform1i(self.program_id(), u.location, value);
+        }
+    }
+
+    pub unsafe fn set_bool(&self, name: &str, value: bool) {
+        if let Some(u) = self.uniform(name, Some(&[gl::BOOL, gl::INT])) {
+            gl::ProgramUniform1i(self.program_id(), u.location, value as i32);
+        }
+    }
+
+    pub unsafe fn set_vec2(&self, name: &str, value: &glm::Vec2) {
+        if let Some(u) = self.uniform(name, Some(&[gl::FLOAT_VEC2])) {
+            gl::ProgramUniform2fv(self.program_id(), u.location, 1, value.as_ptr());
+        }
+    }
+
+    pub unsafe fn set_vec3(&self, name: &str, value: &glm::Vec3) {
+        if let Some(u) = self.uniform(name, Some(&[gl::FLOAT_VEC3])) {
+            gl::ProgramUniform3fv(self.program_id(), u.location, 1, value.as_ptr());
+        }
+    }
+
+    pub unsafe fn set_vec4(&self, name: &str, value: &glm::Vec4) {
+        if let Some(u) = self.uniform(name, Some(&[gl::FLOAT_VEC4])) {
+            gl::ProgramUniform4fv(self.program_id(), u.location, 1, value.as_ptr());
+        }
+    }
+
+    pub unsafe fn set_mat3(&self, name: &str, value: &glm::Mat3) {
+        if let Some(u) = self.uniform(name, Some(&[gl::FLOAT_MAT3])) {
+            gl::ProgramUniformMatrix3fv(self.program_id(), u.location, 1, gl::FALSE, value.as_ptr());
+        }
+    }
+
+    pub unsafe fn set_mat4(&self, name: &str, value: &glm::Mat4) {
+        if let Some(u) = self.uniform(name, Some(&[gl::FLOAT_MAT4])) {
+            gl::ProgramUniformMatrix4fv(self.program_id(), u.location, 1, gl::FALSE, value.as_ptr());
+        }
+    }
+
+    // Binds the texture to the given texture unit, and points the sampler uniform at it
+    pub unsafe fn set_texture(&self, name: &str, unit: u32, target: gl::types::GLenum, texture_id: u32) {
+        gl::ActiveTexture(gl::TEXTURE0 + unit);
+        gl::BindTexture(target, texture_id);
+        gl::ActiveTexture(gl::TEXTURE0);
+        let samplers = [
+            gl::SAMPLER_2D, gl::SAMPLER_2D_SHADOW, gl::SAMPLER_2D_MULTISAMPLE, gl::SAMPLER_CUBE,
+            gl::SAMPLER_3D, gl::SAMPLER_2D_ARRAY, gl::INT_SAMPLER_2D, gl::UNSIGNED_INT_SAMPLER_2D,
+        ];
+        if let Some(u) = self.uniform(name, Some(&samplers)) {
+            gl::ProgramUniform1i(self.program_id(), u.location, unit as i32);
+        }
     }
 }
 
-impl Into<gl::types::GLenum> for ShaderType {
-    fn into(self) -> gl::types::GLenum {
-        match self {
+// Compute
+
+impl Shader {
+    // The local_size declared by a compute shader
+    pub unsafe fn work_group_size(&self) -> [u32; 3] {
+        let mut size = [0i32; 3];
+        gl::GetProgramiv(self.program_id(), gl::COMPUTE_WORK_GROUP_SIZE, size.as_mut_ptr());
+        [size[0] as u32, size[1] as u32, size[2] as u32]
+    }
+
+    // Runs this compute shader for the given number of work groups
+    pub unsafe fn dispatch(&self, groups: [u32; 3]) {
+        self.activate();
+        gl::DispatchCompute(groups[0], groups[1], groups[2]);
+    }
+
+    // Runs enough work groups to cover at least this many invocations along each axis.
+    // The shader has to skip the invocations past the end itself.
+    pub unsafe fn dispatch_invocations(&self, invocations: [u32; 3]) {
+        let size = self.work_group_size();
+        self.dispatch([
+            invocations[0].div_ceil(size[0].max(1)),
+            invocations[1].div_ceil(size[1].max(1)),
+            invocations[2].div_ceil(size[2].max(1)),
+        ]);
+    }
+}
+
+// Makes writes done by earlier shader invocations visible to the kinds of reads given in
+// `barriers`, e.g. gl::SHADER_STORAGE_BARRIER_BIT before another dispatch reads the same buffer,
+// or gl::VERTEX_ATTRIB_ARRAY_BARRIER_BIT before drawing from it
+pub unsafe fn memory_barrier(barriers: gl::types::GLbitfield) {
+    gl::MemoryBarrier(barriers);
+}
+
+// Names and types of the active uniforms or attributes of a program
+unsafe fn active_inputs(program_id: u32, kind: gl::types::GLenum) -> Vec<(String, ShaderInput)> {
+    let mut count = 0;
+    gl::GetProgramiv(program_id, kind, &mut count);
+    let mut name_buffer = [0u8; 256];
+    (0..count as u32).map(|i| {
+        let (mut length, mut size, mut ty) = (0, 0, 0);
+        let name_ptr = name_buffer.as_mut_ptr() as *mut gl::types::GLchar;
+        if kind == gl::ACTIVE_UNIFORMS {
+            gl::GetActiveUniform(program_id, i, name_buffer.len() as i32, &mut length, &mut size, &mut ty, name_ptr);
+        } else {
+            gl::GetActiveAttrib(program_id, i, name_buffer.len() as i32, &mut length, &mut size, &mut ty, name_ptr);
+        }
+        let name = CStr::from_bytes_until_nul(&name_buffer)
+            .map(|n| n.to_string_lossy().to_string())
+            .unwrap_or_default();
+        (name, ShaderInput { location: -1, ty, size })
+    }).collect()
+}
+
+impl From<ShaderType> for gl::types::GLenum {
+    fn from(shader_type: ShaderType) -> gl::types::GLenum {
+        match shader_type {
             ShaderType::Vertex                  => { gl::VERTEX_SHADER          },
             ShaderType::Fragment                => { gl::FRAGMENT_SHADER        },
             ShaderType::TessellationControl     => { gl::TESS_CONTROL_SHADER    },
             ShaderType::TessellationEvaluation  => { gl::TESS_EVALUATION_SHADER } ,
             ShaderType::Geometry                => { gl::GEOMETRY_SHADER        },
+            ShaderType::Compute                 => { gl::COMPUTE_SHADER         },
         }
     }
 }
@@ -56,99 +269,289 @@ impl ShaderType {
             "tcs"  => { Ok(ShaderType::TessellationControl) },
             "tes"  => { Ok(ShaderType::TessellationEvaluation) },
             "geom" => { Ok(ShaderType::Geometry) },
+            "comp" => { Ok(ShaderType::Compute) },
             e => { Err(e.to_string()) },
         }
     }
 }
 
+// Errors
+
+// One message from the driver's info log, pointing into a source file
+#[derive(Clone, Debug)]
+pub struct ShaderDiagnostic {
+    pub file    : String,
+    pub line    : u32,
+    pub message : String,
+}
+
+#[derive(Debug)]
+pub enum ShaderError {
+    Io { path: String, error: std::io::Error },
+    UnknownExtension { path: String },
+    Compile { files: Vec<String>, log: String, diagnostics: Vec<ShaderDiagnostic> },
+    Link { files: Vec<String>, log: String },
+    Interface { files: Vec<String>, message: String }, // Links, but doesn't fit the data it is fed
+    Include { file: String, line: u32, message: String },
+    NulByte { file: String, line: u32 }, // Can't be handed to OpenGL, which reads up to the first one
+}
+
+impl std::fmt::Display for ShaderError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            ShaderError::Io { path, error } => write!(f, "Failed to read shader source {}: {}", path, error),
+            ShaderError::UnknownExtension { path } => write!(
+                f, "Can't tell the shader type of {}, expected .vert, .frag, .tcs, .tes, .geom or .comp", path
+            ),
+            ShaderError::Compile { files, log, diagnostics } => {
+                writeln!(f, "Failed to compile {}:", files.first().map_or("shader", |s| s.as_str()))?;
+                if diagnostics.is_empty() {
+                    return write!(f, "{}", log.trim_end());
+                }
+                for d in diagnostics {
+                    writeln!(f, "{}:{}: {}", d.file, d.line, d.message)?;
+                }
+                Ok(())
+            },
+            ShaderError::Link { files, log } => write!(
+                f, "Failed to link {}:\n{}", files.join(", "), log.trim_end()
+            ),
+            ShaderError::Interface { files, message } => write!(
+                f, "{} can't be used: {}", files.join(", "), message
+            ),
+            ShaderError::Include { file, line, message } => write!(f, "{}:{}: {}", file, line, message),
+            ShaderError::NulByte { file, line } => write!(f, "{}:{}: contains a NUL byte, which can't be passed to OpenGL", file, line),
+        }
+    }
+}
+
+impl std::error::Error for ShaderError {}
+
+// Finds the file and line of each message in an info log. Drivers disagree on the format:
+//   Mesa:   0:12(5): error: ...
+//   NVIDIA: 0(12) : error C0000: ...
+//   AMD:    ERROR: 0:12: ...
+// where the first number is the source string, or the file number given by a #line directive.
+// `files` names the file for each of those numbers.
+fn parse_info_log(log: &str, files: &[String]) -> Vec<ShaderDiagnostic> {
+    fn number(s: &str) -> Option<(u32, &str)> {
+        let end = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+        Some((s[..end].parse().ok()?, &s[end..]))
+    }
+
+    log.lines().filter_map(|line| {
+        let mut rest = line.trim();
+        let mut severity = "";
+        for prefix in ["ERROR: ", "WARNING: "] {
+            if let Some(r) = rest.strip_prefix(prefix) {
+                severity = prefix.trim_end_matches(' ');
+                rest = r;
+            }
+        }
+        let (file, r) = number(rest)?;
+        let (line_number, r) = match r.chars().next()? {
+            ':' => number(&r[1..])?,
+            '(' => {
+                let (n, r) = number(&r[1..])?;
+                (n, r.strip_prefix(')')?)
+            },
+            _ => return None,
+        };
+        let message = r[r.find(':')? + 1..].trim();
+        Some(ShaderDiagnostic {
+            file: files.get(file as usize).cloned().unwrap_or_else(|| format!("<source {}>", file)),
+            line: line_number,
+            message: if severity.is_empty() {
+                message.to_string()
+            } else {
+                format!("{} {}", severity.to_lowercase(), message)
+            },
+        })
+    }).collect()
+}
+
+// The file and line of a byte in preprocessed source, following its #line directives.
+// `files` names the source string numbers used in them.
+fn source_location(source: &str, position: usize, files: &[String]) -> (String, u32) {
+    let (mut file, mut line) = (0, 1);
+    let mut start = 0;
+    for text in source.split_inclusive('\n') {
+        if position < start + text.len() {
+            break;
+        }
+        start += text.len();
+        let mut words = text.split_whitespace();
+        match (words.next(), words.next().and_then(|n| n.parse().ok())) {
+            (Some("#line"), Some(next)) => {
+                line = next;
+                file = words.next().and_then(|f| f.parse().ok()).unwrap_or(file);
+            },
+            _ => line += 1,
+        }
+    }
+    (files.get(file as usize).cloned().unwrap_or_else(|| format!("<source {}>", file)), line)
+}
+
 impl ShaderBuilder {
     pub unsafe fn new() -> ShaderBuilder {
         ShaderBuilder {
-            program_id: gl::CreateProgram(),
+            program: Program::new(),
             shaders: vec![],
+            files: vec![],
+            sources: vec![],
+            defines: vec![],
         }
     }
 
-    pub unsafe fn attach_file(self, shader_path: &str) -> ShaderBuilder {
+    // Adds `#define name value` to the files attached after this
+    pub fn define(mut self, name: &str, value: &str) -> ShaderBuilder {
+        self.defines.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    // Reads the file, resolving its #includes, see shader_preprocessor
+    pub unsafe fn attach_file(mut self, shader_path: &str) -> Result<ShaderBuilder, ShaderError> {
         let path = Path::new(shader_path);
-        if let Some(extension) = path.extension() {
-            let shader_type = ShaderType::from_ext(extension)
-                .expect("Failed to parse file extension.");
-            let shader_src = std::fs::read_to_string(path)
-                .expect(&format!("Failed to read shader source. {}", shader_path));
-            self.compile_shader(&shader_src, shader_type)
-        } else {
-            panic!("Failed to read extension of file with path: {}", shader_path);
-        }
+        let shader_type = path.extension()
+            .and_then(|extension| ShaderType::from_ext(extension).ok())
+            .ok_or_else(|| ShaderError::UnknownExtension { path: shader_path.to_string() })?;
+        let preprocessed = shader_preprocessor::preprocess(shader_path, &self.defines)?;
+        self.files.push(shader_path.to_string());
+        self.sources.extend(preprocessed.files.iter().cloned());
+        self.compile_named(&preprocessed.source, shader_type, preprocessed.files)
+    }
+
+    // Compiles the source as is, without preprocessing
+    pub unsafe fn compile_shader(mut self, shader_src: &str, shader_type: ShaderType) -> Result<ShaderBuilder, ShaderError> {
+        self.files.push("<string>".to_string());
+        self.compile_named(shader_src, shader_type, vec!["<string>".to_string()])
     }
 
-    pub unsafe fn compile_shader(mut self, shader_src: &str, shader_type: ShaderType) -> ShaderBuilder {
-        let shader = gl::CreateShader(shader_type.into());
-        let c_str_shader = CString::new(shader_src.as_bytes()).unwrap();
-        gl::ShaderSource(shader, 1, &c_str_shader.as_ptr(), ptr::null());
-        gl::CompileShader(shader);
+    // `files` names the source string numbers used in the #line directives of the source
+    unsafe fn compile_named(mut self, shader_src: &str, shader_type: ShaderType, files: Vec<String>) -> Result<ShaderBuilder, ShaderError> {
+        let c_str_shader = CString::new(shader_src.as_bytes()).map_err(|e| {
+            let (file, line) = source_location(shader_src, e.nul_position(), &files);
+            ShaderError::NulByte { file, line }
+        })?;
+        let shader = ShaderObject::new(shader_type.into());
+        gl::ShaderSource(shader.id(), 1, &c_str_shader.as_ptr(), ptr::null());
+        gl::CompileShader(shader.id());
 
-        if !self.check_shader_errors(shader) {
-            panic!("Shader failed to compile.");
+        let mut success = i32::from(gl::FALSE);
+        gl::GetShaderiv(shader.id(), gl::COMPILE_STATUS, &mut success);
+        if success != i32::from(gl::TRUE) {
+            let log = shader_info_log(shader.id());
+            return Err(ShaderError::Compile {
+                diagnostics: parse_info_log(&log, &files),
+                files,
+                log,
+            });
         }
 
         self.shaders.push(shader);
-
-        self
+        Ok(self)
     }
 
-    unsafe fn check_shader_errors(&self, shader_id: u32) -> bool {
-        let mut success = i32::from(gl::FALSE);
-        let mut info_log = Vec::with_capacity(512);
-        info_log.set_len(512 - 1);
-        gl::GetShaderiv(shader_id, gl::COMPILE_STATUS, &mut success);
-        if success != i32::from(gl::TRUE) {
-            gl::GetShaderInfoLog(
-                shader_id,
-                512,
-                ptr::null_mut(),
-                info_log.as_mut_ptr() as *mut gl::types::GLchar,
-            );
-            println!("ERROR::Shader Compilation Failed!\n{}", String::from_utf8_lossy(&info_log));
-            return false;
+    #[must_use = "The shader program is useless if not stored in a variable."]
+    pub unsafe fn link(self) -> Result<Shader, ShaderError> {
+        for shader in &self.shaders {
+            gl::AttachShader(self.program.id(), shader.id());
         }
-        true
-    }
+        gl::LinkProgram(self.program.id());
+        for shader in &self.shaders {
+            gl::DetachShader(self.program.id(), shader.id());
+        }
+        drop(self.shaders);
 
-    unsafe fn check_linker_errors(&self) -> bool {
         let mut success = i32::from(gl::FALSE);
-        let mut info_log = Vec::with_capacity(512);
-        info_log.set_len(512 - 1);
-        gl::GetProgramiv(self.program_id, gl::LINK_STATUS, &mut success);
+        gl::GetProgramiv(self.program.id(), gl::LINK_STATUS, &mut success);
         if success != i32::from(gl::TRUE) {
-            gl::GetProgramInfoLog(
-                self.program_id,
-                512,
-                ptr::null_mut(),
-                info_log.as_mut_ptr() as *mut gl::types::GLchar,
-            );
-            println!("ERROR::SHADER::PROGRAM::COMPILATION_FAILED\n{}", String::from_utf8_lossy(&info_log));
-            return false;
+            return Err(ShaderError::Link {
+                files: self.files.clone(),
+                log: program_info_log(self.program.id()),
+            });
         }
-        true
+
+        Ok(Shader::new(self.program, self.sources))
+    }
+}
+
+// Compiles and links the given files, telling the shader types apart by their extensions
+pub unsafe fn from_files(paths: &[&str]) -> Result<Shader, ShaderError> {
+    from_files_with_defines(paths, &[])
+}
+
+pub unsafe fn from_files_with_defines(paths: &[&str], defines: &[(&str, &str)]) -> Result<Shader, ShaderError> {
+    let mut builder = ShaderBuilder::new();
+    for (name, value) in defines {
+        builder = builder.define(name, value);
     }
+    for path in paths {
+        builder = builder.attach_file(path)?;
+    }
+    builder.link()
+}
 
-    #[must_use = "The shader program is useless if not stored in a variable."]
-    pub unsafe fn link(self) -> Shader {
-        for &shader in &self.shaders {
-            gl::AttachShader(self.program_id, shader);
-        }
-        gl::LinkProgram(self.program_id);
+unsafe fn shader_info_log(shader: u32) -> String {
+    let mut length = 0;
+    gl::GetShaderiv(shader, gl::INFO_LOG_LENGTH, &mut length);
+    let mut info_log = vec![0u8; length.max(1) as usize];
+    gl::GetShaderInfoLog(shader, length, ptr::null_mut(), info_log.as_mut_ptr() as *mut gl::types::GLchar);
+    String::from_utf8_lossy(&info_log).trim_end_matches('\0').to_string()
+}
 
-        // todo:: use this to make safer abstraction
-        self.check_linker_errors();
+unsafe fn program_info_log(program: u32) -> String {
+    let mut length = 0;
+    gl::GetProgramiv(program, gl::INFO_LOG_LENGTH, &mut length);
+    let mut info_log = vec![0u8; length.max(1) as usize];
+    gl::GetProgramInfoLog(program, length, ptr::null_mut(), info_log.as_mut_ptr() as *mut gl::types::GLchar);
+    String::from_utf8_lossy(&info_log).trim_end_matches('\0').to_string()
+}
 
-        for &shader in &self.shaders {
-            gl::DeleteShader(shader);
-        }
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-        Shader {
-            program_id: self.program_id
-        }
+    #[test]
+    fn source_location_follows_line_directives() {
+        let files = vec!["main.frag".to_string(), "lighting.glsl".to_string()];
+        let source = "#version 430\n#line 1 1\nfloat a;\nfloat b;\n#line 3 0\nvoid main() {}\n";
+        let at = |text: &str| source_location(source, source.find(text).unwrap(), &files);
+        assert_eq!(at("#version"), ("main.frag".to_string(), 1));
+        assert_eq!(at("float a"), ("lighting.glsl".to_string(), 1));
+        assert_eq!(at("float b"), ("lighting.glsl".to_string(), 2));
+        assert_eq!(at("void"), ("main.frag".to_string(), 3));
+        assert_eq!(source_location("a\nb", 2, &[]), ("<source 0>".to_string(), 2));
+    }
+
+    #[test]
+    fn parse_info_log_reads_every_vendor_format() {
+        let files = vec!["main.frag".to_string(), "lighting.glsl".to_string()];
+        let log = "\
+0:12(5): error: `colour' undeclared
+1:4(10): warning: `unused' declared but never used
+0(7) : error C0000: syntax error, unexpected ';'
+ERROR: 1:3: 'light' : undeclared identifier
+WARNING: 0:9: 'x' : implicit conversion
+ERROR: 2 compilation errors.  No code generated.
+Compilation failed
+3:1(1): error: out of range file
+";
+        let found: Vec<(String, u32, String)> = parse_info_log(log, &files)
+            .into_iter()
+            .map(|d| (d.file, d.line, d.message))
+            .collect();
+        let expected = [
+            ("main.frag", 12, "error: `colour' undeclared"),
+            ("lighting.glsl", 4, "warning: `unused' declared but never used"),
+            ("main.frag", 7, "error C0000: syntax error, unexpected ';'"),
+            ("lighting.glsl", 3, "error: 'light' : undeclared identifier"),
+            ("main.frag", 9, "warning: 'x' : implicit conversion"),
+            ("<source 3>", 1, "error: out of range file"),
+        ];
+        let expected: Vec<(String, u32, String)> = expected.iter()
+            .map(|&(file, line, message)| (file.to_string(), line, message.to_string()))
+            .collect();
+        assert_eq!(found, expected);
     }
 }
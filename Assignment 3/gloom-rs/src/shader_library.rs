@@ -0,0 +1,171 @@
+use std::collections::HashMap;
+use std::ops::BitOr;
+use std::time::{Instant, SystemTime};
+
+use crate::shader::{Shader, ShaderError};
+use crate::shader_preprocessor;
+use crate::shader_reload::{self, ReloadingShader, POLL_INTERVAL};
+
+// Variants of the same shader files, differing in which features are switched on.
+// Each feature is a #define injected into the sources, which the shaders test with #ifdef.
+// Variants are built the first time they are asked for, and kept around after that.
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ShaderFeatures(u32);
+
+impl ShaderFeatures {
+    pub const NONE          : ShaderFeatures = ShaderFeatures(0);
+    pub const LIT           : ShaderFeatures = ShaderFeatures(1 << 0); // Shaded by the frame's lights
+    pub const VERTEX_COLORS : ShaderFeatures = ShaderFeatures(1 << 1); // Colored by aColor instead of white
+
+    // The #define for each feature
+    const NAMES: [(ShaderFeatures, &'static str); 2] = [
+        (ShaderFeatures::LIT,           "LIT"),
+        (ShaderFeatures::VERTEX_COLORS, "VERTEX_COLORS"),
+    ];
+
+    pub fn contains(&self, other: ShaderFeatures) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    pub fn with(self, other: ShaderFeatures) -> ShaderFeatures {
+        ShaderFeatures(self.0 | other.0)
+    }
+
+    pub fn without(self, other: ShaderFeatures) -> ShaderFeatures {
+        ShaderFeatures(self.0 & !other.0)
+    }
+
+    pub fn defines(&self) -> Vec<(&'static str, &'static str)> {
+        ShaderFeatures::NAMES.iter()
+            .filter(|(feature, _)| self.contains(*feature))
+            .map(|&(_, name)| (name, "1"))
+            .collect()
+    }
+}
+
+impl BitOr for ShaderFeatures {
+    type Output = ShaderFeatures;
+
+    fn bitor(self, other: ShaderFeatures) -> ShaderFeatures {
+        self.with(other)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct ShaderKey {
+    pub sources  : Vec<String>,
+    pub features : ShaderFeatures,
+}
+
+enum Variant {
+    Built(Box<ReloadingShader>),
+    // Tried again once one of the files it read, or failed to read, is saved
+    Failed {
+        watched   : Vec<String>,
+        modified  : Vec<Option<SystemTime>>,
+        last_poll : Instant,
+    },
+}
+
+impl Variant {
+    fn failed(key: &ShaderKey) -> Variant {
+        let defines: Vec<(String, String)> = key.features.defines()
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect();
+        let watched = shader_preprocessor::files_read(&key.sources, &defines);
+        Variant::Failed {
+            modified: shader_reload::modification_times(&watched),
+            watched,
+            last_poll: Instant::now(),
+        }
+    }
+}
+
+pub struct ShaderLibrary {
+    variants : HashMap<ShaderKey, Variant>,
+    check    : fn(&Shader) -> Result<(), String>, // Run on every variant, see ReloadingShader
+}
+
+impl ShaderLibrary {
+    pub fn new(check: fn(&Shader) -> Result<(), String>) -> Self {
+        ShaderLibrary {
+            variants: HashMap::new(),
+            check,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.variants.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.variants.is_empty()
+    }
+
+    /// The variant of the given files with the given features, building it if needed.
+    /// None if it failed to build, in which case the error has been printed.
+    pub unsafe fn get(&mut self, sources: &[&str], features: ShaderFeatures) -> Option<&Shader> {
+        let key = ShaderKey {
+            sources: sources.iter().map(|s| s.to_string()).collect(),
+            features,
+        };
+        if !self.variants.contains_key(&key) {
+            let variant = self.build(&key);
+            self.variants.insert(key.clone(), variant);
+        }
+        match &self.variants[&key] {
+            Variant::Built(shader) => Some(shader),
+            Variant::Failed { .. } => None,
+        }
+    }
+
+    /// Reloads the variants whose files changed, returning what was reloaded and how it went.
+    pub unsafe fn poll(&mut self) -> Vec<(ShaderKey, Result<(), ShaderError>)> {
+        let mut results = vec![];
+        for (key, variant) in self.variants.iter_mut() {
+            match variant {
+                Variant::Built(shader) => {
+                    if let Some(result) = shader.poll() {
+                        results.push((key.clone(), result));
+                    }
+                },
+                Variant::Failed { watched, modified, last_poll } => {
+                    if last_poll.elapsed() < POLL_INTERVAL {
+                        continue;
+                    }
+                    *last_poll = Instant::now();
+                    if shader_reload::modification_times(watched) == *modified {
+                        continue;
+                    }
+                    let paths: Vec<&str> = key.sources.iter().map(|s| s.as_str()).collect();
+                    let result = ReloadingShader::with_defines(&paths, &key.features.defines(), self.check);
+                    match result {
+                        Ok(shader) => {
+                            *variant = Variant::Built(Box::new(shader));
+                            results.push((key.clone(), Ok(())));
+                        },
+                        Err(e) => {
+                            // The includes may have changed along with the error
+                            *variant = Variant::failed(key);
+                            results.push((key.clone(), Err(e)));
+                        },
+                    }
+                },
+            }
+        }
+        results
+    }
+
+    unsafe fn build(&self, key: &ShaderKey) -> Variant {
+        let paths: Vec<&str> = key.sources.iter().map(|s| s.as_str()).collect();
+        match ReloadingShader::with_defines(&paths, &key.features.defines(), self.check) {
+            Ok(shader) => Variant::Built(Box::new(shader)),
+            Err(e) => {
+                println!("ERROR: {}\n(variant {:?})", e, key.features);
+                Variant::failed(key)
+            },
+        }
+    }
+}
@@ -0,0 +1,281 @@
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+
+use crate::shader::ShaderError;
+
+// Resolves `#include "file"` directives before a shader is handed to the driver, and injects
+// `#define`s right after the `#version` line so one file can be built into several variants.
+//
+// Included paths are relative to the including file. Every file is included at most once per
+// shader, as if it had an include guard, and an include cycle is an error. `#line` directives
+// are inserted around each included file, with the file's index in `files` as the source string
+// number, so the driver's error messages can be mapped back to the right file and line.
+
+pub struct PreprocessedSource {
+    pub source : String,
+    pub files  : Vec<String>, // Indexed by the source string numbers in the #line directives
+}
+
+pub fn preprocess(path: &str, defines: &[(String, String)]) -> Result<PreprocessedSource, ShaderError> {
+    let mut preprocessor = Preprocessor::new();
+    let mut source = String::new();
+    preprocessor.include(Path::new(path), &mut source, Some(defines))?;
+    Ok(PreprocessedSource {
+        source,
+        files: preprocessor.files,
+    })
+}
+
+/// Every file that preprocessing the given files reads or tries to read, as far as it gets.
+/// Unlike the `files` of a successful run this includes files that are missing, so a shader
+/// that fails to build can be tried again once any of them appears or changes.
+pub fn files_read(paths: &[String], defines: &[(String, String)]) -> Vec<String> {
+    let mut files: Vec<String> = vec![];
+    for path in paths {
+        let mut preprocessor = Preprocessor::new();
+        let _ = preprocessor.include(Path::new(path), &mut String::new(), Some(defines));
+        for file in preprocessor.read {
+            if !files.contains(&file) {
+                files.push(file);
+            }
+        }
+    }
+    files
+}
+
+struct Preprocessor {
+    files    : Vec<String>,
+    read     : Vec<String>,       // Every file opened, or tried to, including repeated includes
+    stack    : Vec<PathBuf>,      // The files currently being included, outermost first
+    included : HashSet<PathBuf>,
+}
+
+impl Preprocessor {
+    fn new() -> Self {
+        Preprocessor {
+            files: vec![],
+            read: vec![],
+            stack: vec![],
+            included: HashSet::new(),
+        }
+    }
+
+    // `defines` is only given for the root file, which the #defines go into
+    fn include(&mut self, path: &Path, out: &mut String, defines: Option<&[(String, String)]>) -> Result<(), ShaderError> {
+        let name = path.to_string_lossy().to_string();
+        self.read.push(name.clone());
+        let source = std::fs::read_to_string(path)
+            .map_err(|error| ShaderError::Io { path: name.clone(), error })?;
+        let key = file_key(path);
+        if !self.included.insert(key.clone()) {
+            return Ok(());
+        }
+
+        let index = self.files.len();
+        self.files.push(name.clone());
+        self.stack.push(key);
+
+        // Without a #version line the defines go first, which is all we can do
+        let has_version = source.lines().any(|l| l.trim_start().starts_with("#version"));
+        match defines {
+            Some(defines) if !has_version => {
+                write_defines(out, defines);
+                out.push_str(&format!("#line 1 {}\n", index));
+            },
+            Some(_) => {},
+            None => out.push_str(&format!("#line 1 {}\n", index)),
+        }
+
+        for (i, line) in source.lines().enumerate() {
+            let line_number = i as u32 + 1;
+            let directive = line.trim_start();
+
+            if directive.starts_with("#version") {
+                match defines {
+                    Some(defines) => {
+                        out.push_str(line);
+                        out.push('\n');
+                        write_defines(out, defines);
+                        out.push_str(&format!("#line {} {}\n", line_number + 1, index));
+                    },
+                    // Only the root file's #version counts, but keep the line count
+                    None => out.push('\n'),
+                }
+            } else if directive.starts_with("#pragma once") {
+                out.push('\n'); // Every file is only included once anyway
+            } else if let Some(rest) = directive.strip_prefix("#include") {
+                let included = parse_include(rest).ok_or_else(|| ShaderError::Include {
+                    file: name.clone(),
+                    line: line_number,
+                    message: format!("Expected #include \"file\", found {}", directive),
+                })?;
+                let included_path = path.parent().unwrap_or_else(|| Path::new("")).join(included);
+                if self.stack.contains(&file_key(&included_path)) {
+                    let mut chain: Vec<String> = self.stack.iter().map(|p| p.to_string_lossy().to_string()).collect();
+                    chain.push(included_path.to_string_lossy().to_string());
+                    return Err(ShaderError::Include {
+                        file: name,
+                        line: line_number,
+                        message: format!("Include cycle: {}", chain.join(" -> ")),
+                    });
+                }
+                self.include(&included_path, out, None).map_err(|e| match e {
+                    // Point at the #include line if the file isn't there
+                    ShaderError::Io { path, error } if path == included_path.to_string_lossy() => ShaderError::Include {
+                        file: name.clone(),
+                        line: line_number,
+                        message: format!("Can't include {}: {}", path, error),
+                    },
+                    e => e,
+                })?;
+                out.push_str(&format!("#line {} {}\n", line_number + 1, index));
+            } else {
+                out.push_str(line);
+                out.push('\n');
+            }
+        }
+
+        self.stack.pop();
+        Ok(())
+    }
+}
+
+// The same file, however it was reached
+fn file_key(path: &Path) -> PathBuf {
+    path.canonicalize().unwrap_or_else(|_| path.to_path_buf())
+}
+
+fn parse_include(rest: &str) -> Option<&str> {
+    let rest = rest.trim();
+    let name = rest.strip_prefix('"')?.strip_suffix('"')?;
+    if name.is_empty() || name.contains('"') {
+        return None;
+    }
+    Some(name)
+}
+
+fn write_defines(out: &mut String, defines: &[(String, String)]) {
+    for (name, value) in defines {
+        out.push_str(&format!("#define {} {}\n", name, value));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A directory of its own for the test, holding the given files
+    fn directory(test: &str, files: &[(&str, &str)]) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("gloom_preprocessor_{}_{}", test, std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        for (name, contents) in files {
+            std::fs::write(dir.join(name), contents).unwrap();
+        }
+        dir
+    }
+
+    fn name(path: &Path) -> String {
+        path.to_string_lossy().to_string()
+    }
+
+    #[test]
+    fn includes_every_file_once() {
+        let dir = directory("once", &[
+            ("main.frag", "#include \"a.glsl\"\n#include \"b.glsl\"\n"),
+            ("a.glsl", "#pragma once\nfloat a;\n"),
+            ("b.glsl", "#include \"a.glsl\"\nfloat b;\n"),
+        ]);
+        let result = preprocess(&name(&dir.join("main.frag")), &[]).unwrap();
+        assert_eq!(result.source.matches("float a;").count(), 1);
+        assert_eq!(result.source.matches("float b;").count(), 1);
+        assert!(!result.source.contains("#pragma once"));
+        assert_eq!(result.files, vec![name(&dir.join("main.frag")), name(&dir.join("a.glsl")), name(&dir.join("b.glsl"))]);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn include_cycles_are_errors() {
+        let dir = directory("cycle", &[
+            ("main.frag", "#include \"a.glsl\"\n"),
+            ("a.glsl", "float a;\n#include \"b.glsl\"\n"),
+            ("b.glsl", "#include \"a.glsl\"\n"),
+        ]);
+        match preprocess(&name(&dir.join("main.frag")), &[]) {
+            Err(ShaderError::Include { file, line, message }) => {
+                assert_eq!((file, line), (name(&dir.join("b.glsl")), 1));
+                assert!(message.starts_with("Include cycle: "), "{}", message);
+                assert!(message.ends_with("a.glsl"), "{}", message);
+            },
+            Err(e) => panic!("unexpected error {}", e),
+            Ok(_) => panic!("the cycle was accepted"),
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn missing_includes_point_at_the_include_line() {
+        let dir = directory("missing", &[("main.frag", "#version 430\n#include \"missing.glsl\"\n")]);
+        match preprocess(&name(&dir.join("main.frag")), &[]) {
+            Err(ShaderError::Include { file, line, message }) => {
+                assert_eq!((file, line), (name(&dir.join("main.frag")), 2));
+                assert!(message.starts_with("Can't include "), "{}", message);
+            },
+            Err(e) => panic!("unexpected error {}", e),
+            Ok(_) => panic!("the missing file was accepted"),
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn defines_go_right_after_the_version() {
+        let dir = directory("defines", &[
+            ("versioned.frag", "// Comment\n#version 430 core\nvoid main() {}\n"),
+            ("unversioned.frag", "void main() {}\n"),
+        ]);
+        let defines = vec![("LIT".to_string(), "1".to_string()), ("LIGHTS".to_string(), "4".to_string())];
+        let versioned = preprocess(&name(&dir.join("versioned.frag")), &defines).unwrap();
+        assert_eq!(
+            versioned.source,
+            "// Comment\n#version 430 core\n#define LIT 1\n#define LIGHTS 4\n#line 3 0\nvoid main() {}\n",
+        );
+        let unversioned = preprocess(&name(&dir.join("unversioned.frag")), &defines).unwrap();
+        assert_eq!(unversioned.source, "#define LIT 1\n#define LIGHTS 4\n#line 1 0\nvoid main() {}\n");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn line_directives_give_the_file_index_and_line() {
+        let dir = directory("lines", &[
+            ("main.frag", "#version 430\n#include \"a.glsl\"\nfloat m;\n#include \"b.glsl\"\n"),
+            ("a.glsl", "float a1;\nfloat a2;\n"),
+            ("b.glsl", "\n#include \"a.glsl\"\nfloat b;\n"),
+        ]);
+        let result = preprocess(&name(&dir.join("main.frag")), &[]).unwrap();
+        let expected = [
+            "#version 430", "#line 2 0",
+            "#line 1 1", "float a1;", "float a2;", "#line 3 0",
+            "float m;",
+            "#line 1 2", "", "#line 3 2", "float b;", "#line 5 0",
+        ];
+        assert_eq!(result.source.lines().collect::<Vec<_>>(), expected);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn files_read_lists_includes_and_missing_files() {
+        let dir = directory("files_read", &[
+            ("main.frag", "#version 430\n#include \"lighting.glsl\"\nvoid main() {}\n"),
+            ("lighting.glsl", "#include \"missing.glsl\"\n"),
+        ]);
+        let (main, lighting) = (dir.join("main.frag"), dir.join("lighting.glsl"));
+
+        let paths = vec![name(&main), name(&lighting)];
+        assert!(preprocess(&paths[0], &[]).is_err());
+        assert_eq!(files_read(&paths, &[]), vec![name(&main), name(&lighting), name(&dir.join("missing.glsl"))]);
+
+        std::fs::write(&lighting, "float light;\n").unwrap();
+        assert_eq!(preprocess(&paths[0], &[]).unwrap().files, vec![name(&main), name(&lighting)]);
+        assert_eq!(files_read(&paths, &[]), vec![name(&main), name(&lighting)]);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
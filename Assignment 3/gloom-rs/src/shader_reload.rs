@@ -0,0 +1,113 @@
+use std::ops::Deref;
+use std::time::{Duration, Instant, SystemTime};
+
+use crate::shader::{self, Shader, ShaderError};
+
+// A shader program built from files, rebuilt whenever one of them changes on disk.
+// The new program only replaces the old one if it compiles, links and passes the check,
+// so a typo while editing leaves the last working program in place.
+
+// How often to look at the files, to avoid hitting the file system every frame
+pub const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+pub struct ReloadingShader {
+    shader      : Shader,
+    paths       : Vec<String>,
+    watched     : Vec<String>,               // The paths and everything they include
+    modified    : Vec<Option<SystemTime>>,   // Of the watched files
+    last_poll   : Instant,
+    check       : fn(&Shader) -> Result<(), String>, // Does the program fit the data it is fed
+    defines     : Vec<(String, String)>,
+}
+
+impl ReloadingShader {
+    pub unsafe fn new(paths: &[&str], check: fn(&Shader) -> Result<(), String>) -> Result<Self, ShaderError> {
+        ReloadingShader::with_defines(paths, &[], check)
+    }
+
+    // The defines are injected into every file, see ShaderBuilder::define
+    pub unsafe fn with_defines(
+        paths: &[&str],
+        defines: &[(&str, &str)],
+        check: fn(&Shader) -> Result<(), String>,
+    ) -> Result<Self, ShaderError> {
+        let paths: Vec<String> = paths.iter().map(|p| p.to_string()).collect();
+        let defines: Vec<(String, String)> = defines.iter().map(|(n, v)| (n.to_string(), v.to_string())).collect();
+        let shader = build(&paths, &defines, check)?;
+        let watched = shader.source_files().to_vec();
+        Ok(ReloadingShader {
+            modified: modification_times(&watched),
+            shader,
+            paths,
+            watched,
+            last_poll: Instant::now(),
+            check,
+            defines,
+        })
+    }
+
+    pub fn paths(&self) -> &[String] {
+        &self.paths
+    }
+
+    /// Rebuilds the program if any of its files changed since last time.
+    /// Returns None if nothing changed, otherwise whether the new program was swapped in.
+    pub unsafe fn poll(&mut self) -> Option<Result<(), ShaderError>> {
+        if self.last_poll.elapsed() < POLL_INTERVAL {
+            return None;
+        }
+        self.last_poll = Instant::now();
+
+        let modified = modification_times(&self.watched);
+        if modified == self.modified {
+            return None;
+        }
+        // Remember the new times even if building fails, so we only try again on the next save
+        self.modified = modified;
+        Some(self.reload())
+    }
+
+    /// Rebuilds the program right away.
+    pub unsafe fn reload(&mut self) -> Result<(), ShaderError> {
+        self.shader = build(&self.paths, &self.defines, self.check)?;
+        // The includes may have changed as well
+        self.watched = self.shader.source_files().to_vec();
+        self.modified = modification_times(&self.watched);
+        Ok(())
+    }
+}
+
+impl Deref for ReloadingShader {
+    type Target = Shader;
+
+    fn deref(&self) -> &Shader {
+        &self.shader
+    }
+}
+
+unsafe fn build(
+    paths: &[String],
+    defines: &[(String, String)],
+    check: fn(&Shader) -> Result<(), String>,
+) -> Result<Shader, ShaderError> {
+    let paths: Vec<&str> = paths.iter().map(|p| p.as_str()).collect();
+    let defines: Vec<(&str, &str)> = defines.iter().map(|(n, v)| (n.as_str(), v.as_str())).collect();
+    let shader = shader::from_files_with_defines(&paths, &defines)?;
+    check(&shader).map_err(|message| ShaderError::Interface {
+        files: paths.iter().map(|p| p.to_string()).collect(),
+        message,
+    })?;
+    Ok(shader)
+}
+
+// None for files that can't be read right now, e.g. while an editor is saving them
+pub fn modification_times(paths: &[String]) -> Vec<Option<SystemTime>> {
+    paths.iter()
+        .map(|p| std::fs::metadata(p).and_then(|m| m.modified()).ok())
+        .collect()
+}
+
+// For shaders that need no checking beyond linking
+pub fn no_check(_: &Shader) -> Result<(), String> {
+    Ok(())
+}
@@ -0,0 +1,109 @@
+extern crate nalgebra_glm as glm;
+
+use crate::render_target::{PreviousTarget, RenderTarget, RenderTargetDescription};
+use crate::shader::{Shader, ShaderError};
+use crate::shader_reload::ReloadingShader;
+
+// Shadows cast by a directional light, rendered into a depth texture from the light's point of
+// view. The light's frustum is fitted around the nearer part of the camera frustum, so the
+// texels are spent where the camera is looking.
+
+pub const SHADOW_MAP_SIZE: i32 = 2048;
+pub const SHADOW_MAP_UNIT: u32 = 1;         // Has to match the binding of uShadowMap in lighting.glsl
+const SHADOW_DISTANCE: f32 = 300.0;         // Beyond this distance from the camera nothing is shadowed
+const SHADOW_CASTER_MARGIN: f32 = 400.0;    // How far behind the shadowed region casters are looked for
+const DEPTH_BIAS_FACTOR: f32 = 2.0;         // Slope scaled depth offset in the shadow pass
+const DEPTH_BIAS_UNITS: f32 = 4.0;
+const NORMAL_OFFSET_TEXELS: f32 = 1.5;      // Receivers are looked up this far along their normal
+
+pub struct ShadowMap {
+    target      : RenderTarget,     // Depth only
+    shader      : ReloadingShader,
+    pub distance : f32,
+}
+
+impl ShadowMap {
+    // `check` is run on the depth only program, see ReloadingShader
+    pub unsafe fn new(check: fn(&Shader) -> Result<(), String>) -> Result<Self, ShaderError> {
+        let shader = ReloadingShader::new(&["shaders/shadow.vert", "shaders/shadow.frag"], check)?;
+
+        let description = RenderTargetDescription::new().depth(gl::DEPTH_COMPONENT32F);
+        let target = RenderTarget::new(&description, SHADOW_MAP_SIZE, SHADOW_MAP_SIZE)
+            .unwrap_or_else(|e| panic!("{}", e));
+
+        // Compared against in the lookups, with everything outside the map being lit
+        let depth = target.depth().unwrap();
+        depth.set_wrap(gl::CLAMP_TO_BORDER);
+        gl::TexParameterfv(gl::TEXTURE_2D, gl::TEXTURE_BORDER_COLOR, [1.0f32; 4].as_ptr());
+        gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_COMPARE_MODE, gl::COMPARE_REF_TO_TEXTURE as i32);
+        gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_COMPARE_FUNC, gl::LEQUAL as i32);
+
+        Ok(ShadowMap {
+            target,
+            shader,
+            distance: SHADOW_DISTANCE,
+        })
+    }
+
+    pub fn shader_mut(&mut self) -> &mut ReloadingShader {
+        &mut self.shader
+    }
+
+    /// The light's view projection, covering what the camera sees up to `distance` away.
+    /// `frustum_corners` are the corners of the camera frustum up to there.
+    pub fn fit_to_frustum(&self, light_direction: &glm::Vec3, frustum_corners: &[glm::Vec3; 8]) -> glm::Mat4 {
+        let up = if light_direction.y.abs() > 0.99 { glm::vec3(0.0, 0.0, 1.0) } else { glm::vec3(0.0, 1.0, 0.0) };
+        let rotation = glm::look_at(&glm::zero(), light_direction, &up);
+
+        // A sphere around the frustum keeps the same size as the camera turns,
+        // so the shadow edges don't crawl
+        let center = frustum_corners.iter().fold(glm::Vec3::zeros(), |sum, c| sum + c) / 8.0;
+        let radius = frustum_corners.iter().map(|c| glm::distance(c, &center)).fold(0.0, f32::max);
+        let radius = (radius * 16.0).ceil() / 16.0;
+
+        // Move in whole texels, for the same reason
+        let texel = 2.0 * radius / SHADOW_MAP_SIZE as f32;
+        let center = (rotation * center.push(1.0)).xyz();
+        let x = (center.x / texel).floor() * texel;
+        let y = (center.y / texel).floor() * texel;
+
+        // Looking down -z, everything between the casters behind the sphere and its far side
+        let projection = glm::ortho(
+            x - radius, x + radius,
+            y - radius, y + radius,
+            -center.z - radius - SHADOW_CASTER_MARGIN, -center.z + radius,
+        );
+        projection * rotation
+    }
+
+    // How far receivers are moved along their normal before the lookup, in world units
+    pub fn normal_offset(&self, light_view_projection: &glm::Mat4) -> f32 {
+        let m = light_view_projection;
+        let scale = glm::vec3(m[(0, 0)], m[(0, 1)], m[(0, 2)]).norm(); // 2 / width of the map in the world
+        let texel = 2.0 / (scale * SHADOW_MAP_SIZE as f32);
+        NORMAL_OFFSET_TEXELS * texel
+    }
+
+    /// Renders the depth of everything `draw` draws into the map. The frame uniforms have to hold
+    /// the light's view projection. The previous framebuffer and viewport are restored afterwards.
+    pub unsafe fn render<F: FnOnce(&Shader)>(&self, draw: F) {
+        let previous = PreviousTarget::save();
+        self.target.bind();
+        gl::DepthMask(gl::TRUE);
+        gl::Clear(gl::DEPTH_BUFFER_BIT);
+        gl::Enable(gl::POLYGON_OFFSET_FILL);
+        gl::PolygonOffset(DEPTH_BIAS_FACTOR, DEPTH_BIAS_UNITS);
+
+        self.shader.activate();
+        draw(&self.shader);
+
+        gl::Disable(gl::POLYGON_OFFSET_FILL);
+        previous.restore();
+    }
+
+    // For the lookups in lighting.glsl
+    pub unsafe fn bind(&self) {
+        self.target.depth().unwrap().bind_to_unit(SHADOW_MAP_UNIT);
+        gl::ActiveTexture(gl::TEXTURE0);
+    }
+}
@@ -0,0 +1,132 @@
+extern crate nalgebra_glm as glm;
+
+use crate::gl_objects::{Texture, VertexArray};
+use crate::heightfield::HeightField;
+use crate::material::Material;
+use crate::shader::{Shader, ShaderError};
+use crate::shader_reload::ReloadingShader;
+use crate::uniform_buffer::FrameUniforms;
+use crate::vertex_layout::{as_bytes, create_vao_with_layout, VertexAttribute, VertexLayout};
+
+// The terrain drawn as a coarse grid of flat patches, which the tessellation shaders split up
+// into more triangles the larger they appear on screen. The heights come from a heightmap
+// texture sampled from the terrain's HeightField, so no detailed mesh is needed on the GPU.
+
+const PATCHES_PER_SIDE: usize = 32;
+const HEIGHTMAP_SIZE: usize = 512;
+const HEIGHTMAP_UNIT: u32 = 0;
+
+// Aim for triangle edges about this many pixels long
+const PIXELS_PER_EDGE: f32 = 12.0;
+
+pub struct TessellatedTerrain {
+    pub pixels_per_edge : f32,
+    pub material        : Material,
+    patches             : VertexArray,
+    heightmap           : Texture,
+    shader              : ReloadingShader,
+    depth_shader        : ReloadingShader, // Tessellated the same way, into the shadow map
+    min                 : glm::Vec2, // Corners of the terrain, in (x, z)
+    max                 : glm::Vec2,
+}
+
+fn check_terrain_shader(shader: &Shader) -> Result<(), String> {
+    unsafe { FrameUniforms::check_layout(shader.program_id()) }
+}
+
+impl TessellatedTerrain {
+    pub unsafe fn new(terrain: &HeightField) -> Result<Self, ShaderError> {
+        let shader = ReloadingShader::new(
+            &["shaders/terrain.vert", "shaders/terrain.tcs", "shaders/terrain.tes", "shaders/terrain.frag"],
+            check_terrain_shader,
+        )?;
+        let depth_shader = ReloadingShader::with_defines(
+            &["shaders/terrain.vert", "shaders/terrain.tcs", "shaders/terrain.tes", "shaders/shadow.frag"],
+            &[("DEPTH_ONLY", "1")],
+            check_terrain_shader,
+        )?;
+
+        println!("Rasterizing {}x{} heightmap...", HEIGHTMAP_SIZE, HEIGHTMAP_SIZE);
+        let before = std::time::Instant::now();
+        let heights = terrain.rasterize(HEIGHTMAP_SIZE, HEIGHTMAP_SIZE);
+        let heightmap = Texture::with_data_2d(
+            HEIGHTMAP_SIZE as i32, HEIGHTMAP_SIZE as i32,
+            gl::R32F, gl::RED, gl::FLOAT,
+            &heights,
+        );
+        let after = std::time::Instant::now();
+        println!("Done in {:.3}ms.", after.duration_since(before).as_micros() as f32 / 1e3);
+
+        // One patch per grid cell, with its corners in the order the evaluation shader expects
+        let (min, max) = terrain.bounds();
+        let n = PATCHES_PER_SIDE + 1;
+        let corners: Vec<f32> = (0..n * n)
+            .flat_map(|i| {
+                let (x, z) = ((i % n) as f32 / PATCHES_PER_SIDE as f32, (i / n) as f32 / PATCHES_PER_SIDE as f32);
+                [min.x + (max.x - min.x) * x, min.y + (max.y - min.y) * z]
+            })
+            .collect();
+        let mut indices: Vec<u32> = Vec::with_capacity(PATCHES_PER_SIDE * PATCHES_PER_SIDE * 4);
+        for z in 0..PATCHES_PER_SIDE {
+            for x in 0..PATCHES_PER_SIDE {
+                let i = (z * n + x) as u32;
+                indices.extend_from_slice(&[i, i + 1, i + 1 + n as u32, i + n as u32]);
+            }
+        }
+        let layout = VertexLayout::new().separate(VertexAttribute::float("aPatchCorner", 0, 2));
+        let patches = create_vao_with_layout(&layout, &[as_bytes(&corners)], &indices, gl::STATIC_DRAW)
+            .unwrap_or_else(|e| panic!("Failed to create the terrain patches: {}", e));
+
+        Ok(TessellatedTerrain {
+            pixels_per_edge: PIXELS_PER_EDGE,
+            material: Material::default(),
+            patches,
+            heightmap,
+            shader,
+            depth_shader,
+            min,
+            max,
+        })
+    }
+
+    pub fn shaders_mut(&mut self) -> [&mut ReloadingShader; 2] {
+        [&mut self.shader, &mut self.depth_shader]
+    }
+
+    /// Draws the terrain with the camera and lights of the frame uniforms.
+    pub unsafe fn draw(&self, alpha: f32) {
+        let mut viewport = [0i32; 4];
+        gl::GetIntegerv(gl::VIEWPORT, viewport.as_mut_ptr());
+
+        self.shader.activate();
+        self.shader.set_f32("uAlpha", alpha);
+        self.material.apply(&self.shader);
+        self.draw_patches(&self.shader, &glm::vec2(viewport[2] as f32, viewport[3] as f32));
+    }
+
+    /// Draws the depth of the terrain into the shadow map, see ShadowMap::render.
+    /// `viewport_size` is the size of the camera's view, so the terrain is split up the same
+    /// way as when it is drawn and doesn't shadow itself where the two differ.
+    pub unsafe fn draw_depth(&self, viewport_size: &glm::Vec2) {
+        self.depth_shader.activate();
+        self.draw_patches(&self.depth_shader, viewport_size);
+    }
+
+    // With the shader active
+    unsafe fn draw_patches(&self, shader: &Shader, viewport_size: &glm::Vec2) {
+        let mut max_level = 0;
+        gl::GetIntegerv(gl::MAX_TESS_GEN_LEVEL, &mut max_level);
+
+        shader.set_texture("uHeightmap", HEIGHTMAP_UNIT, gl::TEXTURE_2D, self.heightmap.id());
+        shader.set_vec2("uTerrainMin", &self.min);
+        shader.set_vec2("uTerrainSize", &(self.max - self.min));
+        shader.set_vec2("uViewportSize", viewport_size);
+        shader.set_f32("uPixelsPerEdge", self.pixels_per_edge);
+        shader.set_f32("uMaxTessLevel", max_level as f32);
+
+        self.patches.bind();
+        gl::PatchParameteri(gl::PATCH_VERTICES, 4);
+        gl::DrawElements(gl::PATCHES, self.patches.index_count(), gl::UNSIGNED_INT, std::ptr::null());
+        gl::BindVertexArray(0);
+    }
+}
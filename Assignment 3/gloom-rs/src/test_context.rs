@@ -0,0 +1,40 @@
+use khronos_egl as egl;
+
+// An OpenGL 4.3 context for the tests that need a GPU, made through EGL without any window or
+// surface, so it works on a machine without a display server. Those tests are #[ignore]d, as
+// not every machine running the tests has a driver for it; run them with
+// `cargo test -- --ignored`.
+
+const PLATFORM_SURFACELESS_MESA: egl::Enum = 0x31DD;
+
+/// Makes a new context current on the calling thread, and loads the OpenGL functions from it.
+/// Each test runs on a thread of its own, so each makes its own. The context lives until the
+/// process ends.
+pub unsafe fn make_current() {
+    let egl = egl::DynamicInstance::<egl::EGL1_5>::load_required()
+        .unwrap_or_else(|e| panic!("Failed to load libEGL: {}", e));
+    let display = egl.get_platform_display(PLATFORM_SURFACELESS_MESA, egl::DEFAULT_DISPLAY, &[egl::ATTRIB_NONE])
+        .unwrap_or_else(|e| panic!("Failed to get a surfaceless EGL display: {}", e));
+    egl.initialize(display).unwrap_or_else(|e| panic!("Failed to initialize EGL: {}", e));
+    egl.bind_api(egl::OPENGL_API).unwrap_or_else(|e| panic!("{}", e));
+
+    // Nothing is drawn to an EGL surface, but asking for none matches no config at all
+    let config = egl.choose_first_config(display, &[
+        egl::SURFACE_TYPE, egl::PBUFFER_BIT,
+        egl::RENDERABLE_TYPE, egl::OPENGL_BIT,
+        egl::NONE,
+    ])
+        .unwrap_or_else(|e| panic!("{}", e))
+        .expect("No EGL config can render with OpenGL");
+    let context = egl.create_context(display, config, None, &[
+        egl::CONTEXT_MAJOR_VERSION, 4,
+        egl::CONTEXT_MINOR_VERSION, 3,
+        egl::CONTEXT_OPENGL_PROFILE_MASK, egl::CONTEXT_OPENGL_CORE_PROFILE_BIT,
+        egl::NONE,
+    ]).unwrap_or_else(|e| panic!("Failed to create an OpenGL 4.3 context: {}", e));
+    egl.make_current(display, None, None, Some(context)).unwrap_or_else(|e| panic!("{}", e));
+
+    gl::load_with(|symbol| egl.get_proc_address(symbol).map_or(std::ptr::null(), |f| f as *const _));
+    // Dropping it would unload libEGL from under the context
+    std::mem::forget(egl);
+}
@@ -0,0 +1,214 @@
+extern crate nalgebra_glm as glm;
+
+use std::ffi::CString;
+use std::marker::PhantomData;
+use std::mem::{offset_of, size_of};
+
+use crate::gl_objects::Buffer;
+
+// A uniform buffer holding a single T, bound to a fixed binding point so that every shader
+// program declaring a block with the same binding reads from it.
+// T has to be #[repr(C)] and match the std140 layout of the block.
+
+pub struct UniformBuffer<T> {
+    buffer  : Buffer,
+    binding : u32,
+    phantom : PhantomData<T>,
+}
+
+impl<T> UniformBuffer<T> {
+    pub unsafe fn new(binding: u32) -> Self {
+        let mut buffer = Buffer::new(gl::UNIFORM_BUFFER);
+        buffer.bind();
+        gl::BufferData(gl::UNIFORM_BUFFER, size_of::<T>() as isize, std::ptr::null(), gl::DYNAMIC_DRAW);
+        gl::BindBufferBase(gl::UNIFORM_BUFFER, binding, buffer.id());
+        gl::BindBuffer(gl::UNIFORM_BUFFER, 0);
+        UniformBuffer {
+            buffer,
+            binding,
+            phantom: PhantomData,
+        }
+    }
+
+    pub fn binding(&self) -> u32 {
+        self.binding
+    }
+
+    pub unsafe fn update(&self, data: &T) {
+        self.buffer.bind();
+        gl::BufferSubData(gl::UNIFORM_BUFFER, 0, size_of::<T>() as isize, data as *const T as *const _);
+        gl::BindBuffer(gl::UNIFORM_BUFFER, 0);
+    }
+
+    // Binds the buffer to its binding point again, in case something else was bound there
+    pub unsafe fn bind(&self) {
+        gl::BindBufferBase(gl::UNIFORM_BUFFER, self.binding, self.buffer.id());
+    }
+}
+
+/// Compares the offsets the driver gives the members of a uniform block with the expected ones.
+/// Members the program doesn't use are optimized away, and are skipped.
+pub unsafe fn check_block_layout(program_id: u32, block: &str, members: &[(&str, usize)]) -> Result<(), String> {
+    let block_name = CString::new(block).unwrap();
+    let block_index = gl::GetUniformBlockIndex(program_id, block_name.as_ptr());
+    if block_index == gl::INVALID_INDEX {
+        return Ok(()); // The program doesn't use the block at all
+    }
+
+    for &(member, expected) in members {
+        let name = CString::new(member).unwrap();
+        let mut index = gl::INVALID_INDEX;
+        gl::GetUniformIndices(program_id, 1, &name.as_ptr(), &mut index);
+        if index == gl::INVALID_INDEX {
+            continue;
+        }
+        let mut offset = -1;
+        gl::GetActiveUniformsiv(program_id, 1, &index, gl::UNIFORM_OFFSET, &mut offset);
+        if offset as usize != expected {
+            return Err(format!(
+                "{}.{} is at offset {} in the shader, but at {} on the Rust side",
+                block, member, offset, expected,
+            ));
+        }
+    }
+    Ok(())
+}
+
+// Per-frame data shared by all shader programs, see FrameData in the shaders
+
+pub const FRAME_UNIFORMS_BINDING: u32 = 0;
+pub const MAX_LIGHTS: usize = 16;
+
+// See Light in light.rs, which fills these in
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct LightUniform {
+    pub position  : [f32; 4], // xyz: position in world space, w: kind of light
+    pub direction : [f32; 4], // xyz: direction the light travels in, w: range
+    pub color     : [f32; 4], // rgb: color, a: intensity
+    pub cone      : [f32; 4], // x: cosine of the outer angle, y: of the inner angle
+}
+
+impl LightUniform {
+    // The kinds of light, as stored in position.w
+    pub const DIRECTIONAL : f32 = 0.0;
+    pub const POINT       : f32 = 1.0;
+    pub const SPOT        : f32 = 2.0;
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct FrameUniforms {
+    pub view            : [[f32; 4]; 4],
+    pub projection      : [[f32; 4]; 4],
+    pub view_projection : [[f32; 4]; 4],
+    pub camera_position : [f32; 4],      // w is unused
+    pub time            : f32,
+    pub light_count     : i32,
+    pub _padding        : [f32; 2],
+    pub ambient_light   : [f32; 4],      // rgb: color, a is unused
+    pub shadow_view_projection : [[f32; 4]; 4],
+    pub shadow_light    : i32,           // Index of the light casting shadows, -1 if none
+    pub shadow_normal_offset : f32,      // In world units, see shadow.rs
+    pub _shadow_padding : [f32; 2],
+    pub lights          : [LightUniform; MAX_LIGHTS],
+}
+
+// std140 puts every member on these offsets, which the #[repr(C)] struct has to follow
+const _: () = {
+    assert!(offset_of!(LightUniform, position) == 0);
+    assert!(offset_of!(LightUniform, direction) == 16);
+    assert!(offset_of!(LightUniform, color) == 32);
+    assert!(offset_of!(LightUniform, cone) == 48);
+    assert!(size_of::<LightUniform>() == 64);
+
+    assert!(offset_of!(FrameUniforms, view) == 0);
+    assert!(offset_of!(FrameUniforms, projection) == 64);
+    assert!(offset_of!(FrameUniforms, view_projection) == 128);
+    assert!(offset_of!(FrameUniforms, camera_position) == 192);
+    assert!(offset_of!(FrameUniforms, time) == 208);
+    assert!(offset_of!(FrameUniforms, light_count) == 212);
+    assert!(offset_of!(FrameUniforms, ambient_light) == 224);
+    assert!(offset_of!(FrameUniforms, shadow_view_projection) == 240);
+    assert!(offset_of!(FrameUniforms, shadow_light) == 304);
+    assert!(offset_of!(FrameUniforms, shadow_normal_offset) == 308);
+    assert!(offset_of!(FrameUniforms, lights) == 320);
+    assert!(size_of::<FrameUniforms>() == 320 + 64 * MAX_LIGHTS);
+};
+
+impl FrameUniforms {
+    pub fn new(view: &glm::Mat4, projection: &glm::Mat4, camera_position: &glm::Vec3, time: f32) -> Self {
+        FrameUniforms {
+            view: (*view).into(),
+            projection: (*projection).into(),
+            view_projection: (projection * view).into(),
+            camera_position: [camera_position.x, camera_position.y, camera_position.z, 1.0],
+            time,
+            light_count: 0,
+            _padding: [0.0; 2],
+            ambient_light: [0.0; 4],
+            shadow_view_projection: glm::Mat4::identity().into(),
+            shadow_light: -1,
+            shadow_normal_offset: 0.0,
+            _shadow_padding: [0.0; 2],
+            lights: [LightUniform::default(); MAX_LIGHTS],
+        }
+    }
+
+    // Light reaching everything from everywhere, scaled by the material's ambient color
+    pub fn set_ambient_light(&mut self, color: &glm::Vec3) {
+        self.ambient_light = [color.x, color.y, color.z, 0.0];
+    }
+
+    // The light has to have been added already
+    pub fn set_shadow(&mut self, light: usize, view_projection: &glm::Mat4, normal_offset: f32) {
+        self.shadow_light = light as i32;
+        self.shadow_view_projection = (*view_projection).into();
+        self.shadow_normal_offset = normal_offset;
+    }
+
+    // Returns false if there is no room for more lights
+    pub fn add_light(&mut self, light: &LightUniform) -> bool {
+        let i = self.light_count as usize;
+        if i >= MAX_LIGHTS {
+            return false;
+        }
+        self.lights[i] = *light;
+        self.light_count += 1;
+        true
+    }
+
+    /// Checks the block layout the driver chose for the program against this struct.
+    pub unsafe fn check_layout(program_id: u32) -> Result<(), String> {
+        // A different MAX_LIGHTS in the shaders shows up as a different size
+        let block_name = CString::new("FrameData").unwrap();
+        let block_index = gl::GetUniformBlockIndex(program_id, block_name.as_ptr());
+        if block_index != gl::INVALID_INDEX {
+            let mut size = 0;
+            gl::GetActiveUniformBlockiv(program_id, block_index, gl::UNIFORM_BLOCK_DATA_SIZE, &mut size);
+            if size as usize != size_of::<FrameUniforms>() {
+                return Err(format!(
+                    "FrameData is {} bytes in the shader, but {} bytes on the Rust side",
+                    size, size_of::<FrameUniforms>(),
+                ));
+            }
+        }
+        check_block_layout(program_id, "FrameData", &[
+            ("view",               offset_of!(FrameUniforms, view)),
+            ("projection",         offset_of!(FrameUniforms, projection)),
+            ("viewProjection",     offset_of!(FrameUniforms, view_projection)),
+            ("cameraPosition",     offset_of!(FrameUniforms, camera_position)),
+            ("time",               offset_of!(FrameUniforms, time)),
+            ("lightCount",         offset_of!(FrameUniforms, light_count)),
+            ("ambientLight",       offset_of!(FrameUniforms, ambient_light)),
+            ("shadowViewProjection", offset_of!(FrameUniforms, shadow_view_projection)),
+            ("shadowLight",        offset_of!(FrameUniforms, shadow_light)),
+            ("shadowNormalOffset", offset_of!(FrameUniforms, shadow_normal_offset)),
+            ("lights[0].position", offset_of!(FrameUniforms, lights) + offset_of!(LightUniform, position)),
+            ("lights[0].direction", offset_of!(FrameUniforms, lights) + offset_of!(LightUniform, direction)),
+            ("lights[0].color",    offset_of!(FrameUniforms, lights) + offset_of!(LightUniform, color)),
+            ("lights[0].cone",     offset_of!(FrameUniforms, lights) + offset_of!(LightUniform, cone)),
+            ("lights[1].position", offset_of!(FrameUniforms, lights) + size_of::<LightUniform>()),
+        ])
+    }
+}
@@ -0,0 +1,376 @@
+extern crate nalgebra_glm as glm;
+
+use std::ffi::CStr;
+
+use crate::gl_objects::{Buffer, VertexArray};
+use crate::graphics::offset;
+
+// Description of how vertex data is laid out in buffers, and which shader inputs it feeds.
+// Each buffer holds one or more attributes; several attributes in the same buffer are
+// interleaved, one vertex after the other.
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum AttributeType {
+    Float,
+    Byte,
+    UnsignedByte,
+    Short,
+    UnsignedShort,
+    Int,
+    UnsignedInt,
+}
+
+impl AttributeType {
+    pub fn size(&self) -> i32 {
+        match self {
+            AttributeType::Float         => 4,
+            AttributeType::Byte          => 1,
+            AttributeType::UnsignedByte  => 1,
+            AttributeType::Short         => 2,
+            AttributeType::UnsignedShort => 2,
+            AttributeType::Int           => 4,
+            AttributeType::UnsignedInt   => 4,
+        }
+    }
+}
+
+impl From<AttributeType> for gl::types::GLenum {
+    fn from(ty: AttributeType) -> gl::types::GLenum {
+        match ty {
+            AttributeType::Float         => gl::FLOAT,
+            AttributeType::Byte          => gl::BYTE,
+            AttributeType::UnsignedByte  => gl::UNSIGNED_BYTE,
+            AttributeType::Short         => gl::SHORT,
+            AttributeType::UnsignedShort => gl::UNSIGNED_SHORT,
+            AttributeType::Int           => gl::INT,
+            AttributeType::UnsignedInt   => gl::UNSIGNED_INT,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct VertexAttribute {
+    pub name       : String,        // Name of the shader input, for error messages and validation
+    pub location   : u32,
+    pub components : i32,           // 1 to 4
+    pub ty         : AttributeType,
+    pub normalized : bool,          // Integer data is mapped to [0, 1] or [-1, 1] floats if set
+    pub integer    : bool,          // Integer data is passed as integers to ivec/uvec inputs if set
+}
+
+impl VertexAttribute {
+    pub fn float(name: &str, location: u32, components: i32) -> Self {
+        VertexAttribute {
+            name: name.to_string(),
+            location,
+            components,
+            ty: AttributeType::Float,
+            normalized: false,
+            integer: false,
+        }
+    }
+
+    // Integer data read by a float input, e.g. colors stored as bytes
+    pub fn normalized(name: &str, location: u32, components: i32, ty: AttributeType) -> Self {
+        VertexAttribute {
+            name: name.to_string(),
+            location,
+            components,
+            ty,
+            normalized: true,
+            integer: false,
+        }
+    }
+
+    // Integer data read by an integer input, e.g. bone indices
+    pub fn integer(name: &str, location: u32, components: i32, ty: AttributeType) -> Self {
+        VertexAttribute {
+            name: name.to_string(),
+            location,
+            components,
+            ty,
+            normalized: false,
+            integer: true,
+        }
+    }
+
+    pub fn size(&self) -> i32 {
+        self.components * self.ty.size()
+    }
+}
+
+// The attributes stored in a single buffer
+#[derive(Clone, Debug, Default)]
+pub struct BufferLayout {
+    pub attributes : Vec<VertexAttribute>,
+    pub divisor    : u32, // 0 to advance every vertex, n to advance every n instances
+}
+
+impl BufferLayout {
+    // Bytes from one vertex to the next
+    pub fn stride(&self) -> i32 {
+        self.attributes.iter().map(|a| a.size()).sum()
+    }
+
+    // Bytes from the start of a vertex to the given attribute
+    pub fn offset_of(&self, attribute: usize) -> i32 {
+        self.attributes[..attribute].iter().map(|a| a.size()).sum()
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct VertexLayout {
+    pub buffers : Vec<BufferLayout>,
+}
+
+impl VertexLayout {
+    pub fn new() -> Self {
+        VertexLayout { buffers: vec![] }
+    }
+
+    // Adds a buffer holding just this attribute
+    pub fn separate(mut self, attribute: VertexAttribute) -> Self {
+        self.buffers.push(BufferLayout { attributes: vec![attribute], divisor: 0 });
+        self
+    }
+
+    // Adds a buffer holding all of these attributes, interleaved in the given order
+    pub fn interleaved(mut self, attributes: Vec<VertexAttribute>) -> Self {
+        self.buffers.push(BufferLayout { attributes, divisor: 0 });
+        self
+    }
+
+    // Adds a buffer of interleaved attributes that advance once per instance instead of per vertex
+    pub fn per_instance(mut self, attributes: Vec<VertexAttribute>) -> Self {
+        self.buffers.push(BufferLayout { attributes, divisor: 1 });
+        self
+    }
+
+    // A mat4 input takes up four consecutive locations, one per column
+    pub fn mat4_attributes(name: &str, location: u32) -> Vec<VertexAttribute> {
+        (0..4).map(|column| VertexAttribute::float(&format!("{}[{}]", name, column), location + column, 4)).collect()
+    }
+
+    // And a mat3 input three
+    pub fn mat3_attributes(name: &str, location: u32) -> Vec<VertexAttribute> {
+        (0..3).map(|column| VertexAttribute::float(&format!("{}[{}]", name, column), location + column, 3)).collect()
+    }
+
+    pub fn attributes(&self) -> impl Iterator<Item = &VertexAttribute> {
+        self.buffers.iter().flat_map(|b| b.attributes.iter())
+    }
+
+    // Checks that the layout makes sense on its own
+    pub fn validate(&self) -> Result<(), String> {
+        let mut locations: Vec<u32> = vec![];
+        for attribute in self.attributes() {
+            if !(1..=4).contains(&attribute.components) {
+                return Err(format!("{} has {} components, expected 1 to 4", attribute.name, attribute.components));
+            }
+            if attribute.integer && attribute.ty == AttributeType::Float {
+                return Err(format!("{} is an integer attribute, but has float data", attribute.name));
+            }
+            if locations.contains(&attribute.location) {
+                return Err(format!("{} uses location {}, which is already taken", attribute.name, attribute.location));
+            }
+            locations.push(attribute.location);
+        }
+        Ok(())
+    }
+
+    // Number of vertices in the given buffer contents, making sure they all agree.
+    // Per instance buffers are not included, and are set up separately.
+    pub fn vertex_count(&self, data: &[&[u8]]) -> Result<usize, String> {
+        let per_vertex = self.buffers.iter().filter(|b| b.divisor == 0).count();
+        if data.len() != per_vertex {
+            return Err(format!("The layout describes {} vertex buffers, but {} were given", per_vertex, data.len()));
+        }
+        let mut count: Option<usize> = None;
+        for (buffer, bytes) in self.buffers.iter().filter(|b| b.divisor == 0).zip(data) {
+            let stride = buffer.stride() as usize;
+            let names: Vec<&str> = buffer.attributes.iter().map(|a| a.name.as_str()).collect();
+            if stride == 0 || bytes.len() % stride != 0 {
+                return Err(format!(
+                    "The buffer for {:?} holds {} bytes, which is not a whole number of {} byte vertices",
+                    names, bytes.len(), stride,
+                ));
+            }
+            let n = bytes.len() / stride;
+            match count {
+                Some(c) if c != n => return Err(format!(
+                    "The buffer for {:?} holds {} vertices, but the previous buffers hold {}",
+                    names, n, c,
+                )),
+                _ => count = Some(n),
+            }
+        }
+        Ok(count.unwrap_or(0))
+    }
+
+    // Points the attributes of the given buffer at it. The VAO and buffer have to be bound.
+    pub unsafe fn apply(&self, buffer: usize) {
+        self.apply_at(buffer, 0);
+    }
+
+    // Same as above, for vertices starting `base` bytes into the buffer
+    pub unsafe fn apply_at(&self, buffer: usize, base: isize) {
+        let layout = &self.buffers[buffer];
+        let stride = layout.stride();
+        for (i, attribute) in layout.attributes.iter().enumerate() {
+            let offset = offset::<u8>(base as u32 + layout.offset_of(i) as u32);
+            if attribute.integer {
+                gl::VertexAttribIPointer(attribute.location, attribute.components, attribute.ty.into(), stride, offset);
+            } else {
+                let normalized = if attribute.normalized { gl::TRUE } else { gl::FALSE };
+                gl::VertexAttribPointer(attribute.location, attribute.components, attribute.ty.into(), normalized, stride, offset);
+            }
+            gl::EnableVertexAttribArray(attribute.location);
+            gl::VertexAttribDivisor(attribute.location, layout.divisor);
+        }
+    }
+
+    /// Checks that every input of the linked program is fed by the layout with matching data.
+    pub unsafe fn validate_against_program(&self, program_id: u32) -> Result<(), String> {
+        let mut count = 0;
+        gl::GetProgramiv(program_id, gl::ACTIVE_ATTRIBUTES, &mut count);
+        let mut name_buffer = [0u8; 256];
+        for i in 0..count as u32 {
+            let (mut length, mut size, mut ty) = (0, 0, 0);
+            gl::GetActiveAttrib(
+                program_id, i, name_buffer.len() as i32,
+                &mut length, &mut size, &mut ty,
+                name_buffer.as_mut_ptr() as *mut gl::types::GLchar,
+            );
+            let name = CStr::from_bytes_until_nul(&name_buffer)
+                .map(|n| n.to_string_lossy().to_string())
+                .unwrap_or_default();
+            if name.starts_with("gl_") {
+                continue; // Built-in inputs like gl_VertexID
+            }
+            let location = gl::GetAttribLocation(program_id, name_buffer.as_ptr() as *const gl::types::GLchar);
+            if location < 0 {
+                continue;
+            }
+
+            let (components, integer) = match glsl_type_shape(ty) {
+                Some(shape) => shape,
+                None => continue, // Matrices and such, which span several locations
+            };
+            let attribute = match self.attributes().find(|a| a.location == location as u32) {
+                Some(a) => a,
+                None => return Err(format!("The shader input {} at location {} is not in the vertex layout", name, location)),
+            };
+            if attribute.components > components {
+                return Err(format!(
+                    "{} has {} components in the layout, but the shader input {} only has {}",
+                    attribute.name, attribute.components, name, components,
+                ));
+            }
+            if attribute.integer != integer {
+                return Err(format!(
+                    "{} is {} in the layout, but the shader input {} is {}",
+                    attribute.name,
+                    if attribute.integer { "integer" } else { "float" },
+                    name,
+                    if integer { "integer" } else { "float" },
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+// (components, is integer) of a GLSL input type
+fn glsl_type_shape(ty: gl::types::GLenum) -> Option<(i32, bool)> {
+    match ty {
+        gl::FLOAT                                   => Some((1, false)),
+        gl::FLOAT_VEC2                              => Some((2, false)),
+        gl::FLOAT_VEC3                              => Some((3, false)),
+        gl::FLOAT_VEC4                              => Some((4, false)),
+        gl::INT | gl::UNSIGNED_INT                  => Some((1, true)),
+        gl::INT_VEC2 | gl::UNSIGNED_INT_VEC2        => Some((2, true)),
+        gl::INT_VEC3 | gl::UNSIGNED_INT_VEC3        => Some((3, true)),
+        gl::INT_VEC4 | gl::UNSIGNED_INT_VEC4        => Some((4, true)),
+        _ => None,
+    }
+}
+
+// Plain numbers, and arrays, vectors and matrices of them. None of them have padding bytes,
+// which are uninitialized and must not be read, so their memory can be viewed as bytes.
+// Sealed, as implementing it for a type with padding would make as_bytes unsound. #[repr(C)]
+// structs in this crate made only of Pod fields may implement it, after checking their size.
+pub trait Pod: Copy + sealed::Sealed {}
+
+pub(crate) mod sealed {
+    pub trait Sealed {}
+}
+
+macro_rules! impl_pod {
+    ($($t:ty),*) => {
+        $(
+            impl sealed::Sealed for $t {}
+            impl Pod for $t {}
+        )*
+    };
+}
+
+impl_pod!(f32, u32, i32, u16, u8);
+
+impl<T: Pod, const N: usize> sealed::Sealed for [T; N] {}
+impl<T: Pod, const N: usize> Pod for [T; N] {}
+
+// Column major arrays of floats, the storage is #[repr(transparent)] in a #[repr(C)] matrix
+impl<const R: usize, const C: usize> sealed::Sealed for glm::TMat<f32, R, C> {}
+impl<const R: usize, const C: usize> Pod for glm::TMat<f32, R, C> {}
+
+// View any slice of plain numbers as raw bytes, to upload them
+pub fn as_bytes<T: Pod>(data: &[T]) -> &[u8] {
+    unsafe { std::slice::from_raw_parts(data.as_ptr() as *const u8, std::mem::size_of_val(data)) }
+}
+
+/// Creates a VAO with one buffer per buffer in the layout, filled with the given data.
+/// Use gl::DYNAMIC_DRAW as the usage for buffers that will be updated later on.
+pub unsafe fn create_vao_with_layout(
+    layout: &VertexLayout,
+    data: &[&[u8]],
+    indices: &[u32],
+    usage: gl::types::GLenum,
+) -> Result<VertexArray, String> {
+    layout.validate()?;
+    let vertex_count = layout.vertex_count(data)?;
+    if let Some(&index) = indices.iter().find(|&&i| i as usize >= vertex_count) {
+        return Err(format!("Index {} is out of range for {} vertices", index, vertex_count));
+    }
+
+    let mut vao = VertexArray::new();
+    vao.bind();
+    let per_vertex = (0..layout.buffers.len()).filter(|&b| layout.buffers[b].divisor == 0);
+    for (i, bytes) in per_vertex.zip(data.iter()) {
+        vao.add_buffer(Buffer::with_data(gl::ARRAY_BUFFER, bytes, usage));
+        layout.apply(i);
+    }
+
+    let ibo = Buffer::with_data(gl::ELEMENT_ARRAY_BUFFER, indices, usage);
+    vao.set_index_buffer(ibo, indices.len() as i32);
+
+    // Unbind the VAO and VBO to avoid accidental modification elsewhere
+    gl::BindBuffer(gl::ARRAY_BUFFER, 0);
+    gl::BindVertexArray(0);
+
+    Ok(vao)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn as_bytes_covers_every_element() {
+        assert_eq!(as_bytes(&[1.0f32, 2.0]), [1.0f32.to_ne_bytes(), 2.0f32.to_ne_bytes()].concat());
+        assert_eq!(as_bytes(&[[1u16, 2, 3]]).len(), 6);
+        let matrices = [glm::Mat4::identity(); 2];
+        assert_eq!(as_bytes(&matrices).len(), 2 * 16 * 4);
+        assert_eq!(&as_bytes(&matrices)[60..64], &1.0f32.to_ne_bytes());
+        assert_eq!(as_bytes(&[glm::vec3(1.0, 2.0, 3.0)]).len(), 12);
+    }
+}